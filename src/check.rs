@@ -0,0 +1,176 @@
+/*
+    Copyright (c) 2026 アクゼスティア. All Rights Reserved.
+*/
+
+/*
+    Reports of `cqlls check`, the diagnostics of every file
+    as text, JSON or SARIF 2.1.0 for code scanning dashboards.
+*/
+
+use clap::ValueEnum;
+use serde_json::{Value, json};
+use std::path::PathBuf;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString};
+
+use crate::lint::{LINT_DOCS_URL, LINT_RULES};
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ReportFormat {
+    Text,
+    Json,
+    Sarif,
+}
+
+pub struct FileReport {
+    pub path: PathBuf,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+fn rule(diagnostic: &Diagnostic) -> &str {
+    match &diagnostic.code {
+        Some(NumberOrString::String(code)) => code,
+        _ => "",
+    }
+}
+
+fn severity_name(diagnostic: &Diagnostic) -> &'static str {
+    match diagnostic.severity {
+        Some(DiagnosticSeverity::ERROR) => "error",
+        Some(DiagnosticSeverity::WARNING) => "warning",
+        Some(DiagnosticSeverity::INFORMATION) => "info",
+        _ => "hint",
+    }
+}
+
+/* `error` → 2, `warning` → 1, anything else → 0 */
+pub fn exit_code(reports: &[FileReport]) -> i32 {
+    reports
+        .iter()
+        .flat_map(|r| &r.diagnostics)
+        .map(|d| match d.severity {
+            Some(DiagnosticSeverity::ERROR) => 2,
+            Some(DiagnosticSeverity::WARNING) => 1,
+            _ => 0,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+pub fn render(reports: &[FileReport], format: ReportFormat) -> String {
+    match format {
+        ReportFormat::Text => render_text(reports),
+        ReportFormat::Json => {
+            serde_json::to_string_pretty(&render_json(reports)).unwrap_or_default() + "\n"
+        }
+        ReportFormat::Sarif => {
+            serde_json::to_string_pretty(&render_sarif(reports)).unwrap_or_default() + "\n"
+        }
+    }
+}
+
+/* schema/users.cql:3:1: warning[allow-filtering]: ... */
+fn render_text(reports: &[FileReport]) -> String {
+    let mut out = String::new();
+
+    for report in reports {
+        for diagnostic in &report.diagnostics {
+            let start = diagnostic.range.start;
+            out.push_str(&format!(
+                "{}:{}:{}: {}[{}]: {}\n",
+                report.path.display(),
+                start.line + 1,
+                start.character + 1,
+                severity_name(diagnostic),
+                rule(diagnostic),
+                diagnostic.message
+            ));
+        }
+    }
+
+    out
+}
+
+fn render_json(reports: &[FileReport]) -> Value {
+    let diagnostics: Vec<Value> = reports
+        .iter()
+        .flat_map(|report| {
+            report.diagnostics.iter().map(|diagnostic| {
+                let range = diagnostic.range;
+                json!({
+                    "file": report.path.display().to_string(),
+                    "line": range.start.line + 1,
+                    "column": range.start.character + 1,
+                    "end_line": range.end.line + 1,
+                    "end_column": range.end.character + 1,
+                    "severity": severity_name(diagnostic),
+                    "rule": rule(diagnostic),
+                    "message": diagnostic.message,
+                })
+            })
+        })
+        .collect();
+
+    Value::Array(diagnostics)
+}
+
+fn render_sarif(reports: &[FileReport]) -> Value {
+    let rules: Vec<Value> = LINT_RULES
+        .iter()
+        .map(|rule| {
+            json!({
+                "id": rule.id,
+                "shortDescription": { "text": rule.description },
+                "helpUri": format!("{LINT_DOCS_URL}#{}", rule.id),
+            })
+        })
+        .collect();
+
+    let results: Vec<Value> = reports
+        .iter()
+        .flat_map(|report| {
+            let uri = report.path.display().to_string().replace('\\', "/");
+
+            report.diagnostics.iter().map(move |diagnostic| {
+                let range = diagnostic.range;
+                let level = match diagnostic.severity {
+                    Some(DiagnosticSeverity::ERROR) => "error",
+                    Some(DiagnosticSeverity::WARNING) => "warning",
+                    _ => "note",
+                };
+
+                json!({
+                    "ruleId": rule(diagnostic),
+                    "level": level,
+                    "message": { "text": diagnostic.message },
+                    "locations": [{
+                        "physicalLocation": {
+                            "artifactLocation": { "uri": uri },
+                            "region": {
+                                "startLine": range.start.line + 1,
+                                "startColumn": range.start.character + 1,
+                                "endLine": range.end.line + 1,
+                                "endColumn": range.end.character + 1,
+                            }
+                        }
+                    }],
+                })
+            })
+        })
+        .collect();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "cqlls",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": LINT_DOCS_URL,
+                    "rules": rules,
+                }
+            },
+            "results": results,
+        }]
+    })
+}
@@ -1,35 +1,823 @@
+/*
+    Copyright (c) 2026 アクゼスティア. All Rights Reserved.
+*/
+
+use crate::check::{self, FileReport, ReportFormat};
 use crate::config::*;
+use crate::config_toml::{is_toml, migrate};
+use crate::connection;
+use crate::cqlsh;
+use crate::lsp::Backend;
 use crate::version::version;
+use crate::workspace::{
+    CONFIG_FILE, Trust, default_config, discover_config, distrust, is_user_config, read_config,
+    read_project_config, user_config_path,
+};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use regex::Regex;
+use similar::TextDiff;
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tower_lsp::LspService;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "cqlls",
+    about = "CQL (Cassandra Query Language) language server"
+)]
+#[command(disable_version_flag = true)]
+pub struct Cli {
+    /// Config file to use instead of the nearest `.cqlls.toml` or `.cqlls`
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Let a `.cqlls` found in the project run `pswd_command` and `udf_formatters` and read secrets like `--trust-secrets`, only your own configs do otherwise
+    #[arg(long, global = true)]
+    pub trust_workspace: bool,
+
+    /// Let a `.cqlls` found in the project read `pswd_file`, a `from_cqlshrc` outside of it and `${VAR}`, but not run anything
+    #[arg(long, global = true)]
+    pub trust_secrets: bool,
+
+    /// Log level, overrides `logging` of the config
+    #[arg(long, global = true, value_enum)]
+    pub log_level: Option<LogLevel>,
+
+    /// Print the version
+    #[arg(short = 'v', long = "version")]
+    pub version: bool,
+
+    /* `--write-default-config`/`-wdc` of older versions, `config init` now */
+    #[arg(long, hide = true)]
+    pub write_default_config: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the language server (default)
+    Lsp(LspArgs),
+    /// Format files in place, or stdin to stdout
+    Fmt(FmtArgs),
+    /// Report the diagnostics of files
+    Check(CheckArgs),
+    /// Print the schema of the configured database
+    Schema(SchemaArgs),
+    /// Manage the config file
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Check the connection to the database step by step
+    Doctor,
+    /// Print the version
+    Version,
+    #[cfg(debug_assertions)]
+    #[command(hide = true)]
+    Debug {
+        #[command(subcommand)]
+        command: DebugCommand,
+    },
+}
+
+#[derive(Debug, Default, Args)]
+#[group(multiple = false)]
+pub struct LspArgs {
+    /// Talk to the editor over stdin/stdout (default)
+    #[arg(long)]
+    pub stdio: bool,
 
-pub async fn exec(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
-    if args.len() == 2 && (args[1] == "version" || args[1] == "-v") {
-        println!("{}", version());
-        return Ok(());
+    /// Listen on <ADDR> (`127.0.0.1:9257`, or just the port), one client after the other, loopback only
+    #[arg(long, value_name = "ADDR", value_parser = parse_tcp_address)]
+    pub tcp: Option<SocketAddr>,
+
+    /// Listen on a Unix socket at <PATH>, one client after the other
+    #[cfg(unix)]
+    #[arg(long, value_name = "PATH")]
+    pub socket: Option<PathBuf>,
+}
+
+/*
+    `9257` is short for `127.0.0.1:9257`. Whoever connects drives the server,
+    settings and all, and there's no login, so it only listens on this machine.
+*/
+fn parse_tcp_address(address: &str) -> Result<SocketAddr, String> {
+    if let Ok(port) = address.parse::<u16>() {
+        return Ok(SocketAddr::from(([127, 0, 0, 1], port)));
     }
 
-    if args.len() == 2 && (args[1] == "--write-default-config" || args[1] == "-wdc") {
-        return CqllsConfig::write_default_config_file();
+    let parsed: SocketAddr = address
+        .parse()
+        .map_err(|_| format!("expected `host:port` or a port, got `{address}`"))?;
+    if !parsed.ip().is_loopback() {
+        return Err(format!(
+            "`{address}` isn't a loopback address, anyone who can reach it could drive cqlls"
+        ));
     }
 
-    #[cfg(debug_assertions)]
-    if args.len() >= 3 && (args[1] == "--debug" || args[1] == "-d") {
-        match args[2].as_ref() {
-            "fmt" => {
-                use crate::test_base::debug_format;
+    Ok(parsed)
+}
+
+#[derive(Debug, Args)]
+pub struct FmtArgs {
+    /// Files, directories (searched for `*.cql`) or globs, the current directory by default
+    pub paths: Vec<PathBuf>,
+
+    /// Don't write anything, print a diff and exit with 1 if anything isn't formatted
+    #[arg(long)]
+    pub check: bool,
+
+    /// Format stdin to stdout
+    #[arg(long, conflicts_with = "paths")]
+    pub stdin: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct CheckArgs {
+    /// Files, directories (searched for `*.cql`) or globs, the current directory by default
+    pub paths: Vec<PathBuf>,
+
+    #[arg(short, long, value_enum, default_value_t = ReportFormat::Text)]
+    pub format: ReportFormat,
+
+    /// Look tables and columns up in the configured database (`unknown-column`), offline otherwise
+    #[arg(long)]
+    pub schema: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct SchemaArgs {
+    /// Only print the tables of this keyspace
+    #[arg(short, long)]
+    pub keyspace: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Write a config with the defaults
+    Init {
+        /// Overwrite an existing config
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check the config for errors
+    Validate,
+    /// Print the config in effect, defaults included
+    Show,
+    /// Convert a `.cqlls` into a `.cqlls.toml` next to it, comments are dropped
+    Migrate {
+        /// Overwrite an existing `.cqlls.toml`
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[cfg(debug_assertions)]
+#[derive(Debug, Subcommand)]
+pub enum DebugCommand {
+    Fmt {
+        file: String,
+    },
+    Cmt {
+        file: String,
+        line: u32,
+        character: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for log::LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => log::LevelFilter::Off,
+            LogLevel::Error => log::LevelFilter::Error,
+            LogLevel::Warn => log::LevelFilter::Warn,
+            LogLevel::Info => log::LevelFilter::Info,
+            LogLevel::Debug => log::LevelFilter::Debug,
+            LogLevel::Trace => log::LevelFilter::Trace,
+        }
+    }
+}
 
-                debug_format(&args[3]).await;
+impl Cli {
+    /*
+        `--config`, otherwise the nearest `.cqlls.toml` or `.cqlls` in the current
+        directory or one of its parents, otherwise the user level config
+    */
+    pub fn config_path(&self) -> Option<PathBuf> {
+        let current_dir = std::env::current_dir().ok()?;
+        self.config_path_for(&current_dir)
+    }
+
+    /* the same starting at `dir`, the config the server uses for a file in it */
+    pub fn config_path_for(&self, dir: &Path) -> Option<PathBuf> {
+        if let Some(path) = &self.config {
+            return Some(path.clone());
+        }
+
+        discover_config(dir, user_config_path().as_deref())
+    }
+
+    /* how far a config found in the project is trusted, `--trust-workspace` goes over `--trust-secrets` */
+    pub fn trust(&self) -> Trust {
+        match (self.trust_workspace, self.trust_secrets) {
+            (true, _) => Trust::Everything,
+            (false, true) => Trust::Secrets,
+            (false, false) => Trust::Nothing,
+        }
+    }
+
+    pub fn try_load_config(&self) -> Result<CqllsConfig, String> {
+        self.try_read_config(self.config_path().as_deref())
+    }
+
+    /* the defaults when there's no config at all, a config found in the project runs no commands and reads no secrets unless trusted to */
+    pub fn try_read_config(&self, path: Option<&Path>) -> Result<CqllsConfig, String> {
+        let (config, ignored) = self.try_read_distrusted(path)?;
+        if let Some(ignored) = ignored {
+            eprintln!("warning: {ignored}");
+        }
+
+        Ok(config)
+    }
+
+    /* the same, what it ignored of the config is for the caller to tell */
+    pub fn try_read_distrusted(
+        &self,
+        path: Option<&Path>,
+    ) -> Result<(CqllsConfig, Option<String>), String> {
+        let Some(path) = path else {
+            return Ok((default_config(), None));
+        };
+
+        let trust = match self.config.is_some() || is_user_config(path) {
+            true => Trust::Everything,
+            false => self.trust(),
+        };
+        let mut config = match trust {
+            Trust::Nothing => read_project_config(path)?,
+            _ => read_config(path)?,
+        };
+        let ignored = distrust(&mut config, path, trust);
+
+        Ok((config, ignored))
+    }
+
+    pub fn load_config(&self) -> CqllsConfig {
+        self.read_config(self.config_path().as_deref())
+    }
+
+    /* a broken config isn't fatal, but silently connecting with the defaults would be confusing */
+    pub fn read_config(&self, path: Option<&Path>) -> CqllsConfig {
+        self.try_read_config(path).unwrap_or_else(|e| {
+            eprintln!("warning: {e}, using the default config");
+            CqllsConfig::default()
+        })
+    }
+}
+
+/* everything but the language server itself, `lsp` is started by `main` */
+pub async fn exec(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    match &cli.command {
+        Some(Command::Version) => println!("{}", version()),
+        Some(Command::Fmt(args)) => fmt(cli, args).await?,
+        Some(Command::Check(args)) => check(cli, args).await?,
+        Some(Command::Schema(args)) => schema(cli, args).await?,
+        Some(Command::Config(command)) => config(cli, command)?,
+        Some(Command::Doctor) => doctor(cli).await?,
+        #[cfg(debug_assertions)]
+        Some(Command::Debug { command }) => match command {
+            DebugCommand::Fmt { file } => crate::test_base::debug_format(file).await,
+            DebugCommand::Cmt {
+                file,
+                line,
+                character,
+            } => crate::test_base::debug_completion(file, *line, *character).await,
+        },
+        None if cli.write_default_config => {
+            eprintln!("warning: `--write-default-config` is deprecated, use `cqlls config init`");
+            config(cli, &ConfigCommand::Init { force: false })?;
+        }
+        Some(Command::Lsp(_)) | None => {}
+    }
+
+    Ok(())
+}
+
+/*
+    cqlls fmt [--check] [--stdin] [paths...]
+
+    Formats the given files (directories are searched for `*.cql`) in place,
+    or stdin to stdout. With `--check` nothing is written, a unified diff is
+    printed for every file that isn't formatted and the exit code is 1.
+*/
+async fn fmt(cli: &Cli, args: &FmtArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut paths = args.paths.clone();
+
+    let mut unformatted = 0;
+
+    if args.stdin {
+        let service = standalone(cli.load_config());
+        let mut source = String::new();
+        std::io::stdin().read_to_string(&mut source)?;
+        let formatted = service.inner().format_text(&source).await;
+
+        if !args.check {
+            std::io::stdout().write_all(formatted.as_bytes())?;
+        } else if formatted != source {
+            print!("{}", unified_diff("<stdin>", &source, &formatted));
+            unformatted += 1;
+        }
+    } else {
+        if paths.is_empty() {
+            paths.push(PathBuf::from("."));
+        }
+
+        /* every file is formatted with its own config, the way the server would */
+        let mut services: BTreeMap<Option<PathBuf>, LspService<Backend>> = BTreeMap::new();
+        for path in cql_files(&paths)? {
+            let dir = std::path::absolute(&path)?
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default();
+            let config_path = cli.config_path_for(&dir);
+            let service = services
+                .entry(config_path.clone())
+                .or_insert_with(|| standalone(cli.read_config(config_path.as_deref())));
+
+            let source = std::fs::read_to_string(&path)?;
+            let formatted = service.inner().format_text(&source).await;
+            if formatted == source {
+                continue;
             }
-            "cmt" => {
-                use crate::test_base::debug_completion;
 
-                let line: u32 = args[4].parse().expect("line must be a number");
-                let character: u32 = args[5].parse().expect("character must be a number");
-                debug_completion(&args[3], line, character).await;
+            if args.check {
+                print!(
+                    "{}",
+                    unified_diff(&path.display().to_string(), &source, &formatted)
+                );
+                unformatted += 1;
+            } else {
+                std::fs::write(&path, formatted)?;
             }
-            _ => {}
         }
-        return Ok(());
+    }
+
+    if unformatted > 0 {
+        eprintln!("{unformatted} file(s) would be reformatted");
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/*
+    cqlls check [--format text|json|sarif] [--schema] [paths...]
+
+    Runs the diagnostics of the editor over the given files, each with its
+    own config like `fmt`. The exit code is 2 when any error was found, 1
+    for warnings and 0 otherwise, 3 when the files couldn't be checked (a
+    file or config that can't be read). The database is only asked with
+    `--schema`, so CI doesn't try to reach it.
+*/
+async fn check(cli: &Cli, args: &CheckArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let reports = match check_reports(cli, args).await {
+        Ok(reports) => reports,
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(3);
+        }
+    };
+
+    print!("{}", check::render(&reports, args.format));
+
+    let code = check::exit_code(&reports);
+    if code != 0 {
+        std::process::exit(code);
     }
 
     Ok(())
 }
+
+async fn check_reports(
+    cli: &Cli,
+    args: &CheckArgs,
+) -> Result<Vec<FileReport>, Box<dyn std::error::Error>> {
+    let mut paths = args.paths.clone();
+    if paths.is_empty() {
+        paths.push(PathBuf::from("."));
+    }
+
+    let mut services: BTreeMap<Option<PathBuf>, LspService<Backend>> = BTreeMap::new();
+    let mut reports = vec![];
+    for path in cql_files(&paths)? {
+        let dir = std::path::absolute(&path)?
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let config_path = cli.config_path_for(&dir);
+        let service = match services.entry(config_path) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let config = cli.try_read_config(entry.key().as_deref())?;
+                entry.insert(standalone(check_config(config, args.schema)))
+            }
+        };
+
+        let source = std::fs::read_to_string(&path)
+            .map_err(|e| format!("{}: can't read it: {e}", path.display()))?;
+        let mut diagnostics = service.inner().compute_diagnostics(&source).await;
+        diagnostics.sort_by_key(|d| (d.range.start.line, d.range.start.character));
+        reports.push(FileReport {
+            path: path.strip_prefix("./").unwrap_or(&path).to_path_buf(),
+            diagnostics,
+        });
+    }
+
+    Ok(reports)
+}
+
+/* the checks run whether the editor has them enabled or not */
+fn check_config(mut config: CqllsConfig, schema: bool) -> CqllsConfig {
+    if !config.has_feature("diagnostics") {
+        config.features.push("diagnostics".to_string());
+    }
+    match schema {
+        true if !config.has_feature("context_aware_completions") => config
+            .features
+            .push("context_aware_completions".to_string()),
+        true => {}
+        false => config.features.retain(|f| f != "context_aware_completions"),
+    }
+
+    config
+}
+
+/*
+    keyspace.table
+        column        type
+*/
+async fn schema(cli: &Cli, args: &SchemaArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = cli.load_config();
+    if !config.has_feature("context_aware_completions") {
+        config
+            .features
+            .push("context_aware_completions".to_string());
+    }
+
+    let columns = match &args.keyspace {
+        Some(keyspace) => cqlsh::query_keyspace_scoped_fields(&config, keyspace).await?,
+        None => cqlsh::query_g_fields(&config).await?,
+    };
+
+    let mut tables: BTreeMap<(String, String), Vec<(String, String)>> = BTreeMap::new();
+    for column in columns {
+        tables
+            .entry((column.keyspace_name, column.table_name))
+            .or_default()
+            .push((column.column_name, column.column_type));
+    }
+
+    let padding = config.type_padding as usize;
+    for ((keyspace, table), columns) in tables {
+        let width = columns
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0);
+
+        println!("{keyspace}.{table}");
+        for (name, kind) in columns {
+            println!("    {name:width$}{:padding$}{kind}", "");
+        }
+    }
+
+    Ok(())
+}
+
+/*
+    cqlls doctor
+
+    Connects to the database one step at a time, DNS, TCP, TLS, logging in
+    and reading the schema, and stops at the first step no node got through.
+*/
+async fn doctor(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let config = cli.try_load_config()?;
+    let source = cli
+        .config_path()
+        .map_or("the defaults".to_string(), |path| {
+            path.display().to_string()
+        });
+    match &config.profile {
+        Some(profile) => println!("config  {source}, profile `{profile}`"),
+        None => println!("config  {source}"),
+    }
+
+    let report = |outcome: &str, step: &str, detail: &dyn std::fmt::Display| {
+        println!("{outcome:<4}    {step:<6}  {detail}");
+    };
+
+    let mut resolved = vec![];
+    for node in &config.known_nodes {
+        match connection::resolve(node).await {
+            Ok(addresses) if !addresses.is_empty() => {
+                let list: Vec<String> = addresses.iter().map(ToString::to_string).collect();
+                report("ok", "dns", &format!("{node} → {}", list.join(", ")));
+                resolved.extend(addresses);
+            }
+            Ok(_) => report("fail", "dns", &format!("`{node}` has no addresses")),
+            Err(e) => report("fail", "dns", &e),
+        }
+    }
+    if resolved.is_empty() {
+        return Err("none of `known_nodes` could be resolved".into());
+    }
+
+    let mut reachable = vec![];
+    for address in resolved {
+        match connection::tcp_connect(address).await {
+            Ok(()) => {
+                report("ok", "tcp", &address);
+                reachable.push(address);
+            }
+            Err(e) => report("fail", "tcp", &e),
+        }
+    }
+    let Some(&address) = reachable.first() else {
+        return Err("no node is reachable".into());
+    };
+
+    match cqlsh::tls_config(&config) {
+        Ok(None) => report("skip", "tls", &"`tls` is none"),
+        Ok(Some(tls)) => match connection::tls_handshake(address, tls).await {
+            Ok(()) => report("ok", "tls", &format!("{address}, the certificate is valid")),
+            Err(e) => {
+                report("fail", "tls", &e);
+                return Err("the TLS handshake failed".into());
+            }
+        },
+        Err(e) => {
+            report("fail", "tls", &e);
+            return Err("the TLS settings can't be used".into());
+        }
+    }
+
+    let session = match cqlsh::connect(&config).await {
+        Ok(session) if config.user.is_empty() => {
+            report("ok", "auth", &"connected without a user");
+            session
+        }
+        Ok(session) => {
+            report("ok", "auth", &format!("logged in as `{}`", config.user));
+            session
+        }
+        Err(e) => {
+            report("fail", "auth", &format!("{}: {e}", e.state));
+            return Err("can't connect".into());
+        }
+    };
+
+    match connection::schema_access(&session).await {
+        Ok(keyspaces) => report("ok", "schema", &format!("{keyspaces} keyspaces readable")),
+        Err(e) => {
+            report("fail", "schema", &e);
+            return Err("the schema can't be read, completions will be empty".into());
+        }
+    }
+
+    if let Some((cluster, version)) = connection::cluster_info(&session).await {
+        println!("cluster {cluster}, version {version}");
+    }
+
+    Ok(())
+}
+
+/* whether there's a password, never how long it is */
+fn mask(pswd: &str) -> String {
+    match pswd.is_empty() {
+        true => String::new(),
+        false => "********".to_string(),
+    }
+}
+
+fn config(cli: &Cli, command: &ConfigCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let path = cli
+        .config_path()
+        .unwrap_or_else(|| PathBuf::from(CONFIG_FILE));
+
+    match command {
+        ConfigCommand::Init { force } => {
+            /* a new config goes into the current directory, not where one was found */
+            let path = cli
+                .config
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(CONFIG_FILE));
+            if path.exists() && !force {
+                return Err(format!(
+                    "`{}` already exists, use `--force` to overwrite it",
+                    path.display()
+                )
+                .into());
+            }
+
+            CqllsConfig::write_default_config_file(&path)?;
+            println!("Wrote {}", path.display());
+        }
+        ConfigCommand::Validate => {
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|e| format!("can't read `{}`: {e}", path.display()))?;
+
+            match parse_config_file(&contents, &path) {
+                Ok(_) => println!("{} is valid", path.display()),
+                Err(e) => {
+                    eprintln!("{}:{}: {}", path.display(), e.line, e.msg);
+                    std::process::exit(1);
+                }
+            }
+        }
+        ConfigCommand::Show => {
+            let mut config = cli.load_config();
+            config.pswd = mask(&config.pswd);
+            for profile in config.profiles.values_mut() {
+                profile.pswd = mask(&profile.pswd);
+            }
+            config.write_config(&mut std::io::stdout())?;
+        }
+        ConfigCommand::Migrate { force } => {
+            if is_toml(&path) {
+                return Err(format!("`{}` is already TOML", path.display()).into());
+            }
+
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|e| format!("can't read `{}`: {e}", path.display()))?;
+            /* a config that doesn't load now wouldn't after the migration either */
+            parse_config_file(&contents, &path)
+                .map_err(|e| format!("{}:{}: {}", path.display(), e.line, e.msg))?;
+
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let target = path.with_file_name(format!("{name}.toml"));
+            if target.exists() && !force {
+                return Err(format!(
+                    "`{}` already exists, use `--force` to overwrite it",
+                    target.display()
+                )
+                .into());
+            }
+
+            std::fs::write(&target, migrate(&contents)?)?;
+            /* the TOML file is found first from now on */
+            println!(
+                "Wrote {}, `{}` isn't read anymore and can be removed",
+                target.display(),
+                path.display()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/* a backend without an editor on the other side, for the CLI commands */
+fn standalone(config: CqllsConfig) -> LspService<Backend> {
+    let (service, _) = LspService::new(|client| Backend::new(client, config));
+
+    service
+}
+
+pub fn unified_diff(name: &str, old: &str, new: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(name, name)
+        .to_string()
+}
+
+/* `*` and `?` don't match a `/`, `**` followed by a `/` matches any number of directories */
+fn glob_regex(pattern: &str) -> Regex {
+    let mut regex = String::from("^");
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+
+    Regex::new(&regex).unwrap_or_else(|_| Regex::new("$^").unwrap())
+}
+
+/*
+    Files are taken as they are, directories are searched for `*.cql`
+    and globs the shell didn't expand are matched against the files under
+    the directory they start with. Hidden directories are skipped unless
+    the glob names one, and symlinked directories aren't followed, they
+    could lead back up the tree.
+*/
+pub fn cql_files(paths: &[PathBuf]) -> std::io::Result<Vec<PathBuf>> {
+    fn is_hidden(path: &Path) -> bool {
+        path.file_name()
+            .is_some_and(|n| n.to_string_lossy().starts_with('.'))
+    }
+
+    fn walk(dir: &Path, out: &mut Vec<PathBuf>) -> std::io::Result<()> {
+        let mut entries = vec![];
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            entries.push((entry.path(), entry.file_type()?));
+        }
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for (path, file_type) in entries {
+            if file_type.is_dir() {
+                if !is_hidden(&path) {
+                    walk(&path, out)?;
+                }
+            } else if path.extension().is_some_and(|e| e == "cql") && path.is_file() {
+                out.push(path);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn walk_all(dir: &Path, hidden: bool, out: &mut Vec<PathBuf>) -> std::io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                if hidden || !is_hidden(&path) {
+                    walk_all(&path, hidden, out)?;
+                }
+            } else if path.is_file() {
+                out.push(path);
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut files = vec![];
+    for path in paths {
+        let pattern = path.to_string_lossy().replace('\\', "/");
+
+        if let Some(wildcard) = pattern.find(['*', '?']) {
+            let root = match pattern[..wildcard].rfind('/') {
+                Some(slash) => &pattern[..slash + 1],
+                None => "",
+            };
+            let regex = glob_regex(&pattern);
+            let hidden = pattern
+                .split('/')
+                .any(|part| part.starts_with('.') && part != "." && part != "..");
+
+            let mut matched = vec![];
+            walk_all(
+                Path::new(if root.is_empty() { "." } else { root }),
+                hidden,
+                &mut matched,
+            )?;
+            let mut matched: Vec<PathBuf> = matched
+                .into_iter()
+                .filter(|p| {
+                    let p = p.to_string_lossy().replace('\\', "/");
+                    regex.is_match(if root.is_empty() {
+                        p.strip_prefix("./").unwrap_or(&p)
+                    } else {
+                        &p
+                    })
+                })
+                .collect();
+            matched.sort();
+            files.extend(matched);
+        } else if path.is_dir() {
+            walk(path, &mut files)?;
+        } else {
+            files.push(path.clone());
+        }
+    }
+
+    Ok(files)
+}
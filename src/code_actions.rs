@@ -0,0 +1,717 @@
+/*
+    Copyright (c) 2026 アクゼスティア. All Rights Reserved.
+*/
+
+use std::collections::HashMap;
+use tower_lsp::lsp_types::*;
+use tree_sitter::Node;
+
+use crate::consts::CQL_RESERVED_KEYWORDS_LWC;
+use crate::formatting::offset_to_position;
+use crate::lint::{LintFix, TableRef};
+use crate::lsp::Backend;
+use crate::schema;
+use crate::tree_sitter::TS_CQL;
+
+/*
+    Plain copy of a statement, so nothing from the tree
+    is held across the schema queries
+*/
+#[derive(Debug)]
+struct StatementInfo {
+    keyword: String,
+    range: Range,
+    bytes: std::ops::Range<usize>,
+    text: String,
+    wild_card: Option<Range>,
+    in_batch: bool,
+}
+
+fn ranges_overlap(a: &Range, b: &Range) -> bool {
+    a.start <= b.end && b.start <= a.end
+}
+
+fn node_range(node: Node, text: &str) -> Range {
+    Range::new(
+        offset_to_position(text, node.start_byte()),
+        offset_to_position(text, node.end_byte()),
+    )
+}
+
+fn statement_info(node: Node, text: &str, in_batch: bool) -> StatementInfo {
+    let mut cursor = node.walk();
+    let children: Vec<Node> = node.children(&mut cursor).collect();
+
+    let keyword = children
+        .first()
+        .filter(|c| !c.is_named())
+        .map(|c| c.kind().to_lowercase())
+        .unwrap_or_default();
+
+    let wild_card = children
+        .iter()
+        .filter(|c| c.kind() == "selectors")
+        .filter_map(|c| c.named_child(0))
+        .find(|c| c.kind() == "wild_card")
+        .map(|c| node_range(c, text));
+
+    StatementInfo {
+        keyword,
+        range: node_range(node, text),
+        bytes: node.byte_range(),
+        text: node.utf8_text(text.as_bytes()).unwrap_or("").to_string(),
+        wild_card,
+        in_batch,
+    }
+}
+
+/*
+    Byte offsets of `needle` outside of string literals and brackets.
+    Alphabetic needles only match whole words, case insensitive.
+*/
+pub(crate) fn top_level_matches(s: &str, needle: &str) -> Vec<usize> {
+    let word = needle.chars().all(|c| c.is_alphabetic());
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+
+    let mut matches = Vec::new();
+    let mut depth = 0usize;
+    let mut quote: Option<char> = None;
+
+    for (i, c) in s.char_indices() {
+        if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            }
+            continue;
+        }
+
+        match c {
+            '\'' | '"' => quote = Some(c),
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+
+        if depth != 0 {
+            continue;
+        }
+
+        let Some(candidate) = s.get(i..i + needle.len()) else {
+            continue;
+        };
+
+        if !candidate.eq_ignore_ascii_case(needle) {
+            continue;
+        }
+
+        if word {
+            let before = s[..i].chars().next_back().is_some_and(is_word_char);
+            let after = s[i + needle.len()..]
+                .chars()
+                .next()
+                .is_some_and(is_word_char);
+            if before || after {
+                continue;
+            }
+        }
+
+        matches.push(i);
+    }
+
+    matches
+}
+
+pub(crate) fn split_top_level<'a>(s: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let mut last = 0;
+
+    for pos in top_level_matches(s, separator) {
+        parts.push(s[last..pos].trim());
+        last = pos + separator.len();
+    }
+    parts.push(s[last..].trim());
+
+    parts
+}
+
+/*
+    Content of the bracket opened at `open` and the offset right after
+    the matching closing bracket
+*/
+pub(crate) fn bracket_content(s: &str, open: usize) -> Option<(&str, usize)> {
+    let mut depth = 0usize;
+    let mut quote: Option<char> = None;
+
+    for (i, c) in s[open..].char_indices() {
+        if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            }
+            continue;
+        }
+
+        match c {
+            '\'' | '"' => quote = Some(c),
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((&s[open + 1..open + i], open + i + 1));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn strip_keyword<'a>(s: &'a str, keyword: &str) -> Option<&'a str> {
+    let s = s.trim_start();
+    let head = s.get(..keyword.len())?;
+    let rest = &s[keyword.len()..];
+
+    if head.eq_ignore_ascii_case(keyword) && rest.starts_with(char::is_whitespace) {
+        Some(rest.trim_start())
+    } else {
+        None
+    }
+}
+
+fn split_table_name(s: &str) -> (&str, &str) {
+    let end = s
+        .find(|c: char| c.is_whitespace() || c == '(')
+        .unwrap_or(s.len());
+    (&s[..end], &s[end..])
+}
+
+fn parse_assignment(s: &str) -> Option<(&str, &str)> {
+    let (column, value) = s.split_once('=')?;
+    let column = column.trim();
+    let value = value.trim();
+
+    let is_plain_column = !column.is_empty()
+        && column
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '"');
+    // `c = c + 1`, `tags = tags + {...}` can't be expressed as an INSERT
+    let references_itself = value
+        .strip_prefix(column)
+        .is_some_and(|rest| rest.trim_start().starts_with(['+', '-']));
+
+    if !is_plain_column || value.is_empty() || references_itself {
+        return None;
+    }
+
+    Some((column, value))
+}
+
+struct InsertParts<'a> {
+    table: &'a str,
+    columns: Vec<&'a str>,
+    values: Vec<&'a str>,
+    using: &'a str,
+}
+
+/* the name the schema has for a column written as `written` */
+pub(crate) fn column_name(written: &str) -> String {
+    match written
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+    {
+        Some(quoted) => quoted.replace("\"\"", "\""),
+        None => written.to_lowercase(),
+    }
+}
+
+/* a column name as it has to be written, quoted unless it's a plain lower case identifier */
+fn quote_identifier(name: &str) -> String {
+    let plain = name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        && !CQL_RESERVED_KEYWORDS_LWC.contains(&name.to_string());
+
+    if plain {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+}
+
+/*
+    INSERT INTO table (c1, c2) VALUES (v1, v2) [USING ...];
+
+    `IF NOT EXISTS` has no UPDATE equivalent, so it isn't converted.
+*/
+fn parse_insert(statement: &str) -> Option<InsertParts<'_>> {
+    let statement = statement.trim().trim_end_matches(';').trim_end();
+    let rest = strip_keyword(strip_keyword(statement, "insert")?, "into")?;
+    let (table, rest) = split_table_name(rest);
+
+    let open = rest.find('(')?;
+    if !rest[..open].trim().is_empty() {
+        return None;
+    }
+    let (columns, after) = bracket_content(rest, open)?;
+    let after_columns = &rest[after..];
+
+    let values_at = *top_level_matches(after_columns, "values").first()?;
+    if !after_columns[..values_at].trim().is_empty() {
+        return None;
+    }
+    let after_values = &after_columns[values_at + "values".len()..];
+    let open = after_values.find('(')?;
+    if !after_values[..open].trim().is_empty() {
+        return None;
+    }
+    let (values, after) = bracket_content(after_values, open)?;
+    let using = after_values[after..].trim();
+
+    if !using.is_empty() && strip_keyword(using, "using").is_none() {
+        return None;
+    }
+
+    let columns = split_top_level(columns, ",");
+    let values = split_top_level(values, ",");
+
+    if columns.len() != values.len() || columns.len() < 2 {
+        return None;
+    }
+
+    Some(InsertParts {
+        table,
+        columns,
+        values,
+        using,
+    })
+}
+
+struct UpdateParts<'a> {
+    table: &'a str,
+    using: &'a str,
+    assignments: Vec<(&'a str, &'a str)>,
+    conditions: Vec<(&'a str, &'a str)>,
+}
+
+/*
+    UPDATE table [USING ...] SET c1 = v1, ... WHERE k1 = v1 AND ...;
+
+    Only plain `=` assignments and restrictions are converted,
+    conditional updates (`IF ...`) are left alone.
+*/
+fn parse_update(statement: &str) -> Option<UpdateParts<'_>> {
+    let statement = statement.trim().trim_end_matches(';').trim_end();
+    let rest = strip_keyword(statement, "update")?;
+    let (table, rest) = split_table_name(rest);
+
+    if !top_level_matches(rest, "if").is_empty() {
+        return None;
+    }
+
+    let set_at = *top_level_matches(rest, "set").first()?;
+    let where_at = *top_level_matches(rest, "where").first()?;
+    if where_at < set_at {
+        return None;
+    }
+
+    let using = rest[..set_at].trim();
+    if !using.is_empty() && strip_keyword(using, "using").is_none() {
+        return None;
+    }
+
+    let assignments = split_top_level(&rest[set_at + "set".len()..where_at], ",")
+        .into_iter()
+        .map(parse_assignment)
+        .collect::<Option<Vec<_>>>()?;
+    let conditions = split_top_level(&rest[where_at + "where".len()..], "and")
+        .into_iter()
+        .map(parse_assignment)
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(UpdateParts {
+        table,
+        using,
+        assignments,
+        conditions,
+    })
+}
+
+fn refactor(title: impl Into<String>, uri: &Url, edits: Vec<TextEdit>) -> CodeActionOrCommand {
+    CodeActionOrCommand::CodeAction(CodeAction {
+        title: title.into(),
+        kind: Some(CodeActionKind::REFACTOR_REWRITE),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(uri.clone(), edits)])),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
+
+impl Backend {
+    /*
+        Quick fixes are computed together with the diagnostics
+        and carried in `Diagnostic.data` (see lint.rs).
+    */
+    pub fn quick_fixes(&self, uri: &Url, diagnostics: &[Diagnostic]) -> Vec<CodeActionOrCommand> {
+        let mut actions = Vec::new();
+
+        for diagnostic in diagnostics {
+            let Some(data) = &diagnostic.data else {
+                continue;
+            };
+
+            let fixes: Vec<LintFix> = match serde_json::from_value(data.clone()) {
+                Ok(fixes) => fixes,
+                Err(_) => continue,
+            };
+
+            for (index, fix) in fixes.into_iter().enumerate() {
+                actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: fix.title,
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    diagnostics: Some(vec![diagnostic.clone()]),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some(HashMap::from([(uri.clone(), fix.edits)])),
+                        ..Default::default()
+                    }),
+                    is_preferred: Some(index == 0),
+                    ..Default::default()
+                }));
+            }
+        }
+
+        actions
+    }
+
+    pub async fn refactor_actions(
+        &self,
+        uri: &Url,
+        text: &str,
+        range: Range,
+    ) -> Vec<CodeActionOrCommand> {
+        let tree = {
+            let mut parser = TS_CQL.lock().await;
+            match parser.parse(text, None) {
+                Some(t) => t,
+                None => return vec![],
+            }
+        };
+
+        let (statements, table_refs) = {
+            let root = tree.root_node();
+            let mut statements = Vec::new();
+            let mut cursor = root.walk();
+
+            for node in root.children(&mut cursor) {
+                if node.kind() != "cql_commands" {
+                    continue;
+                }
+                statements.push(statement_info(node, text, false));
+
+                let mut inner = node.walk();
+                for dml in node.children(&mut inner) {
+                    if dml.kind() == "dml_statement" {
+                        statements.push(statement_info(dml, text, true));
+                    }
+                }
+            }
+
+            (statements, Self::collect_table_refs(root, text))
+        };
+
+        let selected: Vec<&StatementInfo> = statements
+            .iter()
+            .filter(|s| ranges_overlap(&s.range, &range))
+            .collect();
+        let selected_refs: Vec<&TableRef> = table_refs
+            .iter()
+            .filter(|r| ranges_overlap(&r.range, &range))
+            .collect();
+
+        let mut actions = Vec::new();
+
+        if let Some(action) = self.qualify_tables_action(uri, &selected_refs) {
+            actions.push(action);
+        }
+
+        if let Some(action) = self.unqualify_tables_action(uri, &selected_refs) {
+            actions.push(action);
+        }
+
+        if let Some(action) = self.wrap_in_batch_action(uri, text, &selected) {
+            actions.push(action);
+        }
+
+        for statement in &selected {
+            let table_ref = table_refs
+                .iter()
+                .find(|r| ranges_overlap(&r.range, &statement.range));
+
+            match statement.keyword.as_str() {
+                "insert" => {
+                    if let Some(action) = self
+                        .insert_to_update_action(uri, statement, table_ref)
+                        .await
+                    {
+                        actions.push(action);
+                    }
+                }
+                "update" => {
+                    if let Some(action) = self.update_to_insert_action(uri, statement) {
+                        actions.push(action);
+                    }
+                }
+                "select" => {
+                    if let Some(action) = self
+                        .expand_select_star_action(uri, statement, table_ref)
+                        .await
+                    {
+                        actions.push(action);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        actions
+    }
+
+    fn qualify_tables_action(&self, uri: &Url, refs: &[&TableRef]) -> Option<CodeActionOrCommand> {
+        let edits: Vec<TextEdit> = refs
+            .iter()
+            .filter(|r| !r.qualified)
+            .filter_map(|r| {
+                let keyspace = r.use_keyspace.as_ref()?;
+                Some(TextEdit {
+                    range: r.range,
+                    new_text: format!("{keyspace}.{}", r.table),
+                })
+            })
+            .collect();
+
+        if edits.is_empty() {
+            return None;
+        }
+
+        Some(refactor(
+            "Qualify table names with the `USE` keyspace",
+            uri,
+            edits,
+        ))
+    }
+
+    fn unqualify_tables_action(
+        &self,
+        uri: &Url,
+        refs: &[&TableRef],
+    ) -> Option<CodeActionOrCommand> {
+        let edits: Vec<TextEdit> = refs
+            .iter()
+            .filter(|r| r.qualified && r.use_keyspace.is_some() && r.keyspace == r.use_keyspace)
+            .map(|r| TextEdit {
+                range: r.range,
+                new_text: r.table.clone(),
+            })
+            .collect();
+
+        if edits.is_empty() {
+            return None;
+        }
+
+        Some(refactor(
+            "Remove keyspace qualifiers matching `USE`",
+            uri,
+            edits,
+        ))
+    }
+
+    fn wrap_in_batch_action(
+        &self,
+        uri: &Url,
+        text: &str,
+        statements: &[&StatementInfo],
+    ) -> Option<CodeActionOrCommand> {
+        let top_level: Vec<&&StatementInfo> = statements.iter().filter(|s| !s.in_batch).collect();
+
+        if top_level.is_empty()
+            || !top_level
+                .iter()
+                .all(|s| matches!(s.keyword.as_str(), "insert" | "update" | "delete"))
+        {
+            return None;
+        }
+
+        let (first, last) = (top_level.first()?, top_level.last()?);
+        let range = Range::new(first.range.start, last.range.end);
+        let indent = " ".repeat(self.config.indent as usize);
+
+        /* ranges count UTF-16 code units, so the body is cut by bytes */
+        let body: Vec<String> = text
+            .get(first.bytes.start..last.bytes.end)?
+            .split('\n')
+            .map(|line| {
+                let content = line.trim_end();
+                if content.trim().is_empty() {
+                    String::new()
+                } else {
+                    format!("{indent}{content}")
+                }
+            })
+            .collect();
+
+        Some(refactor(
+            "Wrap in `BEGIN BATCH ... APPLY BATCH`",
+            uri,
+            vec![TextEdit {
+                range,
+                new_text: format!("BEGIN BATCH\n{}\nAPPLY BATCH;", body.join("\n")),
+            }],
+        ))
+    }
+
+    async fn primary_key_columns(&self, table_ref: Option<&TableRef>) -> Vec<String> {
+        let Some(table_ref) = table_ref else {
+            return vec![];
+        };
+        let Some(keyspace) = &table_ref.keyspace else {
+            return vec![];
+        };
+
+        schema::columns(&self.config, keyspace, &table_ref.table)
+            .await
+            .into_iter()
+            .filter(|c| c.is_primary_key())
+            .map(|c| c.column_name)
+            .collect()
+    }
+
+    /*
+        Only offered when the primary key is known from the schema
+        and every column of it is inserted.
+    */
+    async fn insert_to_update_action(
+        &self,
+        uri: &Url,
+        statement: &StatementInfo,
+        table_ref: Option<&TableRef>,
+    ) -> Option<CodeActionOrCommand> {
+        let insert = parse_insert(&statement.text)?;
+
+        let primary_key = self.primary_key_columns(table_ref).await;
+        let inserted: Vec<String> = insert.columns.iter().map(|c| column_name(c)).collect();
+        if primary_key.is_empty() || !primary_key.iter().all(|k| inserted.contains(k)) {
+            return None;
+        }
+
+        let is_key = |column: &str| primary_key.contains(&column_name(column));
+        let pairs: Vec<(&str, &str)> = insert
+            .columns
+            .iter()
+            .copied()
+            .zip(insert.values.iter().copied())
+            .collect();
+
+        let set: Vec<String> = pairs
+            .iter()
+            .filter(|(c, _)| !is_key(c))
+            .map(|(c, v)| format!("{c} = {v}"))
+            .collect();
+        let conditions: Vec<String> = pairs
+            .iter()
+            .filter(|(c, _)| is_key(c))
+            .map(|(c, v)| format!("{c} = {v}"))
+            .collect();
+
+        if set.is_empty() || conditions.is_empty() {
+            return None;
+        }
+
+        let using = if insert.using.is_empty() {
+            String::new()
+        } else {
+            format!(" {}", insert.using)
+        };
+
+        Some(refactor(
+            "Convert to `UPDATE ... SET`",
+            uri,
+            vec![TextEdit {
+                range: statement.range,
+                new_text: format!(
+                    "UPDATE {}{using} SET {} WHERE {};",
+                    insert.table,
+                    set.join(", "),
+                    conditions.join(" AND ")
+                ),
+            }],
+        ))
+    }
+
+    fn update_to_insert_action(
+        &self,
+        uri: &Url,
+        statement: &StatementInfo,
+    ) -> Option<CodeActionOrCommand> {
+        let update = parse_update(&statement.text)?;
+
+        let pairs: Vec<&(&str, &str)> = update
+            .conditions
+            .iter()
+            .chain(update.assignments.iter())
+            .collect();
+        let columns: Vec<&str> = pairs.iter().map(|(c, _)| *c).collect();
+        let values: Vec<&str> = pairs.iter().map(|(_, v)| *v).collect();
+
+        let using = if update.using.is_empty() {
+            String::new()
+        } else {
+            format!(" {}", update.using)
+        };
+
+        Some(refactor(
+            "Convert to `INSERT INTO ... VALUES`",
+            uri,
+            vec![TextEdit {
+                range: statement.range,
+                new_text: format!(
+                    "INSERT INTO {} ({}) VALUES ({}){using};",
+                    update.table,
+                    columns.join(", "),
+                    values.join(", ")
+                ),
+            }],
+        ))
+    }
+
+    async fn expand_select_star_action(
+        &self,
+        uri: &Url,
+        statement: &StatementInfo,
+        table_ref: Option<&TableRef>,
+    ) -> Option<CodeActionOrCommand> {
+        let wild_card = statement.wild_card?;
+        let table_ref = table_ref?;
+        let keyspace = table_ref.keyspace.as_ref()?;
+
+        let columns: Vec<String> = schema::columns(&self.config, keyspace, &table_ref.table)
+            .await
+            .into_iter()
+            .map(|c| quote_identifier(&c.column_name))
+            .collect();
+
+        if columns.is_empty() {
+            return None;
+        }
+
+        Some(refactor(
+            "Expand `*` into the column list",
+            uri,
+            vec![TextEdit {
+                range: wild_card,
+                new_text: columns.join(", "),
+            }],
+        ))
+    }
+}
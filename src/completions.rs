@@ -4,6 +4,7 @@
 
 use log::{info, warn};
 
+use crate::config::Case;
 use crate::consts::*;
 use crate::cqlsh::{self, Column};
 use crate::lsp::Backend;
@@ -890,7 +891,7 @@ impl Backend {
                             Ok(mut r) => {
                                 items.append(&mut r);
                             }
-                            Err(_) => {}
+                            Err(e) => warn!("Failed to query the columns of {ksp}.{tbl}: {e}"),
                         }
 
                         let mut result: Vec<CompletionItem> = Vec::new();
@@ -969,7 +970,7 @@ impl Backend {
                     Ok(mut r) => {
                         items.append(&mut r);
                     }
-                    Err(_) => {}
+                    Err(e) => warn!("Failed to query the columns of {keyspace}.{tbl_name}: {e}"),
                 }
             } else {
                 items = cqlsh::query_keyspace_scoped_fields(&self.config, &keyspace)
@@ -1857,13 +1858,54 @@ impl Backend {
         false
     }
 
-    pub fn should_edit_select_statement(&self, line: &str, lines: &Vec<String>) -> bool {
-        false
+    /*
+        Keywords, types and functions are listed in both cases,
+        only the items in the configured `fmt` casing are kept.
+        Items without a counterpart in the other case (e.g. `blobAs`) always stay.
+    */
+    pub fn apply_completion_case(&self, response: CompletionResponse) -> CompletionResponse {
+        let case_of = |item: &CompletionItem| match item.kind {
+            Some(CompletionItemKind::FUNCTION) => self.config.function_case,
+            Some(CompletionItemKind::KEYWORD) if TYPES.iter().any(|t| t == item) => {
+                self.config.type_case
+            }
+            Some(CompletionItemKind::KEYWORD) => self.config.keyword_case,
+            _ => Case::Preserve,
+        };
+
+        let filter = |items: Vec<CompletionItem>| -> Vec<CompletionItem> {
+            let labels: Vec<String> = items.iter().map(|i| i.label.clone()).collect();
+
+            items
+                .into_iter()
+                .filter(|item| {
+                    let cased = case_of(item).apply(&item.label);
+                    cased == item.label || !labels.contains(&cased)
+                })
+                .collect()
+        };
+
+        match response {
+            CompletionResponse::Array(items) => CompletionResponse::Array(filter(items)),
+            CompletionResponse::List(mut list) => {
+                list.items = filter(list.items);
+                CompletionResponse::List(list)
+            }
+        }
     }
 
     pub async fn completion(
         &self,
         params: CompletionParams,
+    ) -> tower_lsp::jsonrpc::Result<Option<CompletionResponse>> {
+        let response = self.suggest_completions(params).await?;
+
+        Ok(response.map(|r| self.apply_completion_case(r)))
+    }
+
+    async fn suggest_completions(
+        &self,
+        params: CompletionParams,
     ) -> tower_lsp::jsonrpc::Result<Option<CompletionResponse>> {
         let uri = params.text_document_position.text_document.uri;
         let position = params.text_document_position.position;
@@ -1957,14 +1999,14 @@ impl Backend {
             return self.handle_drop_view_completions().await;
         }
 
-        if ssh_from {
-            return self.handle_from_completion();
-        }
-
         if ssh_fields {
             return self.handle_fields_completion(line, &position).await;
         }
 
+        if ssh_from {
+            return self.handle_from_completion();
+        }
+
         if ssh_table_completions {
             return self.handle_table_completion(&position).await;
         }
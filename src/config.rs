@@ -2,36 +2,310 @@
     Copyright (c) 2026 アクゼスティア. All Rights Reserved.
 */
 
+use crate::config_toml::{is_toml, migrate, parse_toml};
+use crate::cqlshrc::{Cqlshrc, expand_home};
+use crate::lint::{LINT_RULES, LintLevel};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 
-#[derive(Debug)]
+pub const FEATURES: [&str; 2] = ["context_aware_completions", "diagnostics"];
+
+/* what a key of the config takes, for completion and hover in the config itself */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueKind {
+    Text,
+    Number,
+    Bool,
+    Choice(&'static [&'static str]),
+    /* `known_nodes { ... }` */
+    List,
+    /* `udf_formatters { ... }` */
+    Map,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ConfigKey {
+    pub name: &'static str,
+    pub kind: ValueKind,
+    pub doc: &'static str,
+}
+
+pub const CONFIG_BLOCKS: &[(&str, &str)] = &[
+    (
+        "db",
+        "The database connection, `db \"name\" { ... }` is a named profile on top of it",
+    ),
+    ("fmt", "Settings of the formatter"),
+    ("features", "Enables or disables features"),
+    ("lint", "The level of each diagnostic rule"),
+    ("debug", "Logging"),
+];
+
+pub const DEFAULT_PROFILE_KEY: ConfigKey = ConfigKey {
+    name: "default_profile",
+    kind: ValueKind::Text,
+    doc: "The `db \"name\"` profile used on start, the plain `db` block when there's none",
+};
+
+const DB_KEYS: &[ConfigKey] = &[
+    ConfigKey {
+        name: "type",
+        kind: ValueKind::Choice(&["datastax_hcd", "scylla", "dynamo"]),
+        doc: "The kind of database",
+    },
+    ConfigKey {
+        name: "preferred_dc",
+        kind: ValueKind::Text,
+        doc: "Datacenter the queries are sent to first",
+    },
+    ConfigKey {
+        name: "known_nodes",
+        kind: ValueKind::List,
+        doc: "`host:port` of the nodes to connect to",
+    },
+    ConfigKey {
+        name: "tls",
+        kind: ValueKind::Choice(&["none", "tls", "mtls"]),
+        doc: "Encryption of the connection, `mtls` also authenticates cqlls with `client_cert` and `client_key`",
+    },
+    ConfigKey {
+        name: "ca_cert",
+        kind: ValueKind::Text,
+        doc: "PEM file of the CA the certificate of the server is checked against",
+    },
+    ConfigKey {
+        name: "client_cert",
+        kind: ValueKind::Text,
+        doc: "PEM file of the client certificate, `mtls` only",
+    },
+    ConfigKey {
+        name: "client_key",
+        kind: ValueKind::Text,
+        doc: "PEM file of the key of `client_cert`, `mtls` only",
+    },
+    ConfigKey {
+        name: "from_cqlshrc",
+        kind: ValueKind::Text,
+        doc: "A cqlshrc to read the connection from, what the block sets itself wins",
+    },
+    ConfigKey {
+        name: "user",
+        kind: ValueKind::Text,
+        doc: "User to log in as",
+    },
+    ConfigKey {
+        name: "pswd",
+        kind: ValueKind::Text,
+        doc: "Password of `user`, used when none of `pswd_env`, `pswd_file` and `pswd_command` is set",
+    },
+    ConfigKey {
+        name: "pswd_env",
+        kind: ValueKind::Text,
+        doc: "Environment variable holding the password",
+    },
+    ConfigKey {
+        name: "pswd_file",
+        kind: ValueKind::Text,
+        doc: "File holding the password, without the trailing line break",
+    },
+    ConfigKey {
+        name: "pswd_command",
+        kind: ValueKind::Text,
+        doc: "Command printing the password on its first line",
+    },
+];
+
+const CASES: &[&str] = &["upper", "lower", "preserve"];
+
+const FMT_KEYS: &[ConfigKey] = &[
+    ConfigKey {
+        name: "type_padding",
+        kind: ValueKind::Number,
+        doc: "Padding between column names and their types in `CREATE TABLE`, 0-255",
+    },
+    ConfigKey {
+        name: "indent",
+        kind: ValueKind::Number,
+        doc: "Spaces per indentation level, 0-255",
+    },
+    ConfigKey {
+        name: "max_width",
+        kind: ValueKind::Number,
+        doc: "Line width the formatter breaks at, 0-65535",
+    },
+    ConfigKey {
+        name: "keyword_case",
+        kind: ValueKind::Choice(CASES),
+        doc: "Casing of keywords",
+    },
+    ConfigKey {
+        name: "type_case",
+        kind: ValueKind::Choice(CASES),
+        doc: "Casing of types",
+    },
+    ConfigKey {
+        name: "function_case",
+        kind: ValueKind::Choice(CASES),
+        doc: "Casing of native functions",
+    },
+    ConfigKey {
+        name: "udf_formatters",
+        kind: ValueKind::Map,
+        doc: "Command per language formatting the body of a UDF, it reads the body from stdin",
+    },
+];
+
+const DEBUG_KEYS: &[ConfigKey] = &[ConfigKey {
+    name: "logging",
+    kind: ValueKind::Bool,
+    doc: "Writes a log file",
+}];
+
+/* the keys of `block`, the ones of `features` and `lint` come from what exists */
+pub fn config_keys(block: &str) -> Vec<ConfigKey> {
+    match block {
+        "db" => DB_KEYS.to_vec(),
+        "fmt" => FMT_KEYS.to_vec(),
+        "debug" => DEBUG_KEYS.to_vec(),
+        "features" => FEATURES
+            .iter()
+            .map(|&name| ConfigKey {
+                name,
+                kind: ValueKind::Bool,
+                doc: match name {
+                    "context_aware_completions" => {
+                        "Completes keyspaces, tables and columns from the database"
+                    }
+                    _ => "Shows diagnostics",
+                },
+            })
+            .collect(),
+        "lint" => LINT_RULES
+            .iter()
+            .map(|rule| ConfigKey {
+                name: rule.id,
+                kind: ValueKind::Choice(&["off", "hint", "warning", "error"]),
+                doc: rule.description,
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum TlsMode {
     None,
     Tls,
     MTls,
 }
 
-#[derive(Debug)]
+impl TlsMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(TlsMode::None),
+            "tls" => Some(TlsMode::Tls),
+            "mtls" => Some(TlsMode::MTls),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum DbType {
     DataStaxHCD,
     Scylla,
     Dynamo,
 }
 
-#[derive(Debug)]
+impl DbType {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "datastax_hcd" => Some(DbType::DataStaxHCD),
+            "scylla" => Some(DbType::Scylla),
+            "dynamo" => Some(DbType::Dynamo),
+            _ => None,
+        }
+    }
+}
+
+/* casing the formatter and completions use for keywords, types and functions */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Case {
+    Upper,
+    Lower,
+    Preserve,
+}
+
+impl Case {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "upper" => Some(Case::Upper),
+            "lower" => Some(Case::Lower),
+            "preserve" => Some(Case::Preserve),
+            _ => None,
+        }
+    }
+
+    pub fn apply(&self, s: &str) -> String {
+        match self {
+            Case::Upper => s.to_uppercase(),
+            Case::Lower => s.to_lowercase(),
+            Case::Preserve => s.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for Case {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Case::Upper => "upper",
+            Case::Lower => "lower",
+            Case::Preserve => "preserve",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Clone)]
 pub struct CqllsConfig {
     pub db_type: DbType,
     pub preferred_dc: String,
     pub known_nodes: Vec<String>,
     pub tls: TlsMode,
     pub ca_cert: String,
+    /* client certificate and key for `mtls` */
+    pub client_cert: String,
+    pub client_key: String,
     pub user: String,
     pub pswd: String,
+    /* where the password comes from instead of `pswd`, empty when unset */
+    pub pswd_env: String,
+    pub pswd_file: String,
+    pub pswd_command: String,
+    /* cqlshrc the settings not in the block come from, empty when unset */
+    pub from_cqlshrc: String,
+    /* `db "name" { ... }` blocks, the `db` settings above are the ones of `profile` */
+    pub profiles: BTreeMap<String, DbProfile>,
+    pub default_profile: Option<String>,
+    pub profile: Option<String>,
     pub type_padding: u8,
     pub indent: u8,
+    pub max_width: u16,
+    pub keyword_case: Case,
+    pub type_case: Case,
+    pub function_case: Case,
+    /* language of a UDF body -> command its body is piped through */
+    pub udf_formatters: HashMap<String, String>,
     pub features: Vec<String>,
+    pub lint: HashMap<String, LintLevel>,
     pub logging: bool,
+    /*
+        Keys of a project config left out while reading it, a value that reads
+        the environment (see `resolve`) or a cqlshrc outside of the project
+    */
+    pub left_out: Vec<String>,
 }
 
 impl Default for CqllsConfig {
@@ -42,17 +316,252 @@ impl Default for CqllsConfig {
             known_nodes: vec!["127.0.0.1:9042".to_string()],
             tls: TlsMode::None,
             ca_cert: String::new(),
+            client_cert: String::new(),
+            client_key: String::new(),
             user: "cassandra".to_string(),
             pswd: "cassandra".to_string(),
+            pswd_env: String::new(),
+            pswd_file: String::new(),
+            pswd_command: String::new(),
+            from_cqlshrc: String::new(),
+            profiles: BTreeMap::new(),
+            default_profile: None,
+            profile: None,
             type_padding: 8,
             indent: 4,
+            max_width: 80,
+            keyword_case: Case::Preserve,
+            type_case: Case::Preserve,
+            function_case: Case::Preserve,
+            udf_formatters: HashMap::new(),
             features: vec!["context_aware_completions".to_string()],
+            lint: HashMap::new(),
             logging: false,
+            left_out: vec![],
+        }
+    }
+}
+
+/* by hand, so the password never ends up in a log */
+impl std::fmt::Debug for CqllsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CqllsConfig")
+            .field("db_type", &self.db_type)
+            .field("preferred_dc", &self.preferred_dc)
+            .field("known_nodes", &self.known_nodes)
+            .field("tls", &self.tls)
+            .field("ca_cert", &self.ca_cert)
+            .field("client_cert", &self.client_cert)
+            .field("client_key", &self.client_key)
+            .field("user", &self.user)
+            .field("pswd", &"***")
+            .field("pswd_env", &self.pswd_env)
+            .field("pswd_file", &self.pswd_file)
+            .field("pswd_command", &self.pswd_command)
+            .field("from_cqlshrc", &self.from_cqlshrc)
+            .field("profiles", &self.profiles)
+            .field("default_profile", &self.default_profile)
+            .field("profile", &self.profile)
+            .field("type_padding", &self.type_padding)
+            .field("indent", &self.indent)
+            .field("max_width", &self.max_width)
+            .field("keyword_case", &self.keyword_case)
+            .field("type_case", &self.type_case)
+            .field("function_case", &self.function_case)
+            .field("udf_formatters", &self.udf_formatters)
+            .field("features", &self.features)
+            .field("lint", &self.lint)
+            .field("logging", &self.logging)
+            .field("left_out", &self.left_out)
+            .finish()
+    }
+}
+
+/* the `db` settings of a named profile */
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct DbProfile {
+    pub db_type: DbType,
+    pub preferred_dc: String,
+    pub known_nodes: Vec<String>,
+    pub tls: TlsMode,
+    pub ca_cert: String,
+    /* client certificate and key for `mtls` */
+    pub client_cert: String,
+    pub client_key: String,
+    pub user: String,
+    pub pswd: String,
+    pub pswd_env: String,
+    pub pswd_file: String,
+    pub pswd_command: String,
+    /* cqlshrc the settings not in the block come from, empty when unset */
+    pub from_cqlshrc: String,
+}
+
+impl DbProfile {
+    pub(crate) fn of(config: &CqllsConfig) -> Self {
+        Self {
+            db_type: config.db_type.clone(),
+            preferred_dc: config.preferred_dc.clone(),
+            known_nodes: config.known_nodes.clone(),
+            tls: config.tls.clone(),
+            ca_cert: config.ca_cert.clone(),
+            client_cert: config.client_cert.clone(),
+            client_key: config.client_key.clone(),
+            user: config.user.clone(),
+            pswd: config.pswd.clone(),
+            pswd_env: config.pswd_env.clone(),
+            pswd_file: config.pswd_file.clone(),
+            pswd_command: config.pswd_command.clone(),
+            from_cqlshrc: config.from_cqlshrc.clone(),
+        }
+    }
+}
+
+impl DbProfile {
+    fn write_block(&self, header: &str, file: &mut impl Write) -> std::io::Result<()> {
+        let db_type = match self.db_type {
+            DbType::DataStaxHCD => "datastax_hcd",
+            DbType::Scylla => "scylla",
+            DbType::Dynamo => "dynamo",
+        };
+        let tls = match self.tls {
+            TlsMode::None => "none",
+            TlsMode::Tls => "tls",
+            TlsMode::MTls => "mtls",
+        };
+
+        writeln!(file, "{header} {{")?;
+        if !self.from_cqlshrc.is_empty() {
+            writeln!(file, "    from_cqlshrc: {}", quote(&self.from_cqlshrc))?;
+        }
+        writeln!(file, "    type: \"{db_type}\"")?;
+        writeln!(file, "    preferred_dc: {}", quote(&self.preferred_dc))?;
+        writeln!(file)?;
+        writeln!(file, "    tls: \"{tls}\"")?;
+        writeln!(file, "    ca_cert: {}", quote(&self.ca_cert))?;
+        for (key, value) in [
+            ("client_cert", &self.client_cert),
+            ("client_key", &self.client_key),
+        ] {
+            if !value.is_empty() {
+                writeln!(file, "    {key}: {}", quote(value))?;
+            }
+        }
+        writeln!(file)?;
+        writeln!(file, "    user: {}", quote(&self.user))?;
+        writeln!(file, "    pswd: {}", quote(&self.pswd))?;
+        for (key, value) in [
+            ("pswd_env", &self.pswd_env),
+            ("pswd_file", &self.pswd_file),
+            ("pswd_command", &self.pswd_command),
+        ] {
+            if !value.is_empty() {
+                writeln!(file, "    {key}: {}", quote(value))?;
+            }
         }
+        writeln!(file)?;
+        writeln!(file, "    known_nodes {{")?;
+        for node in &self.known_nodes {
+            writeln!(file, "      {}", quote(node))?;
+        }
+        writeln!(file, "    }}")?;
+        writeln!(file, "}}")
+    }
+}
+
+impl std::fmt::Debug for DbProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DbProfile")
+            .field("db_type", &self.db_type)
+            .field("preferred_dc", &self.preferred_dc)
+            .field("known_nodes", &self.known_nodes)
+            .field("tls", &self.tls)
+            .field("ca_cert", &self.ca_cert)
+            .field("client_cert", &self.client_cert)
+            .field("client_key", &self.client_key)
+            .field("user", &self.user)
+            .field("pswd", &"***")
+            .field("pswd_env", &self.pswd_env)
+            .field("pswd_file", &self.pswd_file)
+            .field("pswd_command", &self.pswd_command)
+            .field("from_cqlshrc", &self.from_cqlshrc)
+            .finish()
     }
 }
 
 impl CqllsConfig {
+    /* `false` when there's no such profile, nothing changes then */
+    pub fn use_profile(&mut self, name: &str) -> bool {
+        let Some(profile) = self.profiles.get(name).cloned() else {
+            return false;
+        };
+
+        self.db_type = profile.db_type;
+        self.preferred_dc = profile.preferred_dc;
+        self.known_nodes = profile.known_nodes;
+        self.tls = profile.tls;
+        self.ca_cert = profile.ca_cert;
+        self.client_cert = profile.client_cert;
+        self.client_key = profile.client_key;
+        self.user = profile.user;
+        self.pswd = profile.pswd;
+        self.pswd_env = profile.pswd_env;
+        self.pswd_file = profile.pswd_file;
+        self.pswd_command = profile.pswd_command;
+        self.from_cqlshrc = profile.from_cqlshrc;
+        self.profile = Some(name.to_string());
+        true
+    }
+
+    /*
+        Drops the settings that run a command, for a config that came with
+        a project. The ones that were set, nothing was dropped when it's empty.
+    */
+    pub fn drop_commands(&mut self) -> Vec<&'static str> {
+        let mut dropped = vec![];
+
+        let commands = std::iter::once(&mut self.pswd_command)
+            .chain(self.profiles.values_mut().map(|p| &mut p.pswd_command));
+        let mut any = false;
+        for command in commands {
+            any |= !command.is_empty();
+            command.clear();
+        }
+        if any {
+            dropped.push("pswd_command");
+        }
+
+        if !self.udf_formatters.is_empty() {
+            self.udf_formatters.clear();
+            dropped.push("udf_formatters");
+        }
+
+        dropped
+    }
+
+    /*
+        Drops the settings that read a secret of the machine, in the `db`
+        settings and every profile, the same way as `drop_commands`.
+        A cqlshrc is left out when the config is read, see `cqlshrc_path`.
+    */
+    pub fn drop_secrets(&mut self) -> Vec<&'static str> {
+        let mut set = [false; 1];
+        let sources = [&mut self.pswd_file];
+        let profiles = self.profiles.values_mut().map(|p| [&mut p.pswd_file]);
+        for fields in std::iter::once(sources).chain(profiles) {
+            for (set, field) in set.iter_mut().zip(fields) {
+                *set |= !field.is_empty();
+                field.clear();
+            }
+        }
+
+        SECRET_DB_KEYS
+            .into_iter()
+            .zip(set)
+            .filter_map(|(key, set)| set.then_some(key))
+            .collect()
+    }
+
     pub fn with_knodes(nodes: Vec<String>) -> Self {
         Self {
             db_type: DbType::Scylla,
@@ -60,66 +569,142 @@ impl CqllsConfig {
             known_nodes: nodes,
             tls: TlsMode::None,
             ca_cert: String::new(),
+            client_cert: String::new(),
+            client_key: String::new(),
             user: "cassandra".to_string(),
             pswd: "cassandra".to_string(),
+            pswd_env: String::new(),
+            pswd_file: String::new(),
+            pswd_command: String::new(),
+            from_cqlshrc: String::new(),
+            profiles: BTreeMap::new(),
+            default_profile: None,
+            profile: None,
             type_padding: 8,
             indent: 4,
+            max_width: 80,
+            keyword_case: Case::Preserve,
+            type_case: Case::Preserve,
+            function_case: Case::Preserve,
+            udf_formatters: HashMap::new(),
             features: vec!["context_aware_completions".to_string()],
+            lint: HashMap::new(),
             logging: false,
+            left_out: vec![],
         }
     }
 
+    /* the database a session of the config connects to, and as whom */
+    pub fn db_key(&self) -> String {
+        format!("{}@{}", self.user, self.known_nodes.join(","))
+    }
+
     pub fn has_feature(&self, feature: &str) -> bool {
         self.features.iter().any(|f| f == feature)
     }
 
+    pub fn lint_level(&self, rule: &str) -> LintLevel {
+        if let Some(level) = self.lint.get(rule) {
+            return *level;
+        }
+
+        LINT_RULES
+            .iter()
+            .find(|r| r.id == rule)
+            .map(|r| r.default_level)
+            .unwrap_or(LintLevel::Off)
+    }
+
     pub fn try_from_config_file() -> CqllsConfig {
-        std::fs::read_to_string(".cqlls")
-            .ok()
-            .and_then(|contents| parse_config(&contents).ok())
-            .unwrap_or_default()
+        Self::from_file(Path::new(".cqlls"))
     }
 
-    pub fn write_default_config_file() -> Result<(), Box<dyn std::error::Error>> {
-        let current_dir = std::env::current_dir()?;
-        let config_path: PathBuf = current_dir.join(".cqlls");
+    /* `path` or the defaults when it doesn't exist or doesn't parse */
+    pub fn from_file(path: &Path) -> CqllsConfig {
+        let Ok(contents) = std::fs::read_to_string(path) else {
+            return CqllsConfig::default();
+        };
 
-        let mut file = std::fs::File::create(config_path)?;
+        parse_config_file(&contents, path).unwrap_or_else(|e| {
+            log::error!(
+                "{}:{}: {}, using the default config",
+                path.display(),
+                e.line,
+                e.msg
+            );
+            CqllsConfig::default()
+        })
+    }
 
-        writeln!(file, "db {{")?;
-        writeln!(file, "    type: \"scylla\"")?;
-        writeln!(file, "    preferred_dc: \"\"")?;
-        writeln!(file)?;
-        writeln!(file, "    tls: \"none\"")?;
-        writeln!(file, "    ca_cert: \"\"")?;
-        writeln!(file)?;
-        writeln!(file, "    user: \"cassandra\"")?;
-        writeln!(file, "    pswd: \"cassandra\"")?;
-        writeln!(file)?;
-        writeln!(file, "    known_nodes {{")?;
-        writeln!(file, "      \"127.0.0.1:9042\"")?;
-        writeln!(file, "    }}")?;
-        writeln!(file, "}}")?;
+    /* in the format of `path`, TOML for `.toml` files */
+    pub fn write_default_config_file(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let mut file = std::fs::File::create(path)?;
+
+        if is_toml(path) {
+            let mut src = vec![];
+            CqllsConfig::default().write_config(&mut src)?;
+            file.write_all(migrate(&String::from_utf8(src)?)?.as_bytes())?;
+        } else {
+            CqllsConfig::default().write_config(&mut file)?;
+        }
+
+        file.flush()?;
+        Ok(())
+    }
+
+    /* writes the config in the `.cqlls` format, `parse_config` reads it back as it is */
+    pub fn write_config(&self, file: &mut impl Write) -> std::io::Result<()> {
+        DbProfile::of(self).write_block("db", file)?;
         writeln!(file)?;
 
+        if let Some(name) = &self.default_profile {
+            writeln!(file, "default_profile: {}", quote(name))?;
+            writeln!(file)?;
+        }
+        for (name, profile) in &self.profiles {
+            profile.write_block(&format!("db {}", quote(name)), file)?;
+            writeln!(file)?;
+        }
+
         writeln!(file, "fmt {{")?;
-        writeln!(file, "    type_padding: 8")?;
-        writeln!(file, "    indent: 4")?;
+        writeln!(file, "    type_padding: {}", self.type_padding)?;
+        writeln!(file, "    indent: {}", self.indent)?;
+        writeln!(file, "    max_width: {}", self.max_width)?;
+        writeln!(file, "    keyword_case: {}", self.keyword_case)?;
+        writeln!(file, "    type_case: {}", self.type_case)?;
+        writeln!(file, "    function_case: {}", self.function_case)?;
+        if !self.udf_formatters.is_empty() {
+            let mut formatters: Vec<_> = self.udf_formatters.iter().collect();
+            formatters.sort();
+
+            writeln!(file, "    udf_formatters {{")?;
+            for (language, command) in formatters {
+                writeln!(file, "        {language}: {}", quote(command))?;
+            }
+            writeln!(file, "    }}")?;
+        }
         writeln!(file, "}}")?;
         writeln!(file)?;
 
         writeln!(file, "features {{")?;
-        writeln!(file, "    context_aware_completions: true")?;
-        writeln!(file, "    diagnostics: false")?;
+        for feature in FEATURES {
+            writeln!(file, "    {feature}: {}", self.has_feature(feature))?;
+        }
+        writeln!(file, "}}")?;
+        writeln!(file)?;
+
+        writeln!(file, "lint {{")?;
+        for rule in LINT_RULES {
+            writeln!(file, "    {}: {}", rule.id, self.lint_level(rule.id))?;
+        }
         writeln!(file, "}}")?;
         writeln!(file)?;
 
         writeln!(file, "debug {{")?;
-        writeln!(file, "    logging: false")?;
+        writeln!(file, "    logging: {}", self.logging)?;
         writeln!(file, "}}")?;
         writeln!(file)?;
 
-        file.flush()?;
         Ok(())
     }
 }
@@ -131,7 +716,7 @@ pub struct ParseError {
 }
 
 impl ParseError {
-    fn new(line: usize, msg: impl Into<String>) -> Self {
+    pub(crate) fn new(line: usize, msg: impl Into<String>) -> Self {
         Self {
             line,
             msg: msg.into(),
@@ -147,25 +732,45 @@ impl std::fmt::Display for ParseError {
 
 impl std::error::Error for ParseError {}
 
-fn meaningful_lines(src: &str) -> impl Iterator<Item = (usize, &str)> {
+/* start of a `//`, `#` or `--` comment outside of a quoted value */
+fn comment_start(line: &str) -> Option<usize> {
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (pos, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            _ if quoted => {}
+            '#' => return Some(pos),
+            '/' | '-' if line[pos + 1..].starts_with(c) => return Some(pos),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+pub(crate) fn meaningful_lines(src: &str) -> impl Iterator<Item = (usize, &str)> {
     src.lines()
         .enumerate()
         .map(|(i, line)| {
-            let stripped = line
-                .find("//")
-                .or_else(|| line.find('#'))
-                .or_else(|| line.find("--"))
-                .map(|pos| &line[..pos])
-                .unwrap_or(line);
+            let stripped = comment_start(line).map(|pos| &line[..pos]).unwrap_or(line);
             (i + 1, stripped.trim())
         })
         .filter(|(_, l)| !l.is_empty())
 }
 
-fn unquote(s: &str, line: usize) -> Result<&str, ParseError> {
+/* a password doesn't go into an error message, even a mistyped one */
+fn is_secret(line_str: &str) -> bool {
+    line_str.trim_start().starts_with("pswd")
+}
+
+fn unquote(s: &str, line: usize) -> Result<String, ParseError> {
     let s = s.trim();
     if s.starts_with('"') && s.ends_with('"') && s.len() >= 2 {
-        Ok(&s[1..s.len() - 1])
+        interpolate(&unescape(&s[1..s.len() - 1]), line)
     } else {
         Err(ParseError::new(
             line,
@@ -174,21 +779,153 @@ fn unquote(s: &str, line: usize) -> Result<&str, ParseError> {
     }
 }
 
-fn split_kv(line_str: &str, line: usize) -> Result<(&str, &str), ParseError> {
-    let (key, rest) = line_str
-        .split_once(':')
-        .ok_or_else(|| ParseError::new(line, format!("expected `key: value`, got `{line_str}`")))?;
+/* `s` as a quoted value, `"` and `\` are escaped with a `\` */
+pub(crate) fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/* `\"` → `"` and `\\` → `\`, any other `\` stays as it is (`C:\certs`) */
+pub(crate) fn unescape(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(&next @ ('"' | '\\'))) => {
+                out.push(next);
+                chars.next();
+            }
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
+
+/* `${VAR}` → the value of the environment variable `VAR`, `$${` is a plain `${` */
+pub(crate) fn interpolate(s: &str, line: usize) -> Result<String, ParseError> {
+    expand(s, line, |name| {
+        std::env::var(name)
+            .map_err(|_| ParseError::new(line, format!("environment variable `{name}` isn't set")))
+    })
+}
+
+/*
+    A config that came with a project doesn't read the environment unless
+    `--trust-secrets`, `None` for a value with `${VAR}` in it, the value is left out
+*/
+pub(crate) fn resolve(s: &str, line: usize, trusted: bool) -> Result<Option<String>, ParseError> {
+    if trusted {
+        return interpolate(s, line).map(Some);
+    }
+
+    let reads_env = std::cell::Cell::new(false);
+    let value = expand(s, line, |_| {
+        reads_env.set(true);
+        Ok(String::new())
+    })?;
+
+    Ok((!reads_env.get()).then_some(value))
+}
+
+fn expand(
+    s: &str,
+    line: usize,
+    lookup: impl Fn(&str) -> Result<String, ParseError>,
+) -> Result<String, ParseError> {
+    let mut out = String::new();
+    let mut rest = s;
+
+    while let Some(start) = rest.find("${") {
+        if let Some(before) = rest[..start].strip_suffix('$') {
+            out.push_str(before);
+            out.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| ParseError::new(line, "`${` without a closing `}`"))?;
+
+        out.push_str(&lookup(&after[..end])?);
+        rest = &after[end + 1..];
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+pub(crate) fn split_kv(line_str: &str, line: usize) -> Result<(&str, &str), ParseError> {
+    let (key, rest) = line_str.split_once(':').ok_or_else(|| {
+        if is_secret(line_str) {
+            ParseError::new(line, "expected `key: value`")
+        } else {
+            ParseError::new(line, format!("expected `key: value`, got `{line_str}`"))
+        }
+    })?;
     Ok((key.trim(), rest.trim().trim_end_matches(',')))
 }
 
 pub fn parse_config(src: &str) -> Result<CqllsConfig, ParseError> {
-    let mut cfg = CqllsConfig::default();
-    let lines: Vec<(usize, &str)> = meaningful_lines(src).collect();
+    parse_config_onto(CqllsConfig::default(), src)
+}
+
+/* keys not in `src` keep the value of `base` */
+pub fn parse_config_onto(base: CqllsConfig, src: &str) -> Result<CqllsConfig, ParseError> {
+    parse(base, src, None, true)
+}
+
+/*
+    The contents of the config at `path`, a relative `from_cqlshrc` is next to it.
+    `.toml` files are read as TOML, anything else as the `.cqlls` format.
+*/
+pub fn parse_config_file(src: &str, path: &Path) -> Result<CqllsConfig, ParseError> {
+    parse_file(src, path, true)
+}
+
+/* the config at `path` that came with a project, see `resolve` */
+pub fn parse_project_config_file(src: &str, path: &Path) -> Result<CqllsConfig, ParseError> {
+    parse_file(src, path, false)
+}
+
+fn parse_file(src: &str, path: &Path, trusted: bool) -> Result<CqllsConfig, ParseError> {
+    if is_toml(path) {
+        return parse_toml(src, path.parent(), trusted);
+    }
+
+    parse(CqllsConfig::default(), src, path.parent(), trusted)
+}
+
+fn parse(
+    base: CqllsConfig,
+    src: &str,
+    dir: Option<&Path>,
+    trusted: bool,
+) -> Result<CqllsConfig, ParseError> {
+    let mut cfg = base;
+    let mut lines: Vec<(usize, &str)> = meaningful_lines(src).collect();
+
+    /* unquoting doesn't make a `${`, the lines can be checked as they are */
+    if !trusted {
+        lines = without_unresolved(&mut cfg, lines)?;
+    }
     let mut i = 0;
+    /* line, name and first line of every `db "name"` block */
+    let mut profiles: Vec<(usize, String, usize)> = vec![];
+    let mut default_profile: Option<(usize, String)> = None;
 
     while i < lines.len() {
         let (ln, text) = lines[i];
 
+        if let Ok(("default_profile", val)) = split_kv(text, ln) {
+            default_profile = Some((ln, unquote(val, ln)?));
+            i += 1;
+            continue;
+        }
+
         let block_name = text.trim_end_matches('{').trim();
 
         if !text.ends_with('{') {
@@ -201,7 +938,16 @@ pub fn parse_config(src: &str) -> Result<CqllsConfig, ParseError> {
         i += 1;
 
         match block_name {
-            "db" => {
+            "db" => parse_db_block(&mut cfg, &lines, &mut i, dir, trusted)?,
+
+            /* `db "staging" {`, read once the whole file is, on top of the plain `db` */
+            name if name.starts_with("db ") => {
+                let profile = unquote(&name[3..], ln)?;
+                profiles.push((ln, profile, i));
+                skip_block(&lines, &mut i);
+            }
+
+            "fmt" => {
                 while i < lines.len() {
                     let (ln, text) = lines[i];
                     if text == "}" {
@@ -209,70 +955,23 @@ pub fn parse_config(src: &str) -> Result<CqllsConfig, ParseError> {
                         break;
                     }
 
-                    if text.starts_with("known_nodes") {
-                        cfg.known_nodes.clear();
+                    if text.starts_with("udf_formatters") {
+                        cfg.udf_formatters.clear();
                         i += 1;
                         while i < lines.len() {
                             let (ln, inner) = lines[i];
-                            let inner = inner.trim_end_matches(',');
                             if inner == "}" || inner == "}," {
                                 i += 1;
                                 break;
                             }
-                            let node = unquote(inner, ln)?;
-                            cfg.known_nodes.push(node.to_string());
+                            let (language, command) = split_kv(inner, ln)?;
+                            cfg.udf_formatters
+                                .insert(language.to_lowercase(), unquote(command, ln)?);
                             i += 1;
                         }
                         continue;
                     }
 
-                    let (key, val) = split_kv(text, ln)?;
-                    match key {
-                        "type" => {
-                            cfg.db_type = match unquote(val, ln)? {
-                                "datastax_hcd" => DbType::DataStaxHCD,
-                                "scylla" => DbType::Scylla,
-                                "dynamo" => DbType::Dynamo,
-                                other => {
-                                    return Err(ParseError::new(
-                                        ln,
-                                        format!("unknown db type `{other}`"),
-                                    ));
-                                }
-                            };
-                        }
-                        "preferred_dc" => cfg.preferred_dc = unquote(val, ln)?.to_string(),
-                        "tls" => {
-                            cfg.tls = match unquote(val, ln)? {
-                                "none" => TlsMode::None,
-                                "tls" => TlsMode::Tls,
-                                "mtls" => TlsMode::MTls,
-                                other => {
-                                    return Err(ParseError::new(
-                                        ln,
-                                        format!("unknown tls mode `{other}`"),
-                                    ));
-                                }
-                            };
-                        }
-                        "ca_cert" => cfg.ca_cert = unquote(val, ln)?.to_string(),
-                        "user" => cfg.user = unquote(val, ln)?.to_string(),
-                        "pswd" => cfg.pswd = unquote(val, ln)?.to_string(),
-                        other => {
-                            return Err(ParseError::new(ln, format!("unknown db key `{other}`")));
-                        }
-                    }
-                    i += 1;
-                }
-            }
-
-            "fmt" => {
-                while i < lines.len() {
-                    let (ln, text) = lines[i];
-                    if text == "}" {
-                        i += 1;
-                        break;
-                    }
                     let (key, val) = split_kv(text, ln)?;
                     match key {
                         "type_padding" => {
@@ -288,6 +987,24 @@ pub fn parse_config(src: &str) -> Result<CqllsConfig, ParseError> {
                                 ParseError::new(ln, format!("`indent` must be u8, got `{val}`"))
                             })?;
                         }
+                        "max_width" => {
+                            cfg.max_width = val.parse::<u16>().map_err(|_| {
+                                ParseError::new(ln, format!("`max_width` must be u16, got `{val}`"))
+                            })?;
+                        }
+                        "keyword_case" | "type_case" | "function_case" => {
+                            let case = Case::from_name(val.trim_matches('"')).ok_or_else(|| {
+                                ParseError::new(
+                                    ln,
+                                    format!("`{key}` must be upper/lower/preserve, got `{val}`"),
+                                )
+                            })?;
+                            match key {
+                                "keyword_case" => cfg.keyword_case = case,
+                                "type_case" => cfg.type_case = case,
+                                _ => cfg.function_case = case,
+                            }
+                        }
                         other => {
                             return Err(ParseError::new(ln, format!("unknown fmt key `{other}`")));
                         }
@@ -319,6 +1036,28 @@ pub fn parse_config(src: &str) -> Result<CqllsConfig, ParseError> {
                 }
             }
 
+            "lint" => {
+                while i < lines.len() {
+                    let (ln, text) = lines[i];
+                    if text == "}" {
+                        i += 1;
+                        break;
+                    }
+                    let (key, val) = split_kv(text, ln)?;
+                    if !LINT_RULES.iter().any(|r| r.id == key) {
+                        return Err(ParseError::new(ln, format!("unknown lint rule `{key}`")));
+                    }
+                    let level = LintLevel::from_name(val.trim_matches('"')).ok_or_else(|| {
+                        ParseError::new(
+                            ln,
+                            format!("lint level must be off/hint/warning/error, got `{val}`"),
+                        )
+                    })?;
+                    cfg.lint.insert(key.to_string(), level);
+                    i += 1;
+                }
+            }
+
             "debug" => {
                 while i < lines.len() {
                     let (ln, text) = lines[i];
@@ -355,5 +1094,399 @@ pub fn parse_config(src: &str) -> Result<CqllsConfig, ParseError> {
         }
     }
 
+    let mut defined = vec![];
+    for (ln, name, start) in profiles {
+        if defined.contains(&name) {
+            return Err(ParseError::new(
+                ln,
+                format!("profile `{name}` is defined twice"),
+            ));
+        }
+
+        let mut profile = cfg.clone();
+        let mut i = start;
+        parse_db_block(&mut profile, &lines, &mut i, dir, trusted)?;
+        cfg.profiles.insert(name.clone(), DbProfile::of(&profile));
+        cfg.left_out = profile.left_out;
+        defined.push(name);
+    }
+
+    if let Some((ln, name)) = default_profile {
+        if !cfg.use_profile(&name) {
+            return Err(ParseError::new(ln, format!("unknown profile `{name}`")));
+        }
+        cfg.default_profile = Some(name);
+    }
+
     Ok(cfg)
 }
+
+/*
+    `lines` without the values that read the environment, their keys go to
+    `left_out`. A value of `known_nodes` or `udf_formatters` is left out on
+    its own, the whole list when that was all of it. A block can't be.
+*/
+fn without_unresolved<'a>(
+    cfg: &mut CqllsConfig,
+    lines: Vec<(usize, &'a str)>,
+) -> Result<Vec<(usize, &'a str)>, ParseError> {
+    let mut kept = vec![];
+    let mut depth = 0usize;
+    /* the key of the list the line is in, `known_nodes {` in a block, and where it starts in `kept` */
+    let mut list: Option<(&str, usize)> = None;
+    let mut dropped_in_list = false;
+
+    for (ln, text) in lines {
+        if resolve(text, ln, false)?.is_none() {
+            if text.ends_with('{') {
+                return Err(ParseError::new(
+                    ln,
+                    "`${` isn't read in a project config, start cqlls with `--trust-secrets` to allow it",
+                ));
+            }
+            let key = match list {
+                Some((key, _)) => key,
+                None => split_kv(text, ln)?.0,
+            };
+            if !cfg.left_out.iter().any(|k| k == key) {
+                cfg.left_out.push(key.to_string());
+            }
+            dropped_in_list |= list.is_some();
+            continue;
+        }
+
+        if text.ends_with('{') {
+            depth += 1;
+            if depth == 2 {
+                let key = text.trim_end_matches('{').trim().trim_end_matches(':');
+                list = Some((key.trim(), kept.len()));
+                dropped_in_list = false;
+            }
+        } else if text.trim_end_matches(',') == "}" {
+            depth = depth.saturating_sub(1);
+            if let Some((_, start)) = list.filter(|_| depth < 2) {
+                list = None;
+                if dropped_in_list && kept.len() == start + 1 {
+                    kept.pop();
+                    continue;
+                }
+            }
+        }
+        kept.push((ln, text));
+    }
+
+    Ok(kept)
+}
+
+/* moves `i` past the `}` closing the block it's in, nested blocks included */
+fn skip_block(lines: &[(usize, &str)], i: &mut usize) {
+    let mut depth = 1;
+
+    while *i < lines.len() && depth > 0 {
+        let text = lines[*i].1.trim_end_matches(',');
+        if text.ends_with('{') {
+            depth += 1;
+        } else if text == "}" {
+            depth -= 1;
+        }
+        *i += 1;
+    }
+}
+
+/*
+    The cqlshrc `path` of `from_cqlshrc` names, a relative one is in `dir`.
+    The user's credentials aren't for a project to send somewhere, a config
+    that isn't trusted only reads the project's own: a relative path that
+    stays in `dir`. `None` when it doesn't.
+*/
+pub(crate) fn cqlshrc_path(path: &str, dir: Option<&Path>, trusted: bool) -> Option<PathBuf> {
+    let mut resolved = expand_home(path);
+    if let Some(dir) = dir.filter(|_| resolved.is_relative()) {
+        resolved = dir.join(resolved);
+    }
+    if trusted {
+        return Some(resolved);
+    }
+
+    let relative = Path::new(path).is_relative() && !path.starts_with('~');
+    let inside = |dir: &Path| match (resolved.canonicalize(), dir.canonicalize()) {
+        (Ok(real), Ok(dir)) => real.starts_with(dir),
+        /* it doesn't exist, reading it tells the user so */
+        _ => Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir)),
+    };
+
+    (relative && dir.is_some_and(inside)).then_some(resolved)
+}
+
+/* the keys of a `db` block, `i` is past its opening line and ends past its `}` */
+fn parse_db_block(
+    cfg: &mut CqllsConfig,
+    lines: &[(usize, &str)],
+    i: &mut usize,
+    dir: Option<&Path>,
+    trusted: bool,
+) -> Result<(), ParseError> {
+    /* the block wins over its cqlshrc, wherever `from_cqlshrc` is in it */
+    let mut end = *i;
+    skip_block(lines, &mut end);
+    for &(ln, text) in &lines[*i..end] {
+        if let Ok(("from_cqlshrc", val)) = split_kv(text, ln) {
+            let path = unquote(val, ln)?;
+            let Some(resolved) = cqlshrc_path(&path, dir, trusted) else {
+                if !cfg.left_out.iter().any(|k| k == "from_cqlshrc") {
+                    cfg.left_out.push("from_cqlshrc".to_string());
+                }
+                continue;
+            };
+
+            let cqlshrc = Cqlshrc::read(&resolved).map_err(|e| {
+                ParseError::new(ln, format!("can't read `{}`: {e}", resolved.display()))
+            })?;
+            cqlshrc.apply(cfg);
+            cfg.from_cqlshrc = path;
+        }
+    }
+
+    while *i < lines.len() {
+        let (ln, text) = lines[*i];
+        if text == "}" {
+            *i += 1;
+            break;
+        }
+
+        if text.starts_with("known_nodes") {
+            cfg.known_nodes.clear();
+            *i += 1;
+            while *i < lines.len() {
+                let (ln, inner) = lines[*i];
+                let inner = inner.trim_end_matches(',');
+                if inner == "}" || inner == "}," {
+                    *i += 1;
+                    break;
+                }
+                let node = unquote(inner, ln)?;
+                cfg.known_nodes.push(node);
+                *i += 1;
+            }
+            continue;
+        }
+
+        let (key, val) = split_kv(text, ln)?;
+        match key {
+            "type" => {
+                let name = unquote(val, ln)?;
+                cfg.db_type = DbType::from_name(&name)
+                    .ok_or_else(|| ParseError::new(ln, format!("unknown db type `{name}`")))?;
+            }
+            "preferred_dc" => cfg.preferred_dc = unquote(val, ln)?,
+            "tls" => {
+                let name = unquote(val, ln)?;
+                cfg.tls = TlsMode::from_name(&name)
+                    .ok_or_else(|| ParseError::new(ln, format!("unknown tls mode `{name}`")))?;
+            }
+            "ca_cert" => cfg.ca_cert = unquote(val, ln)?,
+            "client_cert" => cfg.client_cert = unquote(val, ln)?,
+            "client_key" => cfg.client_key = unquote(val, ln)?,
+            /* read before the rest of the block */
+            "from_cqlshrc" => {}
+            "user" => cfg.user = unquote(val, ln)?,
+            "pswd" => {
+                /* `${VAR}` errors only name the variable, the rest would show the value */
+                let quoted = val.len() >= 2 && val.starts_with('"') && val.ends_with('"');
+                cfg.pswd = unquote(val, ln).map_err(|e| match quoted {
+                    true => e,
+                    false => ParseError::new(ln, "`pswd` must be a quoted string"),
+                })?;
+            }
+            "pswd_env" => cfg.pswd_env = unquote(val, ln)?,
+            "pswd_file" => cfg.pswd_file = unquote(val, ln)?,
+            "pswd_command" => cfg.pswd_command = unquote(val, ln)?,
+            other => {
+                return Err(ParseError::new(ln, format!("unknown db key `{other}`")));
+            }
+        }
+        *i += 1;
+    }
+
+    Ok(())
+}
+
+/*
+    Settings from the editor are the blocks of `.cqlls` as a JSON object,
+    `{ "db": { "known_nodes": ["10.0.0.1:9042"] }, "fmt": { "indent": 2 } }`,
+    named profiles as `"db \"staging\"": { ... }`.
+    They're written out as `.cqlls` text so the same rules apply.
+*/
+pub fn apply_settings(base: CqllsConfig, settings: &Value) -> Result<CqllsConfig, String> {
+    settings_onto(base, settings, None, true).map_err(|e| e.to_string())
+}
+
+/*
+    Settings that may come with the project (a folder's `.vscode/settings.json`)
+    or from whoever connected to the server. What runs a command is left out,
+    what reads a secret of the machine too and what uses `${VAR}` unless
+    `secrets`, the keys left out come with the config.
+*/
+pub fn apply_untrusted_settings(
+    base: CqllsConfig,
+    settings: &Value,
+    secrets: bool,
+) -> Result<(CqllsConfig, Vec<String>), String> {
+    let mut settings = settings.clone();
+    let mut removed = BTreeSet::new();
+    let db_keys: Vec<&'static str> = match secrets {
+        true => COMMAND_DB_KEYS.to_vec(),
+        false => [COMMAND_DB_KEYS.as_slice(), &SECRET_DB_KEYS].concat(),
+    };
+    if let Value::Object(blocks) = &mut settings {
+        for (block, entries) in blocks.iter_mut() {
+            let Value::Object(entries) = entries else {
+                continue;
+            };
+            let keys: &[&'static str] = match block.as_str() {
+                "fmt" => &["udf_formatters"],
+                "db" => &db_keys,
+                profile if profile.starts_with("db ") => &db_keys,
+                _ => &[],
+            };
+            for key in keys {
+                if entries.remove(*key).is_some() {
+                    removed.insert(*key);
+                }
+            }
+        }
+    }
+
+    let mut config = settings_onto(base, &settings, None, secrets).map_err(|e| e.to_string())?;
+    let removed = |keys: &[&str]| -> Vec<String> {
+        keys.iter()
+            .filter(|key| removed.contains(*key))
+            .map(|key| key.to_string())
+            .collect()
+    };
+    let dropped = [
+        removed(&[COMMAND_DB_KEYS.as_slice(), &SECRET_DB_KEYS].concat()),
+        std::mem::take(&mut config.left_out),
+        removed(&["udf_formatters"]),
+    ]
+    .concat();
+
+    Ok((config, dropped))
+}
+
+/* the `db` keys a config that isn't trusted doesn't get, see `drop_commands` and `drop_secrets` */
+const COMMAND_DB_KEYS: [&str; 1] = ["pswd_command"];
+const SECRET_DB_KEYS: [&str; 1] = ["pswd_file"];
+
+/* a setting that can't be used, `key` is its path like `fmt.indent` */
+#[derive(Debug)]
+pub(crate) struct SettingError {
+    pub key: String,
+    pub msg: String,
+}
+
+impl std::fmt::Display for SettingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.key.is_empty() {
+            true => write!(f, "{}", self.msg),
+            false => write!(f, "`{}`: {}", self.key, self.msg),
+        }
+    }
+}
+
+/* `settings` on top of `base`, a relative `from_cqlshrc` is in `dir`, `trusted` as for `parse_file` */
+pub(crate) fn settings_onto(
+    base: CqllsConfig,
+    settings: &Value,
+    dir: Option<&Path>,
+    trusted: bool,
+) -> Result<CqllsConfig, SettingError> {
+    let (src, keys) = settings_source(settings)?;
+
+    parse(base, &src, dir, trusted).map_err(|e| SettingError {
+        key: keys
+            .get(e.line.saturating_sub(1))
+            .cloned()
+            .unwrap_or_default(),
+        msg: e.msg,
+    })
+}
+
+/* the `.cqlls` text of `settings` and the setting each of its lines comes from */
+fn settings_source(settings: &Value) -> Result<(String, Vec<String>), SettingError> {
+    let Value::Object(blocks) = settings else {
+        return Err(SettingError {
+            key: String::new(),
+            msg: format!("settings must be an object, got `{settings}`"),
+        });
+    };
+
+    let mut src = String::new();
+    let mut keys: Vec<String> = vec![];
+    let mut line = |text: String, key: &str| {
+        src.push_str(&text);
+        src.push('\n');
+        keys.push(key.to_string());
+    };
+
+    for (block, entries) in blocks {
+        /* `default_profile`, the one key outside of a block */
+        if let Value::String(_) = entries {
+            line(
+                format!("{block}: {}", setting_value(entries, block)?),
+                block,
+            );
+            continue;
+        }
+
+        let Value::Object(entries) = entries else {
+            return Err(SettingError {
+                key: String::new(),
+                msg: format!("`{block}` must be an object, got `{entries}`"),
+            });
+        };
+
+        line(format!("{block} {{"), block);
+        for (key, value) in entries {
+            let path = format!("{block}.{key}");
+            match value {
+                Value::Array(items) => {
+                    line(format!("{key} {{"), &path);
+                    for item in items {
+                        line(setting_value(item, &path)?, &path);
+                    }
+                    line("}".to_string(), &path);
+                }
+                Value::Object(map) => {
+                    line(format!("{key} {{"), &path);
+                    for (inner, value) in map {
+                        let path = format!("{path}.{inner}");
+                        line(format!("{inner}: {}", setting_value(value, &path)?), &path);
+                    }
+                    line("}".to_string(), &path);
+                }
+                value => line(format!("{key}: {}", setting_value(value, &path)?), &path),
+            }
+        }
+        line("}".to_string(), block);
+    }
+
+    Ok((src, keys))
+}
+
+fn setting_value(value: &Value, path: &str) -> Result<String, SettingError> {
+    let msg = match value {
+        Value::String(s) if !s.contains('\n') => return Ok(quote(s)),
+        Value::Number(n) => return Ok(n.to_string()),
+        Value::Bool(b) => return Ok(b.to_string()),
+        _ if is_secret(path.rsplit('.').next().unwrap_or(path)) => "unsupported value".to_string(),
+        other => format!("unsupported value `{other}`"),
+    };
+
+    Err(SettingError {
+        key: path.to_string(),
+        msg,
+    })
+}
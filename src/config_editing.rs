@@ -0,0 +1,307 @@
+/*
+    Copyright (c) 2026 アクゼスティア. All Rights Reserved.
+*/
+
+/*
+    Completion and hover inside the config itself, `.cqlls` and `.cqlls.toml`.
+
+    Where the cursor is comes from the lines above it, so both work while
+    the config is half written and doesn't parse.
+*/
+
+use tower_lsp::lsp_types::*;
+
+use crate::config::{
+    CONFIG_BLOCKS, ConfigKey, DEFAULT_PROFILE_KEY, ValueKind, config_keys, meaningful_lines,
+};
+use crate::lsp::Backend;
+
+#[derive(Debug, PartialEq)]
+enum Place {
+    /* a key of the block, `""` is outside of every block */
+    Key(String),
+    /* the value of a key of the block */
+    Value(String, String),
+    /* in `known_nodes` or `udf_formatters` */
+    Nested,
+}
+
+/* `db "staging"` and `profiles.staging` are `db` blocks */
+fn block_of(name: &str) -> &str {
+    match name {
+        _ if name.starts_with("db ") || name.starts_with("profiles.") => "db",
+        _ => name,
+    }
+}
+
+fn cqlls_place(text: &str, line: usize, prefix: &str) -> Place {
+    let mut blocks: Vec<&str> = vec![];
+    for (_, l) in meaningful_lines(text).take_while(|(ln, _)| *ln <= line) {
+        let l = l.trim_end_matches(',');
+        if let Some(open) = l.strip_suffix('{') {
+            blocks.push(block_of(open.trim()));
+        } else if l == "}" {
+            blocks.pop();
+        }
+    }
+
+    let block = match blocks.as_slice() {
+        [] => "",
+        [block] => block,
+        _ => return Place::Nested,
+    };
+    match prefix.split_once(':') {
+        Some((key, _)) => Place::Value(block.to_string(), key.trim().to_string()),
+        None => Place::Key(block.to_string()),
+    }
+}
+
+fn toml_place(text: &str, line: usize, prefix: &str) -> Place {
+    let header = text
+        .lines()
+        .take(line)
+        .map(str::trim)
+        .filter(|l| l.starts_with('['))
+        .last();
+
+    let block = match header.map(|h| h.trim_matches(['[', ']']).trim()) {
+        /* a header being written */
+        _ if prefix.starts_with('[') => "",
+        None => "",
+        Some(name) if name.starts_with("profiles.") => "db",
+        Some(name) if name.contains('.') => return Place::Nested,
+        Some(name) => name,
+    };
+    match prefix.split_once('=') {
+        Some((key, _)) => Place::Value(block.to_string(), key.trim().to_string()),
+        None => Place::Key(block.to_string()),
+    }
+}
+
+/* where `position` is, and the text of its line before it */
+fn place(text: &str, position: Position, toml: bool) -> (Place, &str) {
+    let line = text.lines().nth(position.line as usize).unwrap_or("");
+    let prefix = &line[..byte_offset(line, position.character)];
+
+    let place = match toml {
+        true => toml_place(text, position.line as usize, prefix.trim_start()),
+        false => cqlls_place(text, position.line as usize, prefix.trim_start()),
+    };
+    (place, prefix)
+}
+
+/* the byte offset of a UTF-16 `character` of the line */
+fn byte_offset(line: &str, character: u32) -> usize {
+    let mut units = 0;
+    for (offset, c) in line.char_indices() {
+        if units >= character as usize {
+            return offset;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+fn find_key(block: &str, key: &str) -> Option<ConfigKey> {
+    match block {
+        "" => (key == DEFAULT_PROFILE_KEY.name).then_some(DEFAULT_PROFILE_KEY),
+        block => config_keys(block).into_iter().find(|k| k.name == key),
+    }
+}
+
+fn kind_detail(kind: ValueKind) -> String {
+    match kind {
+        ValueKind::Text => "string".to_string(),
+        ValueKind::Number => "number".to_string(),
+        ValueKind::Bool => "true | false".to_string(),
+        ValueKind::Choice(values) => values.join(" | "),
+        ValueKind::List => "list of strings".to_string(),
+        ValueKind::Map => "map of strings".to_string(),
+    }
+}
+
+fn key_item(key: &ConfigKey, toml: bool) -> CompletionItem {
+    let name = key.name;
+    let insert = match (key.kind, toml) {
+        (ValueKind::List, false) | (ValueKind::Map, false) => format!("{name} {{\n    $0\n}}"),
+        (ValueKind::List, true) => format!("{name} = [$0]"),
+        (ValueKind::Map, true) => format!("{name} = {{ $0 }}"),
+        (ValueKind::Text | ValueKind::Choice(_), false) => format!("{name}: \"$0\""),
+        (ValueKind::Text | ValueKind::Choice(_), true) => format!("{name} = \"$0\""),
+        (_, false) => format!("{name}: $0"),
+        (_, true) => format!("{name} = $0"),
+    };
+
+    CompletionItem {
+        label: name.to_string(),
+        kind: Some(CompletionItemKind::PROPERTY),
+        detail: Some(kind_detail(key.kind)),
+        documentation: Some(Documentation::String(key.doc.to_string())),
+        insert_text: Some(insert),
+        insert_text_format: Some(InsertTextFormat::SNIPPET),
+        ..Default::default()
+    }
+}
+
+fn block_items(prefix: &str, toml: bool) -> Vec<CompletionItem> {
+    let bracket = prefix.trim_start().starts_with('[');
+    let mut items: Vec<CompletionItem> = CONFIG_BLOCKS
+        .iter()
+        .map(|&(name, doc)| {
+            let insert = match (toml, bracket) {
+                (false, _) => format!("{name} {{\n    $0\n}}"),
+                (true, false) => format!("[{name}]\n$0"),
+                (true, true) => format!("{name}]\n$0"),
+            };
+            CompletionItem {
+                label: name.to_string(),
+                kind: Some(CompletionItemKind::MODULE),
+                documentation: Some(Documentation::String(doc.to_string())),
+                insert_text: Some(insert),
+                insert_text_format: Some(InsertTextFormat::SNIPPET),
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    let profile = match (toml, bracket) {
+        (false, _) => "db \"$1\" {\n    $0\n}",
+        (true, false) => "[profiles.$1]\n$0",
+        (true, true) => "profiles.$1]\n$0",
+    };
+    items.push(CompletionItem {
+        label: if toml { "profiles" } else { "db \"name\"" }.to_string(),
+        kind: Some(CompletionItemKind::MODULE),
+        documentation: Some(Documentation::String(
+            "A named profile, it starts from the plain `db` block".to_string(),
+        )),
+        insert_text: Some(profile.to_string()),
+        insert_text_format: Some(InsertTextFormat::SNIPPET),
+        ..Default::default()
+    });
+
+    if !bracket {
+        items.push(key_item(&DEFAULT_PROFILE_KEY, toml));
+    }
+    items
+}
+
+/* the names of the profiles the config defines */
+fn profile_names(text: &str, toml: bool) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter_map(|l| match toml {
+            true => l.strip_prefix("[profiles.")?.strip_suffix(']'),
+            false => l.strip_prefix("db ")?.strip_suffix('{'),
+        })
+        .map(|name| name.trim().trim_matches('"').to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+fn value_items(key: &ConfigKey, text: &str, prefix: &str, toml: bool) -> Vec<CompletionItem> {
+    /* the opening quote might be typed already */
+    let quoted = prefix.rsplit([':', '=']).next().unwrap_or("").contains('"');
+    let quote = |value: &str| match quoted {
+        true => value.to_string(),
+        false => format!("\"{value}\""),
+    };
+
+    let values: Vec<(String, String)> = match key.kind {
+        ValueKind::Choice(values) => values.iter().map(|v| (v.to_string(), quote(v))).collect(),
+        ValueKind::Bool => ["true", "false"]
+            .iter()
+            .map(|v| (v.to_string(), v.to_string()))
+            .collect(),
+        _ if key.name == DEFAULT_PROFILE_KEY.name => profile_names(text, toml)
+            .into_iter()
+            .map(|name| (name.clone(), quote(&name)))
+            .collect(),
+        _ => vec![],
+    };
+
+    values
+        .into_iter()
+        .map(|(label, insert)| CompletionItem {
+            label,
+            kind: Some(CompletionItemKind::ENUM_MEMBER),
+            detail: Some(key.name.to_string()),
+            insert_text: Some(insert),
+            ..Default::default()
+        })
+        .collect()
+}
+
+impl Backend {
+    pub fn config_completions(text: &str, position: Position, toml: bool) -> Vec<CompletionItem> {
+        let (place, prefix) = place(text, position, toml);
+
+        match place {
+            Place::Key(block) if block.is_empty() => block_items(prefix, toml),
+            Place::Key(block) => config_keys(&block)
+                .iter()
+                .map(|key| key_item(key, toml))
+                .collect(),
+            Place::Value(block, key) => find_key(&block, &key)
+                .map(|key| value_items(&key, text, prefix, toml))
+                .unwrap_or_default(),
+            Place::Nested => vec![],
+        }
+    }
+
+    /* the documentation of the block or key under the cursor */
+    pub fn config_hover(text: &str, position: Position, toml: bool) -> Option<Hover> {
+        let line = text.lines().nth(position.line as usize)?;
+        let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+
+        let at = byte_offset(line, position.character);
+        let start = line[..at].rfind(|c| !is_word(c)).map_or(0, |i| i + 1);
+        let end = line[at..]
+            .find(|c| !is_word(c))
+            .map_or(line.len(), |i| at + i);
+        let word = &line[start..end];
+        if word.is_empty() {
+            return None;
+        }
+
+        let word_start = Position::new(position.line, line[..start].encode_utf16().count() as u32);
+        let (place, _) = place(text, word_start, toml);
+
+        let contents =
+            match place {
+                Place::Key(block) if block.is_empty() => {
+                    match CONFIG_BLOCKS.iter().find(|(name, _)| *name == block_of(word)) {
+                    Some((name, doc)) => format!("`{name}`\n\n{doc}"),
+                    None if toml && word == "profiles" => {
+                        "`profiles`\n\nNamed `db` profiles, each starts from the plain `db` table"
+                            .to_string()
+                    }
+                    None => key_docs("", &find_key("", word)?),
+                }
+                }
+                Place::Key(block) => key_docs(&block, &find_key(&block, word)?),
+                Place::Value(block, key) => key_docs(&block, &find_key(&block, &key)?),
+                Place::Nested => return None,
+            };
+
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: contents,
+            }),
+            range: Some(Range::new(
+                word_start,
+                Position::new(position.line, line[..end].encode_utf16().count() as u32),
+            )),
+        })
+    }
+}
+
+fn key_docs(block: &str, key: &ConfigKey) -> String {
+    let path = match block {
+        "" => key.name.to_string(),
+        block => format!("{block}.{}", key.name),
+    };
+
+    format!("`{path}`: {}\n\n{}", kind_detail(key.kind), key.doc)
+}
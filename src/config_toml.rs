@@ -0,0 +1,518 @@
+/*
+    Copyright (c) 2026 アクゼスティア. All Rights Reserved.
+*/
+
+/*
+    `.cqlls.toml`, the blocks of `.cqlls` as TOML tables:
+
+        default_profile = "staging"
+
+        [db]
+        type = "scylla"
+        known_nodes = ["127.0.0.1:9042"]
+        pswd_env = "CQLLS_PASSWORD"
+
+        [profiles.staging]
+        known_nodes = ["10.0.1.1:9042"]
+
+        [fmt]
+        indent = 4
+        keyword_case = "upper"
+        udf_formatters = { lua = "stylua -" }
+
+        [features]
+        diagnostics = true
+
+        [lint]
+        select-star = "error"
+
+        [debug]
+        logging = false
+
+    The tables are deserialized as they are, strings are TOML strings and
+    `${VAR}` is the only thing resolved in them (`$${` for a plain `${`).
+    Keys and values are checked with the messages of `.cqlls`.
+*/
+
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::path::Path;
+use toml::Spanned;
+use toml::de::{DeTable, DeValue};
+
+use crate::config::{
+    CONFIG_BLOCKS, Case, CqllsConfig, DbProfile, DbType, ParseError, TlsMode, config_keys,
+    cqlshrc_path, meaningful_lines, resolve, split_kv, unescape,
+};
+use crate::cqlshrc::Cqlshrc;
+use crate::lint::{LINT_RULES, LintLevel};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlConfig {
+    default_profile: Option<Spanned<String>>,
+    db: Option<TomlDb>,
+    #[serde(default)]
+    profiles: BTreeMap<String, TomlDb>,
+    fmt: Option<TomlFmt>,
+    features: Option<BTreeMap<String, bool>>,
+    #[serde(default)]
+    lint: BTreeMap<Spanned<String>, Spanned<String>>,
+    debug: Option<TomlDebug>,
+}
+
+/* `[db]` and every `[profiles.name]` */
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlDb {
+    #[serde(rename = "type")]
+    db_type: Option<Spanned<String>>,
+    preferred_dc: Option<Spanned<String>>,
+    known_nodes: Option<Vec<Spanned<String>>>,
+    tls: Option<Spanned<String>>,
+    ca_cert: Option<Spanned<String>>,
+    client_cert: Option<Spanned<String>>,
+    client_key: Option<Spanned<String>>,
+    from_cqlshrc: Option<Spanned<String>>,
+    user: Option<Spanned<String>>,
+    /* any value, so a password that isn't a string doesn't end up in the error */
+    pswd: Option<Spanned<toml::Value>>,
+    pswd_env: Option<Spanned<String>>,
+    pswd_file: Option<Spanned<String>>,
+    pswd_command: Option<Spanned<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlFmt {
+    type_padding: Option<Spanned<i64>>,
+    indent: Option<Spanned<i64>>,
+    max_width: Option<Spanned<i64>>,
+    keyword_case: Option<Spanned<String>>,
+    type_case: Option<Spanned<String>>,
+    function_case: Option<Spanned<String>>,
+    udf_formatters: Option<BTreeMap<String, Spanned<String>>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlDebug {
+    logging: Option<bool>,
+}
+
+pub fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "toml")
+}
+
+/* a relative `from_cqlshrc` is in `dir` */
+pub fn parse_toml_config(src: &str, dir: Option<&Path>) -> Result<CqllsConfig, ParseError> {
+    parse_toml(src, dir, true)
+}
+
+/* `trusted` is `false` for a config that may not read secrets, see `resolve` */
+pub(crate) fn parse_toml(
+    src: &str,
+    dir: Option<&Path>,
+    trusted: bool,
+) -> Result<CqllsConfig, ParseError> {
+    check_keys(src)?;
+
+    let toml: TomlConfig = toml::from_str(src).map_err(|e| {
+        ParseError::new(
+            line_of(src, e.span().map_or(0, |span| span.start)),
+            e.message(),
+        )
+    })?;
+
+    let mut cfg = CqllsConfig::default();
+    let error = |span: Range<usize>, msg: String| ParseError::new(line_of(src, span.start), msg);
+    /* keys left out as the config isn't trusted with them, see `CqllsConfig::left_out` */
+    let mut left_out = vec![];
+
+    if let Some(db) = &toml.db {
+        apply_db(&mut cfg, db, src, dir, trusted, &mut left_out)?;
+    }
+
+    for (name, db) in &toml.profiles {
+        let mut profile = cfg.clone();
+        apply_db(&mut profile, db, src, dir, trusted, &mut left_out)?;
+        cfg.profiles.insert(name.clone(), DbProfile::of(&profile));
+    }
+
+    let mut string = |key: &'static str, value: &Spanned<String>| {
+        let resolved = resolve(value.get_ref(), line_of(src, value.span().start), trusted)?;
+        if resolved.is_none() && !left_out.contains(&key) {
+            left_out.push(key);
+        }
+        Ok::<_, ParseError>(resolved)
+    };
+
+    if let Some(fmt) = &toml.fmt {
+        let number = |value: &Spanned<i64>, key: &str, max: i64, kind: &str| {
+            let n = *value.get_ref();
+            match (0..=max).contains(&n) {
+                true => Ok(n),
+                false => Err(error(
+                    value.span(),
+                    format!("`{key}` must be {kind}, got `{n}`"),
+                )),
+            }
+        };
+        if let Some(value) = &fmt.type_padding {
+            cfg.type_padding = number(value, "type_padding", u8::MAX.into(), "u8")? as u8;
+        }
+        if let Some(value) = &fmt.indent {
+            cfg.indent = number(value, "indent", u8::MAX.into(), "u8")? as u8;
+        }
+        if let Some(value) = &fmt.max_width {
+            cfg.max_width = number(value, "max_width", u16::MAX.into(), "u16")? as u16;
+        }
+
+        let case = |value: &Spanned<String>, key: &str| {
+            Case::from_name(value.get_ref()).ok_or_else(|| {
+                error(
+                    value.span(),
+                    format!(
+                        "`{key}` must be upper/lower/preserve, got `{}`",
+                        value.get_ref()
+                    ),
+                )
+            })
+        };
+        if let Some(value) = &fmt.keyword_case {
+            cfg.keyword_case = case(value, "keyword_case")?;
+        }
+        if let Some(value) = &fmt.type_case {
+            cfg.type_case = case(value, "type_case")?;
+        }
+        if let Some(value) = &fmt.function_case {
+            cfg.function_case = case(value, "function_case")?;
+        }
+
+        if let Some(formatters) = &fmt.udf_formatters {
+            cfg.udf_formatters = HashMap::new();
+            for (language, command) in formatters {
+                if let Some(command) = string("udf_formatters", command)? {
+                    cfg.udf_formatters.insert(language.to_lowercase(), command);
+                }
+            }
+        }
+    }
+
+    if let Some(features) = &toml.features {
+        cfg.features = features
+            .iter()
+            .filter(|(_, enabled)| **enabled)
+            .map(|(feature, _)| feature.clone())
+            .collect();
+    }
+
+    for (rule, level) in &toml.lint {
+        if !LINT_RULES.iter().any(|r| r.id == rule.get_ref()) {
+            return Err(error(
+                rule.span(),
+                format!("unknown lint rule `{}`", rule.get_ref()),
+            ));
+        }
+        let parsed = LintLevel::from_name(level.get_ref()).ok_or_else(|| {
+            error(
+                level.span(),
+                format!(
+                    "lint level must be off/hint/warning/error, got `{}`",
+                    level.get_ref()
+                ),
+            )
+        })?;
+        cfg.lint.insert(rule.get_ref().clone(), parsed);
+    }
+
+    if let Some(logging) = toml.debug.and_then(|debug| debug.logging) {
+        cfg.logging = logging;
+    }
+
+    if let Some(name) = &toml.default_profile
+        && let Some(profile) = string("default_profile", name)?
+    {
+        if !cfg.use_profile(&profile) {
+            return Err(error(name.span(), format!("unknown profile `{profile}`")));
+        }
+        cfg.default_profile = Some(profile);
+    }
+
+    cfg.left_out = left_out.into_iter().map(String::from).collect();
+
+    Ok(cfg)
+}
+
+/* the settings of `[db]` or a profile onto `cfg`, its cqlshrc first, see `parse_toml` for `left_out` */
+fn apply_db(
+    cfg: &mut CqllsConfig,
+    db: &TomlDb,
+    src: &str,
+    dir: Option<&Path>,
+    trusted: bool,
+    left_out: &mut Vec<&'static str>,
+) -> Result<(), ParseError> {
+    let line = |span: Range<usize>| line_of(src, span.start);
+    let mut resolved = |key: &'static str, value: &str, span: Range<usize>| {
+        let resolved = resolve(value, line(span), trusted)?;
+        if resolved.is_none() && !left_out.contains(&key) {
+            left_out.push(key);
+        }
+        Ok::<_, ParseError>(resolved)
+    };
+
+    let mut outside = false;
+    if let Some(value) = &db.from_cqlshrc
+        && let Some(path) = resolved("from_cqlshrc", value.get_ref(), value.span())?
+    {
+        match cqlshrc_path(&path, dir, trusted) {
+            Some(resolved) => {
+                let cqlshrc = Cqlshrc::read(&resolved).map_err(|e| {
+                    ParseError::new(
+                        line(value.span()),
+                        format!("can't read `{}`: {e}", resolved.display()),
+                    )
+                })?;
+                cqlshrc.apply(cfg);
+                cfg.from_cqlshrc = path;
+            }
+            None => outside = true,
+        }
+    }
+
+    if let Some(value) = &db.db_type
+        && let Some(name) = resolved("type", value.get_ref(), value.span())?
+    {
+        cfg.db_type = DbType::from_name(&name).ok_or_else(|| {
+            ParseError::new(line(value.span()), format!("unknown db type `{name}`"))
+        })?;
+    }
+    if let Some(value) = &db.tls
+        && let Some(name) = resolved("tls", value.get_ref(), value.span())?
+    {
+        cfg.tls = TlsMode::from_name(&name).ok_or_else(|| {
+            ParseError::new(line(value.span()), format!("unknown tls mode `{name}`"))
+        })?;
+    }
+    if let Some(nodes) = &db.known_nodes {
+        let mut known_nodes = vec![];
+        for node in nodes {
+            if let Some(node) = resolved("known_nodes", node.get_ref(), node.span())? {
+                known_nodes.push(node);
+            }
+        }
+        /* the whole list is left out when none of it was kept */
+        if !known_nodes.is_empty() || nodes.is_empty() {
+            cfg.known_nodes = known_nodes;
+        }
+    }
+    if let Some(value) = &db.pswd {
+        /* `${VAR}` errors only name the variable, the rest would show the value */
+        let toml::Value::String(pswd) = value.get_ref() else {
+            return Err(ParseError::new(
+                line(value.span()),
+                "`pswd` must be a quoted string",
+            ));
+        };
+        if let Some(pswd) = resolved("pswd", pswd, value.span())? {
+            cfg.pswd = pswd;
+        }
+    }
+
+    let strings = [
+        ("preferred_dc", &db.preferred_dc, &mut cfg.preferred_dc),
+        ("ca_cert", &db.ca_cert, &mut cfg.ca_cert),
+        ("client_cert", &db.client_cert, &mut cfg.client_cert),
+        ("client_key", &db.client_key, &mut cfg.client_key),
+        ("user", &db.user, &mut cfg.user),
+        ("pswd_env", &db.pswd_env, &mut cfg.pswd_env),
+        ("pswd_file", &db.pswd_file, &mut cfg.pswd_file),
+        ("pswd_command", &db.pswd_command, &mut cfg.pswd_command),
+    ];
+    for (key, value, field) in strings {
+        if let Some(value) = value
+            && let Some(value) = resolved(key, value.get_ref(), value.span())?
+        {
+            *field = value;
+        }
+    }
+
+    if outside && !left_out.contains(&"from_cqlshrc") {
+        left_out.push("from_cqlshrc");
+    }
+    Ok(())
+}
+
+/*
+    Unknown blocks and keys, and blocks that aren't tables, with the messages
+    of `.cqlls`. What's left to check is the type of each value.
+*/
+fn check_keys(src: &str) -> Result<(), ParseError> {
+    let document = DeTable::parse(src).map_err(|e| {
+        ParseError::new(
+            line_of(src, e.span().map_or(0, |span| span.start)),
+            e.message(),
+        )
+    })?;
+    let error = |span: Range<usize>, msg: String| ParseError::new(line_of(src, span.start), msg);
+
+    let check_block = |block: &str, table: &Spanned<DeValue>| -> Result<(), ParseError> {
+        let DeValue::Table(table) = table.get_ref() else {
+            return Ok(());
+        };
+        /* features can be anything and lint rules have their own message */
+        if !matches!(block, "db" | "fmt" | "debug") {
+            return Ok(());
+        }
+        let keys = config_keys(block);
+
+        for key in table.keys() {
+            if !keys.iter().any(|k| k.name == key.get_ref()) {
+                return Err(error(
+                    key.span(),
+                    format!("unknown {block} key `{}`", key.get_ref()),
+                ));
+            }
+        }
+        Ok(())
+    };
+
+    for (key, value) in document.get_ref() {
+        let name = key.get_ref().as_ref();
+        match value.get_ref() {
+            DeValue::String(_) if name == "default_profile" => {}
+            _ if name == "default_profile" => {
+                return Err(error(
+                    key.span(),
+                    "`default_profile` must be a string".into(),
+                ));
+            }
+            /* `[profiles.staging]` is the `db "staging"` block */
+            DeValue::Table(profiles) if name == "profiles" => {
+                for (profile, table) in profiles {
+                    if !table.get_ref().is_table() {
+                        return Err(error(
+                            profile.span(),
+                            format!("`profiles.{}` must be a table", profile.get_ref()),
+                        ));
+                    }
+                    check_block("db", table)?;
+                }
+            }
+            _ if name != "profiles" && !CONFIG_BLOCKS.iter().any(|(block, _)| *block == name) => {
+                return Err(error(key.span(), format!("unknown block `{name}`")));
+            }
+            DeValue::Table(_) => check_block(name, value)?,
+            _ => return Err(error(key.span(), format!("`{name}` must be a table"))),
+        }
+    }
+
+    Ok(())
+}
+
+fn line_of(src: &str, offset: usize) -> usize {
+    src[..offset.min(src.len())].matches('\n').count() + 1
+}
+
+/*
+    `.cqlls` text as `.cqlls.toml`. Values are carried over as written,
+    `${VAR}` and `from_cqlshrc` included, so nothing is resolved into the
+    new file. Comments aren't carried over.
+*/
+pub fn migrate(src: &str) -> Result<String, ParseError> {
+    let lines: Vec<(usize, &str)> = meaningful_lines(src).collect();
+    let mut top = String::new();
+    let mut tables = String::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let (ln, text) = lines[i];
+        i += 1;
+
+        if let Ok(("default_profile", val)) = split_kv(text, ln) {
+            top.push_str(&format!("default_profile = {}\n", toml_value(val)));
+            continue;
+        }
+
+        let Some(block) = text.strip_suffix('{').map(str::trim) else {
+            return Err(ParseError::new(
+                ln,
+                format!("expected block opening `{{`, got `{text}`"),
+            ));
+        };
+        let header = match block.strip_prefix("db ") {
+            Some(name) => format!("profiles.{}", toml_key(name.trim().trim_matches('"'))),
+            None => toml_key(block),
+        };
+        tables.push_str(&format!("\n[{header}]\n"));
+
+        while i < lines.len() {
+            let (ln, text) = lines[i];
+            i += 1;
+            if text == "}" {
+                break;
+            }
+
+            /* `known_nodes {` is a list, `udf_formatters {` a table */
+            if let Some(key) = text.strip_suffix('{').map(str::trim) {
+                let mut items = vec![];
+                while i < lines.len() {
+                    let (ln, inner) = lines[i];
+                    i += 1;
+                    let inner = inner.trim_end_matches(',');
+                    if inner == "}" {
+                        break;
+                    }
+                    items.push(match key {
+                        "known_nodes" => toml_value(inner),
+                        _ => {
+                            let (name, value) = split_kv(inner, ln)?;
+                            format!("{} = {}", toml_key(name), toml_value(value))
+                        }
+                    });
+                }
+
+                let value = match (key, items.is_empty()) {
+                    ("known_nodes", _) => format!("[{}]", items.join(", ")),
+                    (_, true) => "{}".to_string(),
+                    (_, false) => format!("{{ {} }}", items.join(", ")),
+                };
+                tables.push_str(&format!("{} = {value}\n", toml_key(key)));
+                continue;
+            }
+
+            let (key, val) = split_kv(text, ln)?;
+            tables.push_str(&format!("{} = {}\n", toml_key(key), toml_value(val)));
+        }
+    }
+
+    Ok(format!("{top}{tables}").trim_start().to_string())
+}
+
+/* numbers and booleans stay as they are, `upper` or `"a"` become strings */
+fn toml_value(raw: &str) -> String {
+    let raw = raw.trim();
+    let string = match raw.strip_prefix('"').and_then(|r| r.strip_suffix('"')) {
+        Some(inner) => unescape(inner),
+        None if raw == "true" || raw == "false" || raw.parse::<i64>().is_ok() => {
+            return raw.to_string();
+        }
+        None => raw.to_string(),
+    };
+
+    toml::Value::String(string).to_string()
+}
+
+fn toml_key(key: &str) -> String {
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    match bare {
+        true => key.to_string(),
+        false => toml_value(&format!("\"{key}\"")),
+    }
+}
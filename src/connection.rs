@@ -0,0 +1,305 @@
+/*
+    Copyright (c) 2026 アクゼスティア. All Rights Reserved.
+*/
+
+/*
+    How the connection to the database is doing.
+
+    A session is built for every query (see `cqlsh`), every build moves the
+    state of its database: `connecting` until one is done, then `connected` or
+    why it failed. Each workspace folder and profile can point somewhere else,
+    so there's a state per resolved `db` config.
+    The language server tells the editor when the state changed, so
+    completions coming back empty aren't a mystery.
+
+    The steps of a connection can also be taken one at a time, `cqlls doctor`
+    does that to tell where it fails.
+*/
+
+use once_cell::sync::Lazy;
+use rustls::ClientConfig;
+use rustls::pki_types::ServerName;
+use scylla::client::session::Session;
+use scylla::errors::{
+    ConnectionError, ConnectionPoolError, ConnectionSetupRequestErrorKind, DbError, MetadataError,
+    NewSessionError,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+use crate::config::{CqllsConfig, DbProfile};
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConnectionState {
+    #[default]
+    Connecting,
+    Connected,
+    AuthFailed,
+    TlsFailed,
+    Unreachable,
+}
+
+impl std::fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Connected => "connected",
+            ConnectionState::AuthFailed => "authentication failed",
+            ConnectionState::TlsFailed => "TLS failed",
+            ConnectionState::Unreachable => "unreachable",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionStatus {
+    pub state: ConnectionState,
+    /* why, when it failed */
+    pub error: Option<String>,
+    pub cluster_name: Option<String>,
+    pub version: Option<String>,
+}
+
+/* a session that couldn't be built, and the state that puts the connection in */
+#[derive(Debug)]
+pub struct ConnectError {
+    pub state: ConnectionState,
+    pub msg: String,
+}
+
+impl ConnectError {
+    pub fn new(state: ConnectionState, msg: impl Into<String>) -> Self {
+        Self {
+            state,
+            msg: msg.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+impl From<NewSessionError> for ConnectError {
+    fn from(e: NewSessionError) -> Self {
+        let mut msg = e.to_string();
+        let mut source = std::error::Error::source(&e);
+        while let Some(inner) = source {
+            let inner_msg = inner.to_string();
+            if !msg.contains(&inner_msg) {
+                msg = format!("{msg}: {inner_msg}");
+            }
+            source = inner.source();
+        }
+
+        let state = failed_state(&e).unwrap_or_else(|| failed_state_of_message(&msg));
+        Self::new(state, msg)
+    }
+}
+
+/*
+    A failed login or handshake is in the last error of the control
+    connection, the metadata fetch is the first thing that needs one.
+*/
+fn failed_state(e: &NewSessionError) -> Option<ConnectionState> {
+    let connection = match e {
+        NewSessionError::FailedToResolveAnyHostname(_) | NewSessionError::EmptyKnownNodesList => {
+            return Some(ConnectionState::Unreachable);
+        }
+        NewSessionError::MetadataError(MetadataError::ConnectionPoolError(
+            ConnectionPoolError::Broken {
+                last_connection_error,
+            },
+        )) => last_connection_error,
+        _ => return None,
+    };
+
+    match connection {
+        ConnectionError::ConnectionSetupRequestError(setup) => match &setup.error {
+            ConnectionSetupRequestErrorKind::DbError(DbError::AuthenticationError, _)
+            | ConnectionSetupRequestErrorKind::MissingAuthentication
+            | ConnectionSetupRequestErrorKind::StartAuthSessionError(_)
+            | ConnectionSetupRequestErrorKind::AuthChallengeEvaluationError(_)
+            | ConnectionSetupRequestErrorKind::AuthFinishError(_) => {
+                Some(ConnectionState::AuthFailed)
+            }
+            _ => None,
+        },
+        /* the TLS stream hands the rustls error over inside of an io error */
+        ConnectionError::IoError(io)
+            if io
+                .get_ref()
+                .is_some_and(|inner| inner.is::<rustls::Error>()) =>
+        {
+            Some(ConnectionState::TlsFailed)
+        }
+        ConnectionError::ConnectTimeout | ConnectionError::IoError(_) => {
+            Some(ConnectionState::Unreachable)
+        }
+        _ => None,
+    }
+}
+
+/* for what the driver doesn't tell apart, like a TLS handshake with a plain port breaking the connection */
+fn failed_state_of_message(msg: &str) -> ConnectionState {
+    let lower = msg.to_lowercase();
+    match () {
+        _ if ["authenticat", "credentials"]
+            .iter()
+            .any(|s| lower.contains(s)) =>
+        {
+            ConnectionState::AuthFailed
+        }
+        _ if ["tls", "certificate", "handshake", "corrupt message"]
+            .iter()
+            .any(|s| lower.contains(s)) =>
+        {
+            ConnectionState::TlsFailed
+        }
+        _ => ConnectionState::Unreachable,
+    }
+}
+
+/* by the `db` settings in effect, a profile or folder with the same ones shares the state */
+static STATUS: Lazy<Mutex<HashMap<DbProfile, ConnectionStatus>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub async fn status(config: &CqllsConfig) -> ConnectionStatus {
+    STATUS
+        .lock()
+        .await
+        .get(&DbProfile::of(config))
+        .cloned()
+        .unwrap_or_default()
+}
+
+/* back to `connecting`, the `db` settings changed */
+pub async fn reset() {
+    STATUS.lock().await.clear();
+}
+
+/* the state after building a session, the cluster is asked who it is once connected */
+pub async fn record(
+    config: &CqllsConfig,
+    result: Result<Session, ConnectError>,
+) -> Result<Session, ConnectError> {
+    let key = DbProfile::of(config);
+    match &result {
+        Ok(session) => {
+            let connected = STATUS
+                .lock()
+                .await
+                .get(&key)
+                .is_some_and(|status| status.state == ConnectionState::Connected);
+            if !connected {
+                let (cluster_name, version) = cluster_info(session).await.unzip();
+                STATUS.lock().await.insert(
+                    key,
+                    ConnectionStatus {
+                        state: ConnectionState::Connected,
+                        error: None,
+                        cluster_name,
+                        version,
+                    },
+                );
+            }
+        }
+        Err(e) => {
+            STATUS.lock().await.insert(
+                key,
+                ConnectionStatus {
+                    state: e.state,
+                    error: Some(e.msg.clone()),
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    result
+}
+
+/* the name of the cluster and the version of the node the session asked */
+pub async fn cluster_info(session: &Session) -> Option<(String, String)> {
+    session
+        .query_unpaged(
+            "SELECT cluster_name, release_version FROM system.local",
+            &[],
+        )
+        .await
+        .ok()?
+        .into_rows_result()
+        .ok()?
+        .single_row::<(String, String)>()
+        .ok()
+}
+
+/* `host:port` → its addresses */
+pub async fn resolve(node: &str) -> Result<Vec<SocketAddr>, String> {
+    let addresses = tokio::net::lookup_host(node)
+        .await
+        .map_err(|e| format!("can't resolve `{node}`: {e}"))?;
+
+    Ok(addresses.collect())
+}
+
+pub async fn tcp_connect(address: SocketAddr) -> Result<(), String> {
+    tokio::time::timeout(TIMEOUT, TcpStream::connect(address))
+        .await
+        .map_err(|_| format!("{address}: timed out"))?
+        .map_err(|e| format!("{address}: {e}"))?;
+
+    Ok(())
+}
+
+/* a handshake like the driver does it, the certificate of the node is checked against its IP */
+pub async fn tls_handshake(address: SocketAddr, tls: ClientConfig) -> Result<(), String> {
+    let handshake = move || -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut socket = std::net::TcpStream::connect_timeout(&address, TIMEOUT)?;
+        socket.set_read_timeout(Some(TIMEOUT))?;
+
+        let server_name = ServerName::IpAddress(address.ip().into());
+        let mut connection = rustls::ClientConnection::new(Arc::new(tls), server_name)?;
+        while connection.is_handshaking() {
+            connection.complete_io(&mut socket)?;
+        }
+        Ok(())
+    };
+
+    tokio::task::spawn_blocking(handshake)
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("{address}: {e}"))
+}
+
+/* how many keyspaces the user can see, and that it may read the columns of their tables */
+pub async fn schema_access(session: &Session) -> Result<usize, String> {
+    let keyspaces = session
+        .query_unpaged("SELECT keyspace_name FROM system_schema.keyspaces", &[])
+        .await
+        .map_err(|e| format!("can't read `system_schema.keyspaces`: {e}"))?
+        .into_rows_result()
+        .map_err(|e| e.to_string())?
+        .rows_num();
+
+    session
+        .query_unpaged("SELECT column_name FROM system_schema.columns LIMIT 1", &[])
+        .await
+        .map_err(|e| format!("can't read `system_schema.columns`: {e}"))?;
+
+    Ok(keyspaces)
+}
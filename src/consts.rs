@@ -419,6 +419,78 @@ pub static CQL_KEYWORDS_LWC: Lazy<Vec<String>> = Lazy::new(|| {
     ]
 });
 
+/*
+    Lower case reserved CQL keywords
+
+    Identifiers matching one of those
+    must be double quoted e.g
+
+    CREATE TABLE t (id int PRIMARY KEY, "token" text);
+*/
+pub static CQL_RESERVED_KEYWORDS_LWC: Lazy<Vec<String>> = Lazy::new(|| {
+    [
+        "add",
+        "allow",
+        "alter",
+        "and",
+        "apply",
+        "asc",
+        "authorize",
+        "batch",
+        "begin",
+        "by",
+        "columnfamily",
+        "create",
+        "delete",
+        "desc",
+        "describe",
+        "drop",
+        "entries",
+        "execute",
+        "from",
+        "full",
+        "grant",
+        "if",
+        "in",
+        "index",
+        "infinity",
+        "insert",
+        "into",
+        "keyspace",
+        "limit",
+        "modify",
+        "nan",
+        "norecursive",
+        "not",
+        "null",
+        "of",
+        "on",
+        "or",
+        "order",
+        "primary",
+        "rename",
+        "replace",
+        "revoke",
+        "schema",
+        "select",
+        "set",
+        "table",
+        "to",
+        "token",
+        "truncate",
+        "unlogged",
+        "update",
+        "use",
+        "using",
+        "view",
+        "where",
+        "with",
+    ]
+    .iter()
+    .map(|kw| kw.to_string())
+    .collect()
+});
+
 /*
     Lower case CQL types
 
@@ -3,23 +3,28 @@
 */
 
 use futures::stream::StreamExt;
+use once_cell::sync::Lazy;
 use scylla::{
     DeserializeRow,
     client::session::Session,
     client::session_builder::SessionBuilder,
     statement::{Statement, prepared::PreparedStatement},
 };
+use std::collections::HashMap;
 use std::fmt;
 use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::Mutex;
 
 use log::info;
-use rustls::pki_types::{CertificateDer, pem::PemObject};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
 use rustls::{ClientConfig, RootCertStore};
 use std::sync::Arc;
 
 use crate::config::{self, CqllsConfig};
+use crate::connection::{self, ConnectError, ConnectionState};
 
-#[derive(DeserializeRow)]
+#[derive(Debug, Clone, DeserializeRow)]
 pub struct Table {
     pub keyspace_name: String,
     pub table_name: String,
@@ -63,6 +68,22 @@ impl FromIterator<KeySpace> for Vec<String> {
     }
 }
 
+/*
+    kind: partition_key | clustering | static | regular
+*/
+#[derive(Debug, Clone)]
+pub struct ColumnKind {
+    pub column_name: String,
+    pub kind: String,
+    pub position: i32,
+}
+
+impl ColumnKind {
+    pub fn is_primary_key(&self) -> bool {
+        self.kind == "partition_key" || self.kind == "clustering"
+    }
+}
+
 #[derive(Debug)]
 pub struct Role {
     pub name: String,
@@ -98,44 +119,159 @@ pub struct View {
     pub view_name: String,
 }
 
-async fn build_session(config: &CqllsConfig) -> Result<Session, Box<dyn std::error::Error>> {
-    let mut builder = SessionBuilder::new()
-        .user(&config.user, &config.pswd)
-        .connection_timeout(Duration::from_secs(3));
+/* output of every `pswd_command` that ran, a session is built for every query */
+static COMMAND_PASSWORDS: Lazy<Mutex<HashMap<String, String>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
 
-    for node in &config.known_nodes {
-        builder = builder.known_node(node.as_str());
+/* runs the `pswd_command`s again when they're needed next, after the config changed */
+pub async fn forget_passwords() {
+    COMMAND_PASSWORDS.lock().await.clear();
+}
+
+/*
+    The first one set of `pswd_env`, `pswd_file` and `pswd_command`, `pswd` otherwise.
+    Errors say where the password should have come from, never what it is.
+*/
+pub async fn password(config: &CqllsConfig) -> Result<String, String> {
+    if !config.pswd_env.is_empty() {
+        return std::env::var(&config.pswd_env).map_err(|_| {
+            format!(
+                "environment variable `{}` of `pswd_env` isn't set",
+                config.pswd_env
+            )
+        });
+    }
+
+    if !config.pswd_file.is_empty() {
+        return std::fs::read_to_string(&config.pswd_file)
+            .map(|contents| contents.trim_end_matches(['\r', '\n']).to_string())
+            .map_err(|e| format!("can't read `pswd_file` `{}`: {e}", config.pswd_file));
     }
 
+    if !config.pswd_command.is_empty() {
+        let command = &config.pswd_command;
+        if let Some(password) = COMMAND_PASSWORDS.lock().await.get(command) {
+            return Ok(password.clone());
+        }
+
+        /* quoted like a shell would, but no shell runs it */
+        let args = shlex::split(command)
+            .filter(|args| !args.is_empty())
+            .ok_or_else(|| format!("can't parse `pswd_command` `{command}`"))?;
+        let output = Command::new(&args[0])
+            .args(&args[1..])
+            .kill_on_drop(true)
+            .output();
+        let output = tokio::time::timeout(Duration::from_secs(30), output)
+            .await
+            .map_err(|_| format!("`pswd_command` `{command}` timed out"))?
+            .map_err(|e| format!("can't run `pswd_command` `{command}`: {e}"))?;
+        if !output.status.success() {
+            return Err(format!(
+                "`pswd_command` `{command}` exited with {}",
+                output.status
+            ));
+        }
+
+        /* the first line, `pass show` prints more after it */
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let password = stdout.lines().next().unwrap_or_default().to_string();
+        COMMAND_PASSWORDS
+            .lock()
+            .await
+            .insert(command.clone(), password.clone());
+        return Ok(password);
+    }
+
+    Ok(config.pswd.clone())
+}
+
+fn root_store(config: &CqllsConfig) -> Result<RootCertStore, Box<dyn std::error::Error>> {
+    if config.ca_cert.is_empty() {
+        return Err("TLS enabled but ca_cert_path is empty".into());
+    }
+
+    let rustls_ca = CertificateDer::from_pem_file(&config.ca_cert)
+        .map_err(|e| format!("Failed to load CA cert '{}': {}", config.ca_cert, e))?;
+
+    let mut root_store = RootCertStore::empty();
+    root_store.add(rustls_ca)?;
+    Ok(root_store)
+}
+
+/* the TLS setup of `config`, `None` without TLS */
+pub fn tls_config(
+    config: &CqllsConfig,
+) -> Result<Option<ClientConfig>, Box<dyn std::error::Error>> {
     match &config.tls {
         config::TlsMode::None => {
             info!("Connecting without TLS");
+            Ok(None)
         }
         config::TlsMode::Tls => {
-            if config.ca_cert.is_empty() {
-                return Err("TLS enabled but ca_cert_path is empty".into());
-            }
-
             info!("Connecting with TLS, cert path: {}", config.ca_cert);
 
-            let rustls_ca = CertificateDer::from_pem_file(&config.ca_cert)
-                .map_err(|e| format!("Failed to load CA cert '{}': {}", config.ca_cert, e))?;
-
-            let mut root_store = RootCertStore::empty();
-            root_store.add(rustls_ca)?;
+            Ok(Some(
+                ClientConfig::builder()
+                    .with_root_certificates(root_store(config)?)
+                    .with_no_client_auth(),
+            ))
+        }
 
-            let tls_config = ClientConfig::builder()
-                .with_root_certificates(root_store)
-                .with_no_client_auth();
+        config::TlsMode::MTls => {
+            if config.client_cert.is_empty() || config.client_key.is_empty() {
+                return Err("mTLS enabled but client_cert or client_key is empty".into());
+            }
 
-            builder = builder.tls_context(Some(Arc::new(tls_config)));
+            info!(
+                "Connecting with mTLS, cert path: {}, client cert path: {}",
+                config.ca_cert, config.client_cert
+            );
+
+            let client_cert = CertificateDer::pem_file_iter(&config.client_cert)
+                .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                .map_err(|e| {
+                    format!("Failed to load client cert '{}': {}", config.client_cert, e)
+                })?;
+            let client_key = PrivateKeyDer::from_pem_file(&config.client_key)
+                .map_err(|e| format!("Failed to load client key '{}': {}", config.client_key, e))?;
+
+            Ok(Some(
+                ClientConfig::builder()
+                    .with_root_certificates(root_store(config)?)
+                    .with_client_auth_cert(client_cert, client_key)?,
+            ))
         }
+    }
+}
+
+/* a session, or why there's none and what that means for the connection */
+pub async fn connect(config: &CqllsConfig) -> Result<Session, ConnectError> {
+    let password = password(config)
+        .await
+        .map_err(|e| ConnectError::new(ConnectionState::AuthFailed, e))?;
+    let mut builder = SessionBuilder::new()
+        .user(&config.user, &password)
+        .connection_timeout(Duration::from_secs(3));
+
+    for node in &config.known_nodes {
+        builder = builder.known_node(node.as_str());
+    }
 
-        config::TlsMode::MTls => {}
+    let tls = tls_config(config)
+        .map_err(|e| ConnectError::new(ConnectionState::TlsFailed, e.to_string()))?;
+    if let Some(tls) = tls {
+        builder = builder.tls_context(Some(Arc::new(tls)));
     }
 
-    Ok(builder.build().await?)
+    /* boxed, the driver's future is deep enough to overflow the layout of every caller */
+    Ok(Box::pin(builder.build()).await?)
+}
+
+async fn build_session(config: &CqllsConfig) -> Result<Session, Box<dyn std::error::Error>> {
+    Ok(connection::record(config, connect(config).await).await?)
 }
+
 pub async fn query_keyspaces(
     config: &CqllsConfig,
 ) -> Result<Vec<KeySpace>, Box<dyn std::error::Error>> {
@@ -338,6 +474,51 @@ pub async fn query_hard_scoped_fields(
     Ok(items)
 }
 
+/*
+    Columns of a table ordered the way `DESCRIBE TABLE` lists them:
+    partition key, clustering columns, then everything else
+*/
+pub async fn query_column_kinds(
+    config: &CqllsConfig,
+    keyspace_name: &str,
+    table_name: &str,
+) -> Result<Vec<ColumnKind>, Box<dyn std::error::Error>> {
+    if !config.has_feature("context_aware_completions") {
+        return Ok(vec![]);
+    }
+
+    let session = build_session(config).await?;
+    let query = format!(
+        "SELECT column_name, kind, position FROM system_schema.columns WHERE keyspace_name = '{}' AND table_name = '{}';",
+        keyspace_name, table_name
+    );
+
+    let result_rows = session
+        .query_unpaged(query, &[])
+        .await?
+        .into_rows_result()?;
+
+    let mut items = Vec::<ColumnKind>::new();
+
+    for row in result_rows.rows::<(String, String, i32)>()? {
+        let (column_name, kind, position) = row?;
+        items.push(ColumnKind {
+            column_name,
+            kind,
+            position,
+        });
+    }
+
+    let rank = |kind: &str| match kind {
+        "partition_key" => 0,
+        "clustering" => 1,
+        _ => 2,
+    };
+    items.sort_by_key(|c| (rank(&c.kind), c.position));
+
+    Ok(items)
+}
+
 /*
     keyspace_name |
     aggregate_name |
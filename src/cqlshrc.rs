@@ -0,0 +1,127 @@
+/*
+    Copyright (c) 2026 アクゼスティア. All Rights Reserved.
+*/
+
+/*
+    Connection settings of a cqlshrc, so a cluster cqlsh already knows
+    doesn't have to be described again in `.cqlls`.
+
+    Only what cqlls has a setting for is read, everything else of the
+    file (`[ui]`, `[copy]`, ...) is ignored.
+*/
+
+use log::warn;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::config::{CqllsConfig, TlsMode};
+
+#[derive(Debug, Default)]
+pub struct Cqlshrc {
+    sections: HashMap<String, HashMap<String, String>>,
+}
+
+/* `~/.cassandra/cqlshrc` */
+pub fn user_cqlshrc_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".cassandra").join("cqlshrc"))
+}
+
+/* `~/` is the home directory, like cqlsh has it */
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+impl Cqlshrc {
+    pub fn read(path: &Path) -> std::io::Result<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    /* the INI of python's configparser, `key = value` or `key: value` */
+    pub fn parse(src: &str) -> Self {
+        let mut sections: HashMap<String, HashMap<String, String>> = HashMap::new();
+        let mut section = String::new();
+
+        for line in src.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name.trim().to_lowercase();
+                continue;
+            }
+
+            if let Some((key, value)) = line.split_once(['=', ':']) {
+                sections
+                    .entry(section.clone())
+                    .or_default()
+                    .insert(key.trim().to_lowercase(), value.trim().to_string());
+            }
+        }
+
+        Self { sections }
+    }
+
+    fn get(&self, section: &str, key: &str) -> Option<&str> {
+        self.sections
+            .get(section)?
+            .get(key)
+            .map(String::as_str)
+            .filter(|v| !v.is_empty())
+    }
+
+    fn flag(&self, section: &str, key: &str) -> Option<bool> {
+        self.get(section, key)
+            .map(|v| matches!(v.to_lowercase().as_str(), "true" | "yes" | "on" | "1"))
+    }
+
+    /* the `db` settings the file has, on top of `config` */
+    pub fn apply(&self, config: &mut CqllsConfig) {
+        let hostname = self.get("connection", "hostname");
+        let port = self.get("connection", "port");
+        if hostname.is_some() || port.is_some() {
+            let hostname = hostname.unwrap_or("127.0.0.1");
+            let port = port.unwrap_or("9042");
+            config.known_nodes = vec![format!("{hostname}:{port}")];
+        }
+
+        /* `[auth_provider]` is what newer cqlsh versions write */
+        for section in ["authentication", "auth_provider"] {
+            if let Some(username) = self.get(section, "username") {
+                config.user = username.to_string();
+            }
+            if let Some(password) = self.get(section, "password") {
+                config.pswd = password.to_string();
+            }
+        }
+
+        if let Some(certfile) = self.get("ssl", "certfile") {
+            config.ca_cert = expand_home(certfile).display().to_string();
+        }
+        if let Some(usercert) = self.get("ssl", "usercert") {
+            config.client_cert = expand_home(usercert).display().to_string();
+        }
+        if let Some(userkey) = self.get("ssl", "userkey") {
+            config.client_key = expand_home(userkey).display().to_string();
+        }
+
+        if let Some(ssl) = self.flag("connection", "ssl") {
+            config.tls = match ssl {
+                false => TlsMode::None,
+                true if !config.client_cert.is_empty() && !config.client_key.is_empty() => {
+                    TlsMode::MTls
+                }
+                true => TlsMode::Tls,
+            };
+        }
+
+        if self.flag("ssl", "validate") == Some(false) {
+            warn!(
+                "cqlshrc has `validate = false`, cqlls always validates the certificate of the server"
+            );
+        }
+    }
+}
@@ -2,7 +2,11 @@
     Copyright (c) 2026 アクゼスティア. All Rights Reserved.
 */
 
+use crate::config::{interpolate, meaningful_lines, parse_config, parse_config_file};
+use crate::formatting::offset_to_position;
+use crate::lint::{LintFinding, Suppressions};
 use crate::tree_sitter::TS_CQL;
+use std::path::Path;
 use tower_lsp::lsp_types::*;
 use tree_sitter::Node;
 
@@ -10,43 +14,150 @@ use crate::lsp::Backend;
 
 impl Backend {
     pub async fn compute_diagnostics(&self, text: &str) -> Vec<Diagnostic> {
+        self.diagnostics(text, true).await.0
+    }
+
+    /*
+        The diagnostics with only the schema that's cached, nothing waits for
+        the database. `false` when some schema lints had to be left out.
+    */
+    pub async fn cached_diagnostics(&self, text: &str) -> (Vec<Diagnostic>, bool) {
+        self.diagnostics(text, false).await
+    }
+
+    async fn diagnostics(&self, text: &str, fetch: bool) -> (Vec<Diagnostic>, bool) {
         if !self.config.has_feature("diagnostics") {
-            return vec![];
+            return (vec![], true);
         }
 
-        let mut parser = TS_CQL.lock().await;
-        let tree = match parser.parse(text, None) {
-            Some(t) => t,
-            None => return vec![],
+        let tree = {
+            let mut parser = TS_CQL.lock().await;
+            match parser.parse(text, None) {
+                Some(t) => t,
+                None => return (vec![], true),
+            }
         };
 
-        let mut diags = Vec::new();
-        let mut cursor = tree.walk();
-        let root = tree.root_node();
-        Self::collect_error_nodes(root, &mut cursor, &mut diags);
-        diags
+        let mut findings = Vec::new();
+        let (table_refs, batches, suppressions) = {
+            let mut cursor = tree.walk();
+            let root = tree.root_node();
+            Self::collect_error_nodes(root, text, &mut cursor, &mut findings);
+            self.lint_statements(root, text, &mut findings);
+
+            (
+                Self::collect_table_refs(root, text),
+                Self::collect_unlogged_batches(root, text),
+                Suppressions::from_tree(root, text),
+            )
+        };
+
+        let complete = self.lint_schema(table_refs, &mut findings, fetch).await;
+        let complete = self
+            .lint_unlogged_batches(batches, &mut findings, fetch)
+            .await
+            && complete;
+
+        let diagnostics = findings
+            .into_iter()
+            .filter_map(|finding| self.finding_to_diagnostic(finding, &suppressions))
+            .collect();
+        (diagnostics, complete)
+    }
+
+    /*
+        The parse error of a `.cqlls` or `.cqlls.toml`, over the whole offending line,
+        and the cert files it names that don't exist
+    */
+    pub fn config_diagnostics(uri: &Url, text: &str) -> Vec<Diagnostic> {
+        let parsed = match uri.to_file_path() {
+            Ok(path) => parse_config_file(text, &path),
+            Err(()) => parse_config(text),
+        };
+
+        let mut diagnostics = vec![];
+        if let Err(e) = parsed {
+            diagnostics.push(Self::line_diagnostic(
+                text,
+                e.line,
+                DiagnosticSeverity::ERROR,
+                e.msg,
+            ));
+        }
+
+        for (ln, line) in meaningful_lines(text) {
+            let Some((key, value)) = line.split_once([':', '=']) else {
+                continue;
+            };
+            let key = key.trim().rsplit('.').next().unwrap_or_default();
+            if !["ca_cert", "client_cert", "client_key"].contains(&key) {
+                continue;
+            }
+
+            /* an unset `${VAR}` is a parse error already */
+            let value = value.trim().trim_end_matches(',').trim_matches(['"', '\'']);
+            let Ok(path) = interpolate(value, ln) else {
+                continue;
+            };
+            if !path.is_empty() && !Path::new(&path).exists() {
+                diagnostics.push(Self::line_diagnostic(
+                    text,
+                    ln,
+                    DiagnosticSeverity::WARNING,
+                    format!("`{key}` `{path}` doesn't exist"),
+                ));
+            }
+        }
+
+        diagnostics
+    }
+
+    /* `line` counts from 1, like `ParseError` does */
+    fn line_diagnostic(
+        text: &str,
+        line: usize,
+        severity: DiagnosticSeverity,
+        message: String,
+    ) -> Diagnostic {
+        let line = line.saturating_sub(1);
+        let width = text
+            .lines()
+            .nth(line)
+            .map_or(0, |l| l.encode_utf16().count());
+
+        Diagnostic {
+            range: Range::new(
+                Position::new(line as u32, 0),
+                Position::new(line as u32, width as u32),
+            ),
+            severity: Some(severity),
+            source: Some("cqlls".to_string()),
+            message,
+            ..Default::default()
+        }
     }
 
     fn collect_error_nodes(
         node: Node,
+        text: &str,
         cursor: &mut tree_sitter::TreeCursor,
-        out: &mut Vec<Diagnostic>,
+        out: &mut Vec<LintFinding>,
     ) {
         if node.is_error() || node.is_missing() {
-            let range = Self::node_to_range(node);
-            out.push(Diagnostic {
+            let range = Self::node_to_range(node, text);
+            out.push(LintFinding {
+                rule: "syntax-error",
                 range,
-                severity: Some(DiagnosticSeverity::ERROR),
-                source: Some("cql".to_string()),
                 message: "Syntax error".to_string(),
-                ..Default::default()
+                statement_line: range.start.line,
+                fixes: vec![],
             });
         }
 
         if cursor.goto_first_child() {
             loop {
                 let child = cursor.node();
-                Self::collect_error_nodes(child, cursor, out);
+                Self::collect_error_nodes(child, text, cursor, out);
                 if !cursor.goto_next_sibling() {
                     break;
                 }
@@ -55,12 +166,10 @@ impl Backend {
         }
     }
 
-    fn node_to_range(node: Node) -> Range {
-        let start = node.start_position();
-        let end = node.end_position();
-        Range {
-            start: Position::new(start.row as u32, start.column as u32),
-            end: Position::new(end.row as u32, end.column as u32),
-        }
+    pub(crate) fn node_to_range(node: Node, text: &str) -> Range {
+        Range::new(
+            offset_to_position(text, node.start_byte()),
+            offset_to_position(text, node.end_byte()),
+        )
     }
 }
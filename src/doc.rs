@@ -0,0 +1,200 @@
+/*
+    Copyright (c) 2026 アクゼスティア. All Rights Reserved.
+*/
+
+/*
+    Wadler style document algebra used by the formatter.
+
+    A `Group` is printed on one line when it fits into the remaining width,
+    otherwise every `Line`/`SoftLine` directly inside of it becomes a new line.
+    Nested groups make the same choice on their own.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum Doc {
+    Nil,
+    Text(String),
+    /* " " when flat, new line when broken */
+    Line,
+    /* "" when flat, new line when broken */
+    SoftLine,
+    /* always a new line, breaks every enclosing group */
+    HardLine,
+    /* prints nothing, breaks every enclosing group (e.g. after a line comment) */
+    BreakParent,
+    Nest(usize, Box<Doc>),
+    Concat(Vec<Doc>),
+    Group(Box<Doc>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+pub fn nil() -> Doc {
+    Doc::Nil
+}
+
+pub fn text(s: impl Into<String>) -> Doc {
+    Doc::Text(s.into())
+}
+
+pub fn line() -> Doc {
+    Doc::Line
+}
+
+pub fn softline() -> Doc {
+    Doc::SoftLine
+}
+
+pub fn hardline() -> Doc {
+    Doc::HardLine
+}
+
+pub fn break_parent() -> Doc {
+    Doc::BreakParent
+}
+
+pub fn nest(indent: usize, doc: Doc) -> Doc {
+    Doc::Nest(indent, Box::new(doc))
+}
+
+pub fn group(doc: Doc) -> Doc {
+    Doc::Group(Box::new(doc))
+}
+
+pub fn concat(docs: Vec<Doc>) -> Doc {
+    Doc::Concat(docs)
+}
+
+pub fn join(docs: Vec<Doc>, separator: Doc) -> Doc {
+    let mut joined = Vec::with_capacity(docs.len() * 2);
+
+    for (i, doc) in docs.into_iter().enumerate() {
+        if i > 0 {
+            joined.push(separator.clone());
+        }
+        joined.push(doc);
+    }
+
+    Doc::Concat(joined)
+}
+
+fn text_width(s: &str) -> usize {
+    s.chars().count()
+}
+
+/*
+    Whether everything up to the next broken line fits into `remaining` columns.
+    `next` is the group being measured (flat), `rest` is what follows it.
+*/
+fn fits(mut remaining: isize, next: (usize, Mode, &Doc), rest: &[(usize, Mode, &Doc)]) -> bool {
+    let mut stack = vec![next];
+    let mut rest = rest.iter().rev();
+
+    while remaining >= 0 {
+        let (indent, mode, doc) = match stack.pop() {
+            Some(item) => item,
+            None => match rest.next() {
+                Some(item) => *item,
+                None => return true,
+            },
+        };
+
+        match doc {
+            Doc::Nil => {}
+            Doc::Text(s) => {
+                if s.contains('\n') {
+                    return mode == Mode::Break;
+                }
+                remaining -= text_width(s) as isize;
+            }
+            Doc::Line | Doc::SoftLine => match mode {
+                Mode::Break => return true,
+                Mode::Flat => {
+                    if matches!(doc, Doc::Line) {
+                        remaining -= 1;
+                    }
+                }
+            },
+            Doc::HardLine | Doc::BreakParent => {
+                if mode == Mode::Flat {
+                    return false;
+                }
+                if matches!(doc, Doc::HardLine) {
+                    return true;
+                }
+            }
+            Doc::Nest(i, d) => stack.push((indent + i, mode, d)),
+            Doc::Concat(docs) => {
+                for d in docs.iter().rev() {
+                    stack.push((indent, mode, d));
+                }
+            }
+            Doc::Group(d) => stack.push((indent, mode, d)),
+        }
+    }
+
+    false
+}
+
+impl Doc {
+    pub fn pretty(&self, width: usize) -> String {
+        let mut out = String::new();
+        let mut column = 0usize;
+        /* indentation is written lazily, so blank lines don't get trailing spaces */
+        let mut pending_indent: Option<usize> = None;
+        let mut stack: Vec<(usize, Mode, &Doc)> = vec![(0, Mode::Break, self)];
+
+        while let Some((indent, mode, doc)) = stack.pop() {
+            match doc {
+                Doc::Nil | Doc::BreakParent => {}
+                Doc::Text(s) => {
+                    if s.is_empty() {
+                        continue;
+                    }
+                    if let Some(i) = pending_indent.take() {
+                        out.push_str(&" ".repeat(i));
+                    }
+                    out.push_str(s);
+                    column = match s.rfind('\n') {
+                        Some(i) => text_width(&s[i + 1..]),
+                        None => column + text_width(s),
+                    };
+                }
+                Doc::Line | Doc::SoftLine if mode == Mode::Flat => {
+                    if matches!(doc, Doc::Line) {
+                        if let Some(i) = pending_indent.take() {
+                            out.push_str(&" ".repeat(i));
+                        }
+                        out.push(' ');
+                        column += 1;
+                    }
+                }
+                Doc::Line | Doc::SoftLine | Doc::HardLine => {
+                    out.push('\n');
+                    column = indent;
+                    pending_indent = Some(indent);
+                }
+                Doc::Nest(i, d) => stack.push((indent + i, mode, d)),
+                Doc::Concat(docs) => {
+                    for d in docs.iter().rev() {
+                        stack.push((indent, mode, d));
+                    }
+                }
+                Doc::Group(d) => {
+                    let flat = mode == Mode::Flat
+                        || fits(
+                            width as isize - column as isize,
+                            (indent, Mode::Flat, d),
+                            &stack,
+                        );
+                    stack.push((indent, if flat { Mode::Flat } else { Mode::Break }, d));
+                }
+            }
+        }
+
+        out
+    }
+}
@@ -2,1185 +2,1999 @@
     Copyright (c) 2026 アクゼスティア. All Rights Reserved.
 */
 
-use log::{error, info};
+use log::error;
 use tower_lsp::lsp_types::*;
 
-use crate::{consts::*, lsp::Backend};
+use similar::{Algorithm, DiffTag, capture_diff_slices};
+use std::collections::HashMap;
+use std::ops::Range as Span;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
 
-#[derive(Debug, Clone)]
-pub struct InsertStatement {
-    pub table_name: String,
-    pub fields: Vec<String>,
-    pub values: Vec<Vec<String>>,
-}
+use crate::{config::Case, consts::*, doc::*, lsp::Backend, tree_sitter::TS_CQL};
+use tree_sitter::Node;
+
+/*
+    Merges adjacent inserts/deletes/replaces into one change
+*/
+fn changed_spans<T: Eq + std::hash::Hash>(old: &[T], new: &[T]) -> Vec<(Span<usize>, Span<usize>)> {
+    let mut spans: Vec<(Span<usize>, Span<usize>)> = vec![];
 
-impl InsertStatement {
-    pub fn new() -> Self {
-        Self {
-            table_name: "".to_string(),
-            fields: Vec::new(),
-            values: Vec::new(),
+    for op in capture_diff_slices(Algorithm::Myers, old, new) {
+        let (tag, old, new) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            continue;
         }
-    }
-}
 
-impl Backend {
-    pub fn remove_leading_spaces_wildcards(&self, line: &mut String) {
-        let mut result = String::with_capacity(line.len());
-        let chars: Vec<char> = line.chars().collect();
-        let mut iter = chars.iter().peekable();
-
-        while let Some(&current) = iter.next() {
-            if current == ' ' {
-                if let Some(&&next) = iter.peek() {
-                    if matches!(next, ' ' | ';' | ',' | ')' | '>') {
-                        continue;
-                    }
-                }
+        match spans.last_mut() {
+            Some((o, n)) if o.end == old.start && n.end == new.start => {
+                o.end = old.end;
+                n.end = new.end;
             }
-            result.push(current);
+            _ => spans.push((old, new)),
         }
-
-        *line = result;
     }
 
-    pub fn remove_tailing_spaces_wildcards(&self, line: &mut String) {
-        let mut chars: Vec<char> = line.chars().collect();
-        let mut index = 0;
-        let mut met_wild_card = false;
+    spans
+}
 
-        while index < chars.len() {
-            let current_char = chars[index];
+pub(crate) fn offset_to_position(text: &str, offset: usize) -> Position {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
 
-            if !met_wild_card && (current_char == '(' || current_char == '<') {
-                met_wild_card = true;
-            }
+    Position::new(
+        before.matches('\n').count() as u32,
+        before[line_start..].encode_utf16().count() as u32,
+    )
+}
 
-            if met_wild_card && current_char != '(' && current_char != '<' {
-                met_wild_card = false;
-            }
+/*
+    The formatter works on the tokens of the tree-sitter CST.
 
-            if met_wild_card && index + 1 < chars.len() && chars[index + 1] == ' ' {
-                chars.remove(index + 1);
-                met_wild_card = false;
-                if index >= 2 {
-                    index -= 2;
-                } else if index > 0 {
-                    index -= 1;
-                }
-            }
+    The grammar is mostly flat (a statement is a list of keywords and leaves),
+    so the layout is driven by keywords and brackets rather than by node kinds.
+    Every token is printed exactly as it is in the source, only the whitespace
+    between tokens is decided by the formatter. That keeps literals, comments
+    and UDF bodies byte for byte identical.
+*/
+/* nodes printed as a single token even though they may have children */
+const ATOMIC_NODES: [&str; 5] = [
+    "string_literal",
+    "quoted_identifier",
+    "line_comment",
+    "block_comment",
+    "code_block",
+];
+
+/* keywords opening the type parameters, `map<`, `frozen<`, ... */
+const TYPE_CONSTRUCTORS: [&str; 6] = ["map", "set", "list", "frozen", "tuple", "vector"];
+
+/* `CREATE INDEX ON t (KEYS(c))` */
+const INDEX_TARGETS: [&str; 4] = ["entries", "keys", "values", "full"];
+
+/* keywords the grammar accepts as function names, `token(a)`, `uuid()`, ... */
+const CALL_KEYWORDS: [&str; 9] = [
+    "count",
+    "max",
+    "min",
+    "sum",
+    "avg",
+    "writetime",
+    "ttl",
+    "token",
+    "uuid",
+];
+
+/* keywords that aren't reserved but are followed by a `(` that isn't a call */
+const NOT_CALL_KEYWORDS: [&str; 6] = ["values", "key", "by", "initcond", "as", "contains"];
+
+/* keywords followed by an object name, `INTO users (`, `TABLE ks.t (`, ... */
+const OBJECT_KEYWORDS: [&str; 10] = [
+    "into", "table", "type", "exists", "on", "index", "view", "keyspace", "from", "to",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TokenKind {
+    Keyword,
+    Word,
+    Punct,
+    LineComment,
+    BlockComment,
+    Verbatim,
+}
 
-            index += 1;
-        }
+#[derive(Debug, Clone)]
+struct Token<'a> {
+    text: &'a str,
+    kind: TokenKind,
+    parent: &'static str,
+    start: usize,
+    end: usize,
+    row: usize,
+    end_row: usize,
+    /* unterminated literal/comment or bytes the parser skipped, never re-spaced */
+    suspicious: bool,
+}
 
-        *line = chars.into_iter().collect();
+impl Token<'_> {
+    fn is(&self, keyword: &str) -> bool {
+        matches!(self.kind, TokenKind::Keyword | TokenKind::Word)
+            && self.text.eq_ignore_ascii_case(keyword)
     }
 
-    pub fn is_insert_statement_line(&self, line: &str) -> bool {
-        if line.trim().to_lowercase().starts_with("insert into") {
-            return true;
-        } else {
-            return false;
-        };
+    fn is_comment(&self) -> bool {
+        matches!(self.kind, TokenKind::LineComment | TokenKind::BlockComment)
     }
+}
 
-    pub async fn format_insert(&self, lines: &mut Vec<String>, _document_url: &Url) {
-        let mut indices: Vec<usize> = Vec::new();
-        let mut ending_indices: Vec<usize> = Vec::new();
-        let mut met_insert_start = false;
-
-        for (pos, line) in lines.iter().enumerate() {
-            let is_insert_line = line.to_lowercase().trim().starts_with("insert into");
-            let mut is_end_st = false;
+fn classify(node: Node, text: &str) -> TokenKind {
+    match node.kind() {
+        "line_comment" => TokenKind::LineComment,
+        "block_comment" => TokenKind::BlockComment,
+        "code_block" => TokenKind::Verbatim,
+        _ if !node.is_named() && text.chars().all(|c| c.is_ascii_alphabetic() || c == '_') => {
+            TokenKind::Keyword
+        }
+        _ if !text
+            .chars()
+            .any(|c| c.is_alphanumeric() || c == '\'' || c == '"') =>
+        {
+            TokenKind::Punct
+        }
+        _ => TokenKind::Word,
+    }
+}
 
-            if pos + 1 != lines.len() {
-                is_end_st = line.trim().ends_with(";") || lines[pos + 1].trim().is_empty();
-            } else {
-                is_end_st = true
-            }
+fn is_unterminated(text: &str, kind: TokenKind) -> bool {
+    let unclosed = |quote: char| {
+        text.starts_with(quote) && (text.chars().count() < 2 || !text.ends_with(quote))
+    };
 
-            if is_insert_line && met_insert_start {
-                is_end_st = true;
-            }
+    unclosed('\'') || unclosed('"') || (kind == TokenKind::BlockComment && !text.ends_with("*/"))
+}
 
-            if is_insert_line && !met_insert_start {
-                indices.push(pos);
-                met_insert_start = true;
-            }
-            if met_insert_start && is_end_st {
-                ending_indices.push(pos);
-                met_insert_start = false;
-            }
+fn collect_tokens<'a>(node: Node, source: &'a str, out: &mut Vec<Token<'a>>) {
+    if node.child_count() == 0 || ATOMIC_NODES.contains(&node.kind()) {
+        if node.start_byte() == node.end_byte() {
+            return;
         }
 
-        let mut total_new_lines: i32 = 0;
-        let mut statements_info: Vec<(usize, usize, Vec<String>)> = Vec::new();
+        let text = &source[node.byte_range()];
+        let kind = classify(node, text);
+
+        out.push(Token {
+            text,
+            kind,
+            parent: node.parent().map_or("", |p| p.kind()),
+            start: node.start_byte(),
+            end: node.end_byte(),
+            row: node.start_position().row,
+            end_row: node.end_position().row,
+            suspicious: is_unterminated(text, kind),
+        });
+        return;
+    }
 
-        for (start, end) in indices.iter().zip(ending_indices.iter()) {
-            if *start <= *end && *end < lines.len() {
-                let statement: String = lines[*start..=*end].join(" ");
-                let normalized: String = statement
-                    .split_whitespace()
-                    .collect::<Vec<&str>>()
-                    .join(" ");
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        collect_tokens(child, source, out);
+    }
+}
 
-                let formatted = self.format_insert_statement(&normalized);
-                let formatted_lines: Vec<String> =
-                    formatted.lines().map(|s| s.to_string()).collect();
+fn gap_token(source: &str, from: usize, to: usize) -> Option<Token<'_>> {
+    let raw = &source[from..to];
+    let trimmed = raw.trim();
+    if trimmed.is_empty() {
+        return None;
+    }
 
-                let original_count = (*end - *start + 1) as i32;
-                let new_count = formatted_lines.len() as i32;
-                total_new_lines += new_count - original_count;
+    let start = from + raw.find(trimmed).unwrap_or(0);
+    let end = start + trimmed.len();
+    Some(Token {
+        text: trimmed,
+        kind: if trimmed == ";" {
+            TokenKind::Punct
+        } else {
+            TokenKind::Verbatim
+        },
+        parent: "",
+        start,
+        end,
+        row: source[..start].matches('\n').count(),
+        end_row: source[..end].matches('\n').count(),
+        suspicious: trimmed != ";",
+    })
+}
 
-                statements_info.push((*start, *end, formatted_lines));
-            }
-        }
+/*
+    Anything between the leaves that isn't whitespace was skipped by the parser,
+    it's kept as a token of its own so nothing gets lost.
+*/
+fn fill_gaps<'a>(tokens: Vec<Token<'a>>, source: &'a str) -> Vec<Token<'a>> {
+    let mut filled = Vec::with_capacity(tokens.len());
+    let mut last_end = 0;
 
-        if total_new_lines > 0 {
-            let new_size = lines.len() + total_new_lines as usize;
-            lines.resize(new_size, String::new());
+    let gap = |from: usize, to: usize| gap_token(source, from, to);
+
+    for token in tokens {
+        if token.start > last_end {
+            filled.extend(gap(last_end, token.start));
         }
+        last_end = last_end.max(token.end);
+        filled.push(token);
+    }
+    filled.extend(gap(last_end, source.len()));
 
-        for (start, end, formatted_lines) in statements_info.into_iter().rev() {
-            let original_line_count = end - start + 1;
-            let new_line_count = formatted_lines.len();
+    filled
+}
 
-            if new_line_count > original_line_count {
-                let shift = new_line_count - original_line_count;
-                for i in (end + 1..lines.len() - shift).rev() {
-                    lines[i + shift] = lines[i].clone();
-                }
-            } else if new_line_count < original_line_count {
-                let shift = original_line_count - new_line_count;
-                for i in (end + 1)..lines.len() {
-                    if i + shift < lines.len() {
-                        lines[i] = lines[i + shift].clone();
-                    }
-                }
-                lines.truncate(lines.len() - shift);
-            }
+/*
+    The grammar doesn't know UDF bodies in quotes, `LANGUAGE lua AS '...'`,
+    a multi line one ends up split over error nodes. The whole literal becomes
+    one token, `''` included.
+*/
+fn merge_string_bodies<'a>(tokens: Vec<Token<'a>>, source: &'a str) -> Vec<Token<'a>> {
+    let mut merged: Vec<Token<'a>> = Vec::with_capacity(tokens.len());
+    let mut tokens = tokens.into_iter().peekable();
+
+    while let Some(token) = tokens.next() {
+        let is_body = token.text.starts_with('\'')
+            && merged.len() >= 3
+            && merged[merged.len() - 1].is("as")
+            && merged[merged.len() - 3].is("language");
+        if !is_body {
+            merged.push(token);
+            continue;
+        }
 
-            for (i, formatted_line) in formatted_lines.into_iter().enumerate() {
-                lines[start + i] = formatted_line;
+        let Some(end) = string_end(source, token.start) else {
+            merged.push(token);
+            continue;
+        };
+
+        let mut last_end = token.end;
+        while last_end < end {
+            match tokens.next() {
+                Some(next) => last_end = next.end,
+                None => break,
             }
         }
+
+        merged.push(Token {
+            text: &source[token.start..end],
+            kind: TokenKind::Word,
+            parent: "string_body",
+            start: token.start,
+            end,
+            row: token.row,
+            end_row: source[..end].matches('\n').count(),
+            suspicious: false,
+        });
+        /* what's left of a token the closing quote was part of, usually the `;` */
+        merged.extend(gap_token(source, end, last_end.max(end)));
     }
 
-    pub fn format_insert_statement(&self, input: &str) -> String {
-        let mut result = String::new();
-        let mut indent_level: usize = 0;
-        let mut chars = input.chars().peekable();
-        let mut in_string = false;
-        let mut string_char = ' ';
-        let mut paren_depth: usize = 0;
-        let mut in_function = false;
-        let mut after_into_table = false;
-        let indent_unit = " ".repeat(self.config.indent as usize);
+    merged
+}
+
+/* end of the literal opened at `start`, `''` is an escaped quote */
+fn string_end(source: &str, start: usize) -> Option<usize> {
+    let bytes = source.as_bytes();
+    let mut index = start + 1;
 
-        while let Some(c) = chars.next() {
-            if (c == '\'' || c == '"') && !in_string {
-                in_string = true;
-                string_char = c;
-                result.push(c);
-                continue;
-            }
-            if in_string && c == string_char {
-                in_string = false;
-                result.push(c);
-                continue;
-            }
-            if in_string {
-                result.push(c);
+    while index < bytes.len() {
+        if bytes[index] == b'\'' {
+            if bytes.get(index + 1) == Some(&b'\'') {
+                index += 2;
                 continue;
             }
-            match c {
-                '(' => {
-                    let is_function_call = self.is_function_call(&result);
-                    if is_function_call && !after_into_table {
-                        if !in_function {
-                            in_function = true;
-                            paren_depth = 1;
-                        } else {
-                            paren_depth += 1;
-                        }
-                        result.push(c);
-                    } else {
-                        after_into_table = false;
-                        result.push(c);
-                        indent_level += 1;
-                        result.push('\n');
-                        result.push_str(&indent_unit.repeat(indent_level));
-                    }
-                }
-                ')' => {
-                    if in_function {
-                        paren_depth -= 1;
-                        result.push(c);
-                        if paren_depth == 0 {
-                            in_function = false;
-                        }
-                    } else {
-                        indent_level = indent_level.saturating_sub(1);
-                        result.push('\n');
-                        result.push_str(&indent_unit.repeat(indent_level));
-                        result.push(c);
-                    }
-                }
-                '{' => {
-                    result.push(c);
-                    indent_level += 1;
-                    result.push('\n');
-                    result.push_str(&indent_unit.repeat(indent_level));
-                }
-                '}' => {
-                    indent_level = indent_level.saturating_sub(1);
-                    result.push('\n');
-                    result.push_str(&indent_unit.repeat(indent_level));
-                    result.push(c);
-                }
-                ',' => {
-                    result.push(c);
-                    if !in_function {
-                        result.push('\n');
-                        result.push_str(&indent_unit.repeat(indent_level));
-                    }
-                    while chars.peek() == Some(&' ') {
-                        chars.next();
-                    }
-                }
-                ' ' => {
-                    if !result.ends_with(' ')
-                        && !result.ends_with('\n')
-                        && !result.ends_with(indent_unit.as_str())
-                    {
-                        result.push(c);
-                    }
-                    if self.is_after_into_tablename(&result) {
-                        after_into_table = true;
-                    }
-                }
-                _ => {
-                    result.push(c);
-                }
-            }
+            return Some(index + 1);
         }
-        result = result.replace("VALUES", "\nVALUES");
-        result
-            .lines()
-            .map(|line| line.trim_end())
-            .collect::<Vec<&str>>()
-            .join("\n")
-    }
-    fn is_function_call(&self, s: &str) -> bool {
-        let known_functions = [
-            "UUID",
-            "NOW",
-            "TOTIMESTAMP",
-            "TOUNIXTIMESTAMP",
-            "TODATE",
-            "TOKEN",
-            "TTL",
-            "WRITETIME",
-            "CAST",
-            "COUNT",
-            "MIN",
-            "MAX",
-            "SUM",
-            "AVG",
-            "BLOB",
-            "ASCII",
-            "BIGINT",
-            "BOOLEAN",
-            "COUNTER",
-            "DATE",
-            "DECIMAL",
-            "DOUBLE",
-            "FLOAT",
-            "INET",
-            "INT",
-            "SMALLINT",
-            "TEXT",
-            "TIME",
-            "TIMESTAMP",
-            "TIMEUUID",
-            "TINYINT",
-            "VARCHAR",
-            "VARINT",
-            "MINTIMEUUID",
-            "MAXTIMEUUID",
-            "BLOBASINT",
-            "BLOBASTEXT",
-            "INTASBLOB",
-            "TEXTASBLOB",
-        ];
+        index += 1;
+    }
 
-        let last_word: String = s
-            .chars()
-            .rev()
-            .take_while(|c| c.is_alphanumeric() || *c == '_')
-            .collect::<String>()
-            .chars()
-            .rev()
-            .collect();
+    None
+}
 
-        if last_word.is_empty() {
-            return false;
+/*
+    The grammar only knows the body of some languages, everything between
+    the `$$` is kept as it is, whitespace included.
+*/
+fn merge_code_blocks<'a>(tokens: Vec<Token<'a>>, source: &'a str) -> Vec<Token<'a>> {
+    let is_dollars = |t: &Token| t.kind == TokenKind::Punct && t.text == "$$";
+    let mut merged = Vec::with_capacity(tokens.len());
+    let mut tokens = tokens.into_iter();
+
+    while let Some(open) = tokens.next() {
+        if !is_dollars(&open) {
+            merged.push(open);
+            continue;
+        }
+
+        let (start, row) = (open.end, open.end_row);
+        merged.push(open);
+
+        /* the closing `$$` is consumed here and rebuilt below */
+        let body: Vec<Token> = tokens.by_ref().take_while(|t| !is_dollars(t)).collect();
+        let body_end = body.last().map_or(start, |t| t.end);
+
+        let Some(end) = source[body_end..].find("$$").map(|i| body_end + i) else {
+            /* unterminated body, the statement is printed as it is */
+            merged.last_mut().unwrap().suspicious = true;
+            merged.extend(body);
+            continue;
+        };
+
+        if end > start {
+            merged.push(Token {
+                text: &source[start..end],
+                kind: TokenKind::Verbatim,
+                parent: "code_block",
+                start,
+                end,
+                row,
+                end_row: source[..end].matches('\n').count(),
+                suspicious: false,
+            });
         }
 
-        let upper = last_word.to_uppercase();
+        let end_row = source[..end].matches('\n').count();
+        merged.push(Token {
+            text: &source[end..end + 2],
+            kind: TokenKind::Punct,
+            parent: "",
+            start: end,
+            end: end + 2,
+            row: end_row,
+            end_row,
+            suspicious: false,
+        });
+    }
+
+    merged
+}
+
+#[derive(Debug)]
+enum Item<'a> {
+    Tok(Token<'a>),
+    Bracket {
+        open: Token<'a>,
+        items: Vec<Item<'a>>,
+        close: Option<Token<'a>>,
+    },
+}
 
-        known_functions.contains(&upper.as_str())
+impl<'a> Item<'a> {
+    fn first(&self) -> &Token<'a> {
+        match self {
+            Item::Tok(t) => t,
+            Item::Bracket { open, .. } => open,
+        }
     }
 
-    fn is_after_into_tablename(&self, s: &str) -> bool {
-        let trimmed = s.trim_end();
-        let words: Vec<&str> = trimmed.split_whitespace().collect();
+    fn last(&self) -> &Token<'a> {
+        match self {
+            Item::Tok(t) => t,
+            Item::Bracket { open, items, close } => close
+                .as_ref()
+                .or_else(|| items.last().map(|i| i.last()))
+                .unwrap_or(open),
+        }
+    }
 
-        if words.len() >= 3 {
-            let len = words.len();
-            if words[len - 3].to_uppercase() == "INSERT" && words[len - 2].to_uppercase() == "INTO"
-            {
-                return true;
-            }
+    fn tok(&self) -> Option<&Token<'a>> {
+        match self {
+            Item::Tok(t) => Some(t),
+            Item::Bracket { .. } => None,
         }
+    }
 
-        false
+    fn is(&self, keyword: &str) -> bool {
+        self.tok().is_some_and(|t| t.is(keyword))
     }
 
-    pub fn indent_comments_inside_crteate_table(&self, lines: &mut [String]) {
-        info!("Checking for comment indent");
+    fn is_punct(&self, punct: &str) -> bool {
+        self.tok()
+            .is_some_and(|t| t.kind == TokenKind::Punct && t.text == punct)
+    }
+}
 
-        let mut indices: Vec<usize> = Vec::new();
+fn closing_bracket(open: &str) -> Option<&'static str> {
+    match open {
+        "(" => Some(")"),
+        "[" => Some("]"),
+        "{" => Some("}"),
+        _ => None,
+    }
+}
 
-        for (idx, line) in lines.iter().enumerate() {
-            let is_inside_create_table = self.is_inside_create_table_no_position(idx, lines);
+fn build_items<'a>(tokens: Vec<Token<'a>>) -> Vec<Item<'a>> {
+    let mut stack: Vec<(Token<'a>, Vec<Item<'a>>)> = vec![];
+    let mut items: Vec<Item<'a>> = vec![];
 
-            let trimmed_line = line.trim();
-            info!(
-                "COMMENT INDENT: {}\nInside table?: {is_inside_create_table}",
-                trimmed_line
-            );
+    for token in tokens {
+        let is_punct = token.kind == TokenKind::Punct;
 
-            if is_inside_create_table
-                && (trimmed_line.starts_with("--") || trimmed_line.starts_with("//"))
-            {
-                info!("Indenting comment on line {}", idx + 1);
-                indices.push(idx);
-            }
+        if is_punct && closing_bracket(token.text).is_some() {
+            stack.push((token, std::mem::take(&mut items)));
+            continue;
         }
 
-        for i in indices {
-            lines[i].insert_str(0, &" ".repeat(4));
+        let closes_top = is_punct
+            && stack
+                .last()
+                .is_some_and(|(open, _)| closing_bracket(open.text) == Some(token.text));
+
+        if closes_top {
+            let (open, outer) = stack.pop().unwrap();
+            let inner = std::mem::replace(&mut items, outer);
+            items.push(Item::Bracket {
+                open,
+                items: inner,
+                close: Some(token),
+            });
+        } else {
+            items.push(Item::Tok(token));
         }
     }
 
-    pub async fn add_tabs_to_cql_types(&self, lines: &mut Vec<String>, document_url: &Url) {
-        let mut index = 0;
-        let mut in_function_or_aggregate = false;
+    while let Some((open, outer)) = stack.pop() {
+        let inner = std::mem::replace(&mut items, outer);
+        items.push(Item::Bracket {
+            open,
+            items: inner,
+            close: None,
+        });
+    }
 
-        for line in lines.iter_mut() {
-            if line.trim().is_empty() {
-                index += 1;
-                continue;
-            }
+    items
+}
 
-            let trimmed_lower = line.trim().to_lowercase();
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Angle {
+    /* map<text, int> */
+    Type,
+    /* SELECT <uuid>AsBlob(...) */
+    Cast,
+}
 
-            if trimmed_lower.starts_with("create function")
-                || trimmed_lower.starts_with("create or replace function")
-                || trimmed_lower.starts_with("create aggregate")
-                || trimmed_lower.starts_with("create or replace aggregate")
-            {
-                in_function_or_aggregate = true;
-            }
+fn opens_angle(prev: Option<&Token>, token: &Token) -> Option<Angle> {
+    if token.kind != TokenKind::Punct || token.text != "<" {
+        return None;
+    }
 
-            if in_function_or_aggregate {
-                if line.contains(";") {
-                    in_function_or_aggregate = false;
-                }
-                index += 1;
-                continue;
-            }
+    if prev.is_some_and(|p| TYPE_CONSTRUCTORS.iter().any(|c| p.is(c))) {
+        Some(Angle::Type)
+    } else if token.parent.starts_with("cql_types") {
+        Some(Angle::Cast)
+    } else {
+        None
+    }
+}
 
-            let mut found_type = None;
-            for word in line.split_whitespace() {
-                if (CQL_TYPES_LWC
-                    .contains(&word.to_lowercase().replace(",", "").trim().to_string())
-                    || word.starts_with("set")
-                    || word.starts_with("map")
-                    || word.starts_with("list")
-                    || word.starts_with("frozen"))
-                    && !(line.trim().starts_with("--")
-                        || line.trim().starts_with("//")
-                        || self
-                            .is_inside_multiline_comment_no_position(index, &document_url)
-                            .await)
-                {
-                    found_type = Some(word);
-                    break;
-                }
-            }
+/*
+    Comma separated element of a bracket or of a column list,
+    comments on the same line as the comma stay after it and
+    line comments right before the comma move after it.
+*/
+struct Element<'i, 'a> {
+    items: &'i [Item<'a>],
+    comma: Option<&'i Token<'a>>,
+    trailing: Vec<&'i Token<'a>>,
+}
 
-            if let Some(typ) = found_type {
-                if let Some(offset) = line.find(typ) {
-                    if offset > 0 {
-                        if !line[..offset]
-                            .ends_with(&" ".repeat((self.config.type_padding - 1) as usize))
-                        {
-                            line.insert_str(
-                                offset,
-                                &" ".repeat((self.config.type_padding - 1) as usize),
-                            );
-                        }
-                    }
-                }
+fn split_elements<'i, 'a>(items: &'i [Item<'a>]) -> Vec<Element<'i, 'a>> {
+    let mut elements = vec![];
+    let mut angles = 0usize;
+    let mut start = 0;
+    let mut index = 0;
+
+    while index < items.len() {
+        let item = &items[index];
+        let prev = index.checked_sub(1).and_then(|i| items[i].tok());
+
+        if let Some(token) = item.tok() {
+            if opens_angle(prev, token) == Some(Angle::Type) {
+                angles += 1;
+            } else if angles > 0 && token.text == ">" {
+                angles -= 1;
             }
+        }
 
+        if angles > 0 || !item.is_punct(",") {
             index += 1;
+            continue;
+        }
+
+        let comma = item.tok().unwrap();
+        let mut end = index;
+        let mut trailing = vec![];
+
+        /* `v text -- value` and a `,` below it, the comma goes before the comment */
+        if items[start..index]
+            .last()
+            .and_then(|i| i.tok())
+            .is_some_and(|t| t.kind == TokenKind::LineComment)
+        {
+            while end > start && items[end - 1].tok().is_some_and(|t| t.is_comment()) {
+                end -= 1;
+            }
+            trailing.extend(items[end..index].iter().filter_map(|i| i.tok()));
+        }
+
+        let mut next = index + 1;
+        while let Some(Item::Tok(t)) = items.get(next) {
+            if !t.is_comment() || t.row != comma.end_row {
+                break;
+            }
+            trailing.push(t);
+            next += 1;
         }
+
+        elements.push(Element {
+            items: &items[start..end],
+            comma: Some(comma),
+            trailing,
+        });
+        start = next;
+        index = next;
     }
 
-    pub async fn add_tabs_to_function_keywords(&self, lines: &mut Vec<String>, document_url: &Url) {
-        let function_keywords = ["called", "returns", "language"];
+    if start < items.len() {
+        elements.push(Element {
+            items: &items[start..],
+            comma: None,
+            trailing: vec![],
+        });
+    }
 
-        let aggregate_keywords = ["sfunc", "stype", "finalfunc"];
+    elements
+}
 
-        let indent = " ".repeat(4);
+/*
+    Statement kind, e.g. ["create", "table"] or ["update"],
+    `OR REPLACE` and `IF NOT EXISTS` aren't part of it.
+*/
+fn statement_words(items: &[Item]) -> Vec<String> {
+    items
+        .iter()
+        .map_while(|i| i.tok().filter(|t| t.kind == TokenKind::Keyword))
+        .map(|t| t.text.to_lowercase())
+        .filter(|w| w != "or" && w != "replace")
+        .take(2)
+        .collect()
+}
 
-        let mut inside_function = false;
-        let mut inside_aggregate = false;
-        let mut index = 0;
+/*
+    Clause keywords start a new line once the statement doesn't fit.
+*/
+fn clause_starts(items: &[Item], words: &[String]) -> Vec<usize> {
+    let kind = words.first().map(String::as_str).unwrap_or("");
+    let object = words.get(1).map(String::as_str).unwrap_or("");
+    let is_routine = kind == "create" && (object == "function" || object == "aggregate");
 
-        for line in lines.iter_mut() {
-            if line.trim().is_empty() {
-                index += 1;
-                continue;
-            }
+    let mut starts = vec![];
+    let mut seen_dml_clause = false;
 
-            if line.trim().starts_with("--")
-                || line.trim().starts_with("//")
-                || self
-                    .is_inside_multiline_comment_no_position(index, &document_url)
-                    .await
-            {
-                index += 1;
-                continue;
-            }
+    for index in 1..items.len() {
+        let Some(token) = items[index].tok() else {
+            continue;
+        };
+        if token.kind != TokenKind::Keyword && token.kind != TokenKind::Word {
+            continue;
+        }
 
-            let line_lower = line.to_lowercase();
+        let prev = &items[index - 1];
+        let next = items.get(index + 1);
+        let next_is = |w: &str| next.is_some_and(|n| n.is(w));
+
+        let word = token.text.to_lowercase();
+        let starts_clause = match word.as_str() {
+            "from" => !prev.is("delete") && kind != "revoke",
+            "where" | "using" | "having" => true,
+            "set" => kind == "update" && !next.is_some_and(|n| n.is_punct("<")),
+            "values" => kind == "insert",
+            "if" => seen_dml_clause,
+            "order" | "group" => next_is("by") && !prev.is("clustering"),
+            "per" => next_is("partition"),
+            "limit" => !prev.is("partition"),
+            "allow" => next_is("filtering"),
+            "with" => true,
+            "primary" => next_is("key"),
+            "select" => !matches!(kind, "grant" | "revoke" | "list"),
+            "on" => kind == "create" && (object == "index" || object == "search"),
+            "called" | "returns" | "language" => is_routine && object == "function",
+            "sfunc" | "stype" | "finalfunc" | "initcond" => is_routine && object == "aggregate",
+            _ => false,
+        };
 
-            if line_lower.contains("create function")
-                || line_lower.contains("create or replace function")
-            {
-                inside_function = true;
-                inside_aggregate = false;
-            }
+        if !starts_clause || (token.kind != TokenKind::Keyword && word != "limit") {
+            continue;
+        }
 
-            if line_lower.contains("create aggregate")
-                || line_lower.contains("create or replace aggregate")
-            {
-                inside_aggregate = true;
-                inside_function = false;
-            }
+        if matches!(word.as_str(), "where" | "set" | "values") {
+            seen_dml_clause = true;
+        }
 
-            if (inside_function || inside_aggregate) && line.trim().ends_with(";") {
-                let trimmed = line.trim_start();
-                let first_word = trimmed
-                    .split_whitespace()
-                    .next()
-                    .unwrap_or("")
-                    .to_lowercase();
-
-                let keywords = if inside_function {
-                    &function_keywords[..]
-                } else {
-                    &aggregate_keywords[..]
-                };
-
-                if keywords.contains(&first_word.as_str()) {
-                    *line = format!("{}{}", indent, trimmed);
-                }
+        starts.push(index);
+    }
 
-                inside_function = false;
-                inside_aggregate = false;
-                index += 1;
-                continue;
-            }
+    starts
+}
 
-            if inside_function {
-                let trimmed = line.trim_start();
-                let first_word = trimmed
-                    .split_whitespace()
-                    .next()
-                    .unwrap_or("")
-                    .to_lowercase();
+/*
+    Lines of a UDF body without the blank lines around it and without
+    the indentation all of its lines share. The first line may follow
+    the `$$` directly, its own indentation doesn't count.
+*/
+fn body_lines(body: &str) -> Vec<String> {
+    let lines: Vec<&str> = body.lines().map(str::trim_end).collect();
+    let Some(first) = lines.iter().position(|l| !l.is_empty()) else {
+        return vec![];
+    };
+    let last = lines.iter().rposition(|l| !l.is_empty()).unwrap_or(first);
+    let lines = &lines[first..=last];
+
+    let mut common: Option<&str> = None;
+    for line in lines.iter().skip(usize::from(first == 0)) {
+        if line.is_empty() {
+            continue;
+        }
 
-                if function_keywords.contains(&first_word.as_str()) {
-                    *line = format!("{}{}", indent, trimmed);
-                }
+        let indent = &line[..line.len() - line.trim_start().len()];
+        common = Some(match common {
+            None => indent,
+            Some(c) => {
+                let shared = c
+                    .char_indices()
+                    .zip(indent.chars())
+                    .find(|((_, a), b)| a != b)
+                    .map_or(c.len().min(indent.len()), |((i, _), _)| i);
+                &c[..shared]
             }
+        });
+    }
+    let common = common.unwrap_or("");
+
+    lines
+        .iter()
+        .enumerate()
+        .map(|(index, line)| match line.strip_prefix(common) {
+            _ if index == 0 && first == 0 => line.trim_start().to_string(),
+            Some(stripped) => stripped.to_string(),
+            None => line.trim_start().to_string(),
+        })
+        .collect()
+}
 
-            if inside_aggregate {
-                let trimmed = line.trim_start();
-                let first_word = trimmed
-                    .split_whitespace()
-                    .next()
-                    .unwrap_or("")
-                    .to_lowercase();
+/*
+    The UDF bodies of `CREATE FUNCTION` statements with their language,
+    `LANGUAGE java AS $$ ... $$` or `LANGUAGE lua AS '...'`.
+*/
+fn function_bodies<'t, 'a>(tokens: &'t [Token<'a>]) -> Vec<(String, &'t Token<'a>)> {
+    let mut bodies = vec![];
 
-                if aggregate_keywords.contains(&first_word.as_str()) {
-                    *line = format!("{}{}", indent, trimmed);
-                }
-            }
+    for (index, token) in tokens.iter().enumerate() {
+        if !token.is("language") || !tokens.get(index + 2).is_some_and(|t| t.is("as")) {
+            continue;
+        }
 
-            index += 1;
+        let language = tokens[index + 1].text.to_lowercase();
+        let body = match &tokens[index + 3..] {
+            [open, body, ..] if open.text == "$$" && body.parent == "code_block" => body,
+            [body, ..] if body.parent == "string_body" => body,
+            _ => continue,
+        };
+
+        bodies.push((language, body));
+    }
+
+    bodies
+}
+
+/*
+    Pipes a UDF body through the command configured for its language,
+    `None` when the command fails, the body is then kept as it is.
+*/
+async fn run_external_formatter(command: &str, body: &str) -> Option<String> {
+    /* quoted like a shell would, but no shell runs it */
+    let Some(args) = shlex::split(command).filter(|args| !args.is_empty()) else {
+        error!("Failed to parse `{command}`");
+        return None;
+    };
+
+    let mut child = Command::new(&args[0])
+        .args(&args[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| error!("Failed to run `{command}`: {e}"))
+        .ok()?;
+
+    let mut stdin = child.stdin.take()?;
+    let input = body.to_string();
+    let writer = tokio::spawn(async move {
+        let _ = stdin.write_all(input.as_bytes()).await;
+    });
+
+    let output = tokio::time::timeout(Duration::from_secs(10), child.wait_with_output())
+        .await
+        .map_err(|_| error!("`{command}` timed out"))
+        .ok()?
+        .ok()?;
+    let _ = writer.await;
+
+    if !output.status.success() {
+        error!(
+            "`{command}` exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return None;
+    }
+
+    String::from_utf8(output.stdout).ok()
+}
+
+/*
+    `WITH ... AND ...` of tables, views and keyspaces
+*/
+fn has_table_options(words: &[String]) -> bool {
+    let kind = words.first().map(String::as_str).unwrap_or("");
+    let object = words.get(1).map(String::as_str).unwrap_or("");
+
+    matches!(kind, "create" | "alter")
+        && matches!(
+            object,
+            "table" | "columnfamily" | "keyspace" | "materialized" | "schema"
+        )
+}
+
+/*
+    Splits a clause before every `AND`, `WHERE a = 1` `AND b = 2`
+*/
+fn split_on_and<'i, 'a>(items: &'i [Item<'a>]) -> Vec<&'i [Item<'a>]> {
+    let mut parts = vec![];
+    let mut start = 0;
+
+    for (index, item) in items.iter().enumerate().skip(1) {
+        if item.is("and") {
+            parts.push(&items[start..index]);
+            start = index;
         }
     }
+    parts.push(&items[start..]);
 
-    pub async fn add_tabs_to_args(&self, lines: &mut Vec<String>, document_url: &Url) {
-        let mut indices: Vec<usize> = Vec::new();
-
-        for (idx, line) in lines.iter().enumerate() {
-            let trimmed = line.trim_start();
-            let trimmed_lower = trimmed.to_lowercase();
-
-            let is_inside_multiline_comment =
-                self.is_line_in_multiline_comment(trimmed, idx, lines);
-            let is_arg = self.is_line_inside_init_args(trimmed, idx, lines);
-            let is_selector = self.is_line_inside_selectors(trimmed, idx, lines);
-            let is_ml_comment_clause = self.is_multi_line_comment_clause(trimmed);
-            let is_pk_line = trimmed_lower.starts_with("primary");
-
-            let is_in_create_table = self.is_inside_create_table_no_position(idx, lines);
-            let is_in_create_type = self
-                .is_inside_create_type_no_position(idx, document_url)
-                .await;
-            let is_inside_curly_bracers =
-                self.is_inside_curly_braces_block(idx, document_url).await;
-
-            let banned_starts = [
-                "create table",
-                "create type",
-                "alter table",
-                "alter",
-                "select",
-                "as",
-                "on",
-                "where",
-                "with",
-                "--",
-                "//",
-                "/*",
-                "*/",
-            ];
-
-            let starts_with_banned = banned_starts.iter().any(|p| {
-                if trimmed_lower.starts_with(p) {
-                    trimmed_lower.len() == p.len()
-                        || !trimmed_lower[p.len()..]
-                            .starts_with(|c: char| c.is_alphanumeric() || c == '_')
-                } else {
-                    false
-                }
-            });
+    parts
+}
 
-            if is_inside_multiline_comment && !is_ml_comment_clause && !starts_with_banned {
-                indices.push(idx);
-                continue;
+struct Printer {
+    max_width: usize,
+    indent: usize,
+    type_padding: usize,
+    keyword_case: Case,
+    type_case: Case,
+    function_case: Case,
+    /* UDF bodies replaced by the external formatter, keyed by their start byte */
+    bodies: HashMap<usize, String>,
+}
+
+impl Printer {
+    fn separator<'a>(
+        &self,
+        items: &[Item<'a>],
+        index: usize,
+        angles: &[Angle],
+        closed_angle: Option<Angle>,
+        bind_marker: bool,
+    ) -> Doc {
+        let prev = items[index - 1].last();
+        let item = &items[index];
+        let next = item.first();
+        let space = text(" ");
+
+        if prev.kind == TokenKind::LineComment {
+            return hardline();
+        }
+
+        if next.is_comment() || prev.kind == TokenKind::BlockComment {
+            return if next.row > prev.end_row {
+                hardline()
+            } else {
+                space
+            };
+        }
+
+        /* `'it''s'` is lexed as two literals, a space would change its value */
+        let glued = prev.end == next.start;
+        if glued && prev.kind == TokenKind::Word && next.kind == TokenKind::Word {
+            return nil();
+        }
+
+        let dollar_body = |a: &Token, b: &Token| {
+            a.text == "$$" && (b.kind == TokenKind::Verbatim || b.text == "$$")
+        };
+        if dollar_body(prev, next) || (prev.kind == TokenKind::Verbatim && next.text == "$$") {
+            return nil();
+        }
+
+        if next.kind == TokenKind::Punct {
+            match next.text {
+                "," | ";" | ")" | "]" | "}" | "." => return nil(),
+                ":" if prev.kind == TokenKind::Word || [")", "]", "}"].contains(&prev.text) => {
+                    return nil();
+                }
+                "<" if matches!(opens_angle(Some(prev), next), Some(Angle::Type)) => return nil(),
+                ">" if !angles.is_empty() => return nil(),
+                "[" if prev.kind == TokenKind::Word || [")", "]"].contains(&prev.text) => {
+                    return nil();
+                }
+                "(" if Self::is_call(items, index - 1) => return nil(),
+                _ => {}
             }
+        }
 
-            if is_inside_curly_bracers && !starts_with_banned {
-                indices.push(idx);
-                continue;
+        if prev.kind == TokenKind::Punct {
+            match prev.text {
+                "(" | "[" | "{" | "." => return nil(),
+                ":" if bind_marker => return nil(),
+                "<" if !angles.is_empty() => return nil(),
+                ">" if closed_angle == Some(Angle::Cast) => return nil(),
+                _ => {}
             }
+        }
+
+        space
+    }
+
+    fn is_call(items: &[Item], index: usize) -> bool {
+        let Some(Item::Bracket { open, .. }) = items.get(index + 1) else {
+            return false;
+        };
+        let Item::Tok(name) = &items[index] else {
+            return false;
+        };
 
-            let is_arg_context = (is_arg || is_selector || (is_pk_line && is_in_create_table))
-                && !is_inside_multiline_comment
-                && !is_ml_comment_clause;
+        /*
+            Decided from the text of the name alone, whether it's a keyword or
+            an identifier depends on how the parser recovered from errors
+            earlier in the file. Keywords that are never calls keep their space,
+            `VALUES (`, `IN (`, `KEY (`, ...
+        */
+        let lower = name.text.to_lowercase();
+        let is_name = matches!(name.kind, TokenKind::Keyword | TokenKind::Word)
+            && name
+                .text
+                .starts_with(|c: char| c.is_alphabetic() || c == '_' || c == '"');
+        open.text == "("
+            && is_name
+            && (CALL_KEYWORDS.contains(&lower.as_str())
+                || !(CQL_RESERVED_KEYWORDS_LWC.contains(&lower)
+                    || NOT_CALL_KEYWORDS.contains(&lower.as_str())))
+            && !Self::names_object(items, index + 1)
+    }
 
-            if is_arg_context && (is_in_create_table || is_in_create_type) && !starts_with_banned {
-                indices.push(idx);
+    /*
+        `INTO users (`, `TABLE ks.t (` vs. function calls like `toJson(`
+    */
+    fn names_object(items: &[Item], index: usize) -> bool {
+        let mut before = index;
+
+        while before > 0 {
+            let item = &items[before - 1];
+            let part_of_name = item.is_punct(".")
+                || item
+                    .tok()
+                    .is_some_and(|t| t.kind == TokenKind::Word && !t.is_comment());
+            if !part_of_name {
+                break;
             }
+            before -= 1;
         }
 
-        for i in indices {
-            lines[i].insert_str(0, &" ".repeat(self.config.indent as usize));
+        /* function signatures read like calls, `FUNCTION IF NOT EXISTS ks.f(int)` */
+        let mut object = before;
+        while object > 0
+            && ["if", "not", "exists"]
+                .iter()
+                .any(|k| items[object - 1].is(k))
+        {
+            object -= 1;
+        }
+        if object > 0 && (items[object - 1].is("function") || items[object - 1].is("aggregate")) {
+            return false;
         }
+
+        before > 0 && OBJECT_KEYWORDS.iter().any(|k| items[before - 1].is(k))
     }
 
-    pub fn fix_string_literals(&self, lines: &mut Vec<String>) {
-        for line in lines.iter_mut() {
-            let mut position = 0;
-            while position < line.len() {
-                if let Some(start) = line[position..].find('"').map(|p| p + position) {
-                    if let Some(end) = line[start + 1..].find('"').map(|p| p + start + 1) {
-                        let str = String::from(&line[start + 1..end]);
-                        let trimmed = str.trim();
-                        line.replace_range(start + 1..end, trimmed);
-                        position = end + 1;
-                    } else {
-                        position = start + 1;
-                    }
-                } else if let Some(start) = line[position..].find('\'').map(|p| p + position) {
-                    if let Some(end) = line[start + 1..].find('\'').map(|p| p + start + 1) {
-                        let str = String::from(&line[start + 1..end]);
-                        let trimmed = str.trim();
-                        line.replace_range(start + 1..end, trimmed);
-                        position = end + 1;
-                    } else {
-                        position = start + 1;
+    fn inline(&self, items: &[Item]) -> Doc {
+        let mut docs = vec![];
+        let mut angles: Vec<Angle> = vec![];
+        let mut closed_angle = None;
+        let mut bind_marker = false;
+
+        for (index, item) in items.iter().enumerate() {
+            if index > 0 {
+                docs.push(self.separator(items, index, &angles, closed_angle, bind_marker));
+            }
+
+            closed_angle = None;
+            bind_marker = false;
+
+            match item {
+                Item::Tok(token) => {
+                    let prev = index.checked_sub(1).map(|i| items[i].last());
+                    let in_type = angles.last() == Some(&Angle::Type);
+                    docs.push(self.cased(items, index, in_type));
+
+                    if let Some(angle) = opens_angle(prev, token) {
+                        angles.push(angle);
+                    } else if token.text == ">" && token.kind == TokenKind::Punct {
+                        closed_angle = angles.pop();
+                    } else if token.text == ":" && token.kind == TokenKind::Punct {
+                        bind_marker = !prev.is_some_and(|p| {
+                            p.kind == TokenKind::Word || [")", "]", "}"].contains(&p.text)
+                        });
                     }
-                } else {
-                    break;
+                }
+                Item::Bracket { open, items, close } => {
+                    docs.push(self.bracket(open, items, close.as_ref()));
                 }
             }
         }
+
+        concat(docs)
     }
 
     /*
-    Removes spaces before ;
+        `fmt { keyword_case, type_case, function_case }`, quoted names are never touched
     */
-    pub fn fix_semi_colon(&self, lines: &mut Vec<String>) {
-        let mut index = 0;
+    fn cased(&self, items: &[Item], index: usize, in_type: bool) -> Doc {
+        let Item::Tok(token) = &items[index] else {
+            return nil();
+        };
+        let lower = token.text.to_lowercase();
+        let next_is_angle = items.get(index + 1).is_some_and(|i| i.is_punct("<"));
+
+        let case = if Self::is_call(items, index) {
+            self.function_case
+        } else if token.kind == TokenKind::Keyword
+            && CQL_TYPES_LWC.contains(&lower)
+            && (token.parent.starts_with("cql_types") || in_type || next_is_angle)
+        {
+            self.type_case
+        } else if token.kind == TokenKind::Keyword
+            || (token.kind == TokenKind::Word && CQL_RESERVED_KEYWORDS_LWC.contains(&lower))
+        {
+            self.keyword_case
+        } else {
+            Case::Preserve
+        };
 
-        while index < lines.len() {
-            self.remove_leading_spaces_wildcards(&mut lines[index]);
-            self.remove_tailing_spaces_wildcards(&mut lines[index]);
-            index += 1;
+        if case == Case::Preserve || token.text.starts_with(['"', '\'']) {
+            return self.token(token);
+        }
+
+        text(case.apply(token.text))
+    }
+
+    fn token(&self, token: &Token) -> Doc {
+        if token.kind == TokenKind::LineComment {
+            concat(vec![text(token.text), break_parent()])
+        } else {
+            text(token.text)
+        }
+    }
+
+    fn element(&self, element: &Element, content: Doc) -> Doc {
+        let mut docs = vec![content];
+
+        if let Some(comma) = element.comma {
+            docs.push(text(comma.text));
+        }
+
+        for comment in &element.trailing {
+            docs.push(text(" "));
+            docs.push(self.token(comment));
         }
+
+        concat(docs)
     }
 
     /*
-    Removes duplicates of ;
+        `(KEYS(todo))`, the target of an index is glued to its column
     */
-    pub fn fix_duplicate_semi_colon(&self, line: &mut String) {
-        let mut chars: Vec<char> = line.chars().collect();
-        let mut last_colon = false;
-        let mut index = 0;
-
-        while index < chars.len() {
-            let current_char = chars[index];
-
-            if !last_colon && current_char == ';' {
-                last_colon = true;
-            } else if last_colon && current_char == ';' {
-                chars.remove(index);
-                last_colon = false;
-                if index >= 2 {
-                    index -= 2;
-                } else if index > 0 {
-                    index -= 1;
-                }
-                continue;
-            } else if current_char != ';' {
-                last_colon = false;
+    fn index_target(&self, items: &[Item]) -> Doc {
+        match items {
+            [Item::Tok(target), column @ Item::Bracket { .. }, rest @ ..]
+                if INDEX_TARGETS.iter().any(|t| target.is(t)) =>
+            {
+                let column = std::slice::from_ref(column);
+                let rest = self.inline(rest);
+                let target = text(self.keyword_case.apply(target.text));
+                concat(vec![target, self.inline(column), rest])
             }
-            index += 1;
+            _ => self.inline(items),
+        }
+    }
+
+    fn bracket(&self, open: &Token, items: &[Item], close: Option<&Token>) -> Doc {
+        let close = close.map_or(nil(), |c| text(c.text));
+
+        if items.is_empty() {
+            return concat(vec![text(open.text), close]);
         }
 
-        *line = chars.into_iter().collect();
+        let elements: Vec<Doc> = split_elements(items)
+            .iter()
+            .map(|e| self.element(e, self.index_target(e.items)))
+            .collect();
+
+        group(concat(vec![
+            text(open.text),
+            nest(
+                self.indent,
+                concat(vec![softline(), join(elements, line())]),
+            ),
+            softline(),
+            close,
+        ]))
     }
 
-    pub fn fix_spacing(&self, line: &mut String) {
-        let mut chars: Vec<char> = line.chars().collect();
-        let mut last_space = false;
-        let mut index = 0;
+    /*
+        Comments before the statement and after its `;` are printed outside
+        of the group, a line comment would otherwise break the whole statement.
+    */
+    fn statement(&self, items: &[Item]) -> Doc {
+        let body_start = items
+            .iter()
+            .position(|i| !i.first().is_comment())
+            .unwrap_or(items.len());
+        let mut body_end = items.len();
+        while body_end > body_start && items[body_end - 1].first().is_comment() {
+            body_end -= 1;
+        }
+        if body_end > body_start && !items[body_end - 1].is_punct(";") {
+            body_end = items.len();
+        }
 
-        while index < chars.len() {
-            let current_char = chars[index];
+        let comment = |index: usize| (index, self.token(items[index].first()));
+        let mut pieces: Vec<(usize, Doc)> = (0..body_start).map(comment).collect();
+        if body_start < body_end {
+            pieces.push((
+                body_start,
+                self.statement_body(&items[body_start..body_end]),
+            ));
+        }
+        pieces.extend((body_end..items.len()).map(comment));
 
-            if !last_space && current_char == ' ' {
-                last_space = true;
-            } else if last_space && current_char == ' ' {
-                chars.remove(index);
-                last_space = false;
-                if index >= 2 {
-                    index -= 2;
-                } else if index > 0 {
-                    index -= 1;
-                }
-                continue;
-            } else if current_char != ' ' {
-                last_space = false;
+        let mut docs = vec![];
+        for (index, doc) in pieces {
+            if index > 0 {
+                docs.push(self.separator(items, index, &[], None, false));
             }
-            index += 1;
+            docs.push(doc);
         }
 
-        *line = chars.into_iter().collect();
+        concat(docs)
     }
 
-    pub fn fix_new_lines(&self, lines: &mut Vec<String>) {
-        let mut index = 0;
-        let mut last_new_line = false;
-        let mut last_bracket = false;
+    fn statement_body(&self, items: &[Item]) -> Doc {
+        let words = statement_words(items);
+        let kind = words.first().map(String::as_str).unwrap_or("");
+        let object = words.get(1).map(String::as_str).unwrap_or("");
 
-        while index < lines.len() {
-            if last_new_line && lines[index].len() == 0 {
-                lines.remove(index);
-                if index >= 2 {
-                    index -= 2;
-                } else if index > 0 {
-                    index -= 1;
-                }
-            }
+        if kind == "begin" {
+            return self.batch(items);
+        }
 
-            if last_bracket && lines[index].len() == 0 {
-                lines.remove(index);
-                if index >= 2 {
-                    index -= 2;
-                } else if index > 0 {
-                    index -= 1;
-                }
-            }
+        if kind == "create"
+            && (object == "table" || object == "type" || object == "columnfamily")
+            && let Some(doc) = self.create_table(items, &words)
+        {
+            return doc;
+        }
 
-            if lines[index].len() == 0
-                && !self.is_line_in_multiline_comment(&lines[index], index, lines)
-            {
-                last_new_line = true;
-            } else {
-                last_new_line = false;
-            }
+        let indent = if kind == "create" && (object == "function" || object == "aggregate") {
+            self.indent
+        } else {
+            0
+        };
 
-            if lines[index].contains("(") {
-                last_bracket = true;
-            } else {
-                last_bracket = false
-            }
+        group(self.clauses(items, &words, indent))
+    }
 
-            index += 1;
+    fn clauses(&self, items: &[Item], words: &[String], indent: usize) -> Doc {
+        let mut starts = clause_starts(items, words);
+        starts.push(items.len());
+
+        let mut docs = vec![self.clause(&items[..starts[0]], words)];
+        for pair in starts.windows(2) {
+            docs.push(nest(
+                indent,
+                concat(vec![line(), self.clause(&items[pair[0]..pair[1]], words)]),
+            ));
         }
+
+        concat(docs)
     }
 
     /*
-        Removes all '\n' inside code_blocks
+        A clause that doesn't fit breaks on its own, the rest of the statement
+        stays as it is:
+
+        SELECT
+            id,
+            name
+        FROM ks.t
+        WHERE a = 1
+            AND b = 2
     */
-    pub fn remove_new_lines_from_code_block(&self, lines: &mut Vec<String>) {
-        let mut index = 0;
-        let mut inside_code_block = false;
+    fn clause(&self, items: &[Item], words: &[String]) -> Doc {
+        let Some(head) = items.first() else {
+            return nil();
+        };
 
-        while index < lines.len() {
-            let line = lines[index].to_lowercase();
+        if head.is("select") {
+            let modifiers = items[1..]
+                .iter()
+                .take_while(|i| i.is("json") || i.is("distinct"))
+                .count();
+            let (head, selectors) = items.split_at(1 + modifiers);
 
-            if !inside_code_block && line.len() > 0 && !line.contains(";") {
-                inside_code_block = true;
+            if selectors.is_empty() {
+                return self.inline(head);
             }
 
-            if inside_code_block && line.contains(";") {
-                inside_code_block = false;
-            }
+            let selectors: Vec<Doc> = split_elements(selectors)
+                .iter()
+                .map(|e| self.element(e, self.inline(e.items)))
+                .collect();
 
-            if inside_code_block
-                && line.len() == 0
-                && !self.is_line_in_multiline_comment(&line, index, lines)
-            {
-                lines.remove(index);
-                if index >= 2 {
-                    index -= 2;
-                } else if index > 0 {
-                    index -= 1;
-                }
-            }
+            return group(concat(vec![
+                self.inline(head),
+                nest(self.indent, concat(vec![line(), join(selectors, line())])),
+            ]));
+        }
 
-            index += 1;
+        if head.is("language")
+            && let Some(doc) = self.function_body(items)
+        {
+            return doc;
+        }
+
+        if head.is("set") && words.first().is_some_and(|w| w == "update") {
+            let assignments: Vec<Doc> = split_elements(&items[1..])
+                .iter()
+                .map(|e| self.element(e, self.inline(e.items)))
+                .collect();
+
+            return group(concat(vec![
+                self.inline(&items[..1]),
+                nest(self.indent, concat(vec![line(), join(assignments, line())])),
+            ]));
+        }
+
+        if head.is("with") && has_table_options(words) {
+            return self.table_options(items);
+        }
+
+        if ["where", "if", "with"].iter().any(|h| head.is(h)) {
+            let mut parts = split_on_and(items).into_iter().map(|p| self.inline(p));
+            let first = parts.next().unwrap_or_else(nil);
+            let rest: Vec<Doc> = parts.map(|p| concat(vec![line(), p])).collect();
+
+            return group(concat(vec![first, nest(self.indent, concat(rest))]));
         }
+
+        self.inline(items)
     }
 
     /*
-        Adds missing semi colon to the and of CQL command
+        LANGUAGE java
+        AS $$
+            return a + b;
+        $$;
 
-        The list of Keywords that start CQL commands is strored inside
-        CQL_KEYWORDS_LWC | LWC - lower_case
+        Multi line bodies keep their own indentation relative to each other,
+        the whole body is moved one `indent` in from the statement.
     */
-    pub fn apply_semi_colon(&self, lines: &mut Vec<String>) {
-        let mut index = 0;
-
-        while index < lines.len() {
-            let line = lines[index].to_lowercase();
-
-            if index + 1 != lines.len()
-                && line.len() > 0
-                && !line.contains(";")
-                && !line.contains("begin")
-                && !line.contains("//")
-                && !line.contains("--")
-                && !line.contains("/*")
-                && !line.contains("*/")
-                && !line.ends_with("as")
-                && !line.ends_with("with")
-                && !self.is_line_in_multiline_comment(&line, index, lines)
+    fn function_body(&self, items: &[Item]) -> Option<Doc> {
+        let as_index = items.iter().position(|i| i.is("as"))?;
+        let (open, body, close, after) = match &items[as_index + 1..] {
+            [Item::Tok(open), Item::Tok(body), Item::Tok(close), ..]
+                if open.text == "$$" && body.parent == "code_block" =>
             {
-                let lw = lines[index + 1].to_lowercase();
-                let split: Vec<&str> = lw.split(' ').collect();
-                if lines[index + 1].to_lowercase().len() == 0
-                    || CQL_KEYWORDS_LWC.contains(&split[0].to_string())
-                {
-                    lines[index].push(';');
-                }
+                (open.text, body, close.text, as_index + 4)
             }
+            [Item::Tok(body), ..] if body.parent == "string_body" => ("'", body, "'", as_index + 2),
+            _ => return None,
+        };
 
-            if index == lines.len() - 1
-                && line.len() > 0
-                && !line.contains(";")
-                && !line.contains("begin")
-                && !line.contains("//")
-                && !line.contains("--")
-                && !line.contains("/*")
-                && !line.contains("*/")
-                && !line.ends_with("as")
-                && !line.ends_with("with")
-                && !self.is_line_in_multiline_comment(&line, index, lines)
-            {
-                lines[index].push(';');
-            }
+        let content = match self.bodies.get(&body.start) {
+            Some(formatted) => formatted.as_str(),
+            None if open == "$$" => body.text,
+            None => &body.text[1..body.text.len() - 1],
+        };
+        if !self.bodies.contains_key(&body.start) && !content.contains('\n') {
+            return None;
+        }
 
-            index += 1;
+        let lines: Vec<Doc> = body_lines(content).into_iter().map(text).collect();
+        let mut docs = vec![
+            self.inline(&items[..=as_index]),
+            text(" "),
+            text(open),
+            nest(
+                self.indent,
+                concat(vec![hardline(), join(lines, hardline())]),
+            ),
+            hardline(),
+            text(close),
+        ];
+        if after < items.len() {
+            docs.push(self.separator(items, after, &[], None, false));
+            docs.push(self.inline(&items[after..]));
         }
+
+        Some(concat(docs))
     }
 
-    pub fn add_spacing_new_lines(&self, lines: &mut Vec<String>) {
-        let mut index = 0;
+    /*
+        WITH compaction = {
+            'class':                 'SizeTieredCompactionStrategy',
+            'max_threshold':         32
+        }
+            AND comment = 'users';
 
-        while index < lines.len() {
-            if index + 1 != lines.len()
-                && (lines[index].contains(";") || lines[index].to_lowercase().contains("begin"))
-                && lines[index + 1].len() > 0
-            {
-                lines.insert(index + 1, "".to_string());
+        Only the AND lines are indented, so a map is nested relative to
+        the line its option starts on.
+    */
+    fn table_options(&self, items: &[Item]) -> Doc {
+        let mut options = split_on_and(items)
+            .into_iter()
+            .map(|option| self.option(option));
+        let Some(first) = options.next() else {
+            return nil();
+        };
+        let rest: Vec<Doc> = options.flat_map(|option| [hardline(), option]).collect();
+        if rest.is_empty() {
+            return first;
+        }
+
+        concat(vec![first, nest(self.indent, concat(rest))])
+    }
+
+    fn option(&self, items: &[Item]) -> Doc {
+        let Some(map) = items
+            .iter()
+            .position(|i| matches!(i, Item::Bracket { open, .. } if open.text == "{"))
+        else {
+            return self.inline(items);
+        };
+        let Item::Bracket {
+            open,
+            items: entries,
+            close,
+        } = &items[map]
+        else {
+            return self.inline(items);
+        };
+        let Some(aligned) = self.aligned_map(open, entries, close.as_ref()) else {
+            return self.inline(items);
+        };
+
+        let mut docs = vec![self.inline(&items[..map])];
+        if map > 0 {
+            docs.push(self.separator(items, map, &[], None, false));
+        }
+        docs.push(aligned);
+        if map + 1 < items.len() {
+            docs.push(self.separator(items, map + 1, &[], None, false));
+            docs.push(self.inline(&items[map + 1..]));
+        }
+
+        concat(docs)
+    }
+
+    /*
+        Option maps get one entry per line with the values aligned,
+        the same way `type_padding` aligns column types.
+    */
+    fn aligned_map(&self, open: &Token, items: &[Item], close: Option<&Token>) -> Option<Doc> {
+        let elements = split_elements(items);
+        if elements.len() < 2 {
+            return None;
+        }
+
+        let mut entries = vec![];
+        for element in &elements {
+            let colon = element.items.iter().position(|i| i.is_punct(":"))?;
+            let key = &element.items[..colon];
+            if colon == 0 || key.iter().any(|i| i.first().is_comment()) {
+                return None;
             }
 
-            index += 1;
+            let width = self.inline(key).pretty(usize::MAX).chars().count();
+            entries.push((element, colon, width));
         }
+
+        let key_width = entries.iter().map(|(_, _, w)| *w).max().unwrap_or(0);
+        let lines: Vec<Doc> = entries
+            .into_iter()
+            .map(|(element, colon, width)| {
+                let content = concat(vec![
+                    self.inline(&element.items[..=colon]),
+                    text(" ".repeat(self.type_padding + key_width - width)),
+                    self.inline(&element.items[colon + 1..]),
+                ]);
+                self.element(element, content)
+            })
+            .collect();
+
+        Some(concat(vec![
+            text(open.text),
+            nest(
+                self.indent,
+                concat(vec![hardline(), join(lines, hardline())]),
+            ),
+            hardline(),
+            close.map_or(nil(), |c| text(c.text)),
+        ]))
     }
 
-    pub fn add_new_line_before_pk(&self, lines: &mut Vec<String>) {
-        let mut index = lines.len() - 1;
+    /*
+        Column definitions are always one per line with aligned types,
+        see `type_padding` in the README.
+    */
+    fn create_table(&self, items: &[Item], words: &[String]) -> Option<Doc> {
+        let list = items
+            .iter()
+            .position(|i| matches!(i, Item::Bracket { open, .. } if open.text == "("))?;
+        let Item::Bracket {
+            open,
+            items: columns,
+            close,
+        } = &items[list]
+        else {
+            return None;
+        };
 
-        let mut indicies: Vec<usize> = Vec::new();
+        let elements = split_elements(columns);
+        let is_constraint = |e: &Element| {
+            let mut content = e.items.iter().filter(|i| !i.first().is_comment());
+            content.next().is_some_and(|i| i.is("primary"))
+        };
+        let name_of = |e: &Element| e.items.iter().position(|i| !i.first().is_comment());
+
+        let name_width = elements
+            .iter()
+            .filter(|e| !is_constraint(e))
+            .filter_map(|e| name_of(e).map(|n| e.items[n].first().text.chars().count()))
+            .max()
+            .unwrap_or(0);
+
+        let mut lines = vec![];
+        for (index, element) in elements.iter().enumerate() {
+            let content = match name_of(element) {
+                Some(name) if !is_constraint(element) && name + 1 < element.items.len() => {
+                    let width = element.items[name].first().text.chars().count();
+                    concat(vec![
+                        self.inline(&element.items[..=name]),
+                        text(" ".repeat(self.type_padding + name_width - width)),
+                        self.inline(&element.items[name + 1..]),
+                    ])
+                }
+                _ => self.inline(element.items),
+            };
 
-        while index > 0 {
-            if index - 1 != 0
-                && (lines[index - 1]
-                    .trim_start()
-                    .to_lowercase()
-                    .starts_with("primary"))
-            {
-                indicies.push(index - 1);
+            if index > 0 {
+                lines.push(hardline());
+                if is_constraint(element) {
+                    lines.push(hardline());
+                }
             }
+            lines.push(self.element(element, content));
+        }
 
-            index -= 1;
+        let close = close.as_ref().map_or(nil(), |c| text(c.text));
+        let columns = concat(vec![
+            text(open.text),
+            nest(self.indent, concat(vec![hardline(), concat(lines)])),
+            hardline(),
+            close,
+        ]);
+
+        let rest = &items[list + 1..];
+        let mut starts = clause_starts(rest, words);
+        if starts.first() != Some(&0) && rest.first().is_some_and(|i| i.is("with")) {
+            starts.insert(0, 0);
         }
+        starts.push(rest.len());
 
-        for index in indicies.iter() {
-            lines.insert(*index, "".to_string());
+        let mut docs = vec![self.inline(&items[..list]), text(" "), columns];
+        docs.push(self.inline(&rest[..starts[0]]));
+        for pair in starts.windows(2) {
+            docs.push(line());
+            docs.push(self.clause(&rest[pair[0]..pair[1]], words));
         }
+
+        Some(group(concat(docs)))
     }
 
-    pub fn add_spacing_after_comma(&self, lines: &mut Vec<String>) {
-        for line in lines.iter_mut() {
-            let mut chars: Vec<char> = line.chars().collect();
-            let mut idx = 0;
+    fn batch(&self, items: &[Item]) -> Doc {
+        let body_start = items
+            .iter()
+            .position(|i| i.is("insert") || i.is("update") || i.is("delete"))
+            .unwrap_or(items.len());
+        let apply = items
+            .iter()
+            .rposition(|i| i.is("apply"))
+            .filter(|a| *a >= body_start)
+            .unwrap_or(items.len());
+
+        let body: Vec<Doc> = split_statements(&items[body_start..apply])
+            .into_iter()
+            .map(|statement| concat(vec![hardline(), self.statement(statement)]))
+            .collect();
+
+        let mut docs = vec![
+            self.inline(&items[..body_start]),
+            nest(self.indent, concat(body)),
+        ];
+        if apply < items.len() {
+            docs.push(hardline());
+            docs.push(self.inline(&items[apply..]));
+        }
 
-            while idx < chars.len() {
-                if idx + 1 < chars.len() && chars[idx] == ',' && chars[idx + 1] != ' ' {
-                    chars.insert(idx + 1, ' ');
-                }
-                idx += 1;
-            }
+        concat(docs)
+    }
+}
+
+/*
+    Splits on `;`, comments on the same line as the `;` stay with its statement.
+*/
+fn split_statements<'i, 'a>(items: &'i [Item<'a>]) -> Vec<&'i [Item<'a>]> {
+    let mut statements = vec![];
+    let mut start = 0;
+    let mut index = 0;
+
+    while index < items.len() {
+        if !items[index].is_punct(";") {
+            index += 1;
+            continue;
+        }
 
-            *line = chars.into_iter().collect();
+        let row = items[index].last().end_row;
+        index += 1;
+        while items
+            .get(index)
+            .is_some_and(|i| i.first().is_comment() && i.first().row == row)
+        {
+            index += 1;
         }
+
+        statements.push(&items[start..index]);
+        start = index;
     }
 
-    /*
-        Hate this shit だよ xD
-        Formats select statements in the following manner
+    if start < items.len() {
+        statements.push(&items[start..]);
+    }
 
-        SELECT
-        selector1,
-        selector2,
-        selector3,
-        ...,
-        selectorN,
-        FROM [keyspace_name].table_name;
-    */
-    pub fn format_selectors(&self, lines: &mut Vec<String>) {
-        let mut index = 0;
-        let mut working_buf = Vec::<String>::new();
+    statements
+}
 
-        while index < lines.len() {
-            let lw = lines[index].to_lowercase();
-            if lw.contains("select") && self.should_edit_select_statement(&lines[index], lines) {
-                working_buf.clear();
+enum Chunk<'a> {
+    Comment(Token<'a>),
+    Statement(Vec<Token<'a>>),
+}
 
-                let mut idx = index;
+impl Chunk<'_> {
+    fn first_row(&self) -> usize {
+        match self {
+            Chunk::Comment(t) => t.row,
+            Chunk::Statement(ts) => ts[0].row,
+        }
+    }
 
-                while idx < lines.len() {
-                    if !lines[idx].to_lowercase().contains("from") && lines[idx].contains(";") {
-                        return;
-                    }
+    fn last_row(&self) -> usize {
+        match self {
+            Chunk::Comment(t) => t.end_row,
+            Chunk::Statement(ts) => ts[ts.len() - 1].end_row,
+        }
+    }
+}
 
-                    if lines[idx].to_lowercase().contains("from") {
-                        let split: Vec<&str> = lines[idx].split(' ').collect();
-                        for sp in split.into_iter() {
-                            if sp.to_lowercase() == "from" {
-                                break;
-                            }
-                            if sp.to_lowercase() != "select" {
-                                let mut retained = sp.to_string();
-                                retained.retain(|c| c != '\n' && c != '\r');
-                                retained.push('\n');
-                                working_buf.push(retained);
-                            }
-                        }
-
-                        let from_pos = lines[idx].to_lowercase().rfind("from").unwrap();
-                        working_buf.push(lines[idx][from_pos..].to_string());
-                        break;
-                    }
+/* keywords that start a statement, where a `;` is missing the next line starting with one ends the statement before it */
+const STATEMENT_STARTS: [&str; 12] = [
+    "select", "insert", "update", "delete", "create", "alter", "drop", "truncate", "use", "grant",
+    "revoke", "begin",
+];
+
+/*
+    Whether `token` starts a new statement although `statement` has no `;` yet:
+    it's the first word of its line and nothing it continues, like
+    `ALTER TABLE t` + `DROP c` or `CREATE MATERIALIZED VIEW v AS` + `SELECT ...`.
+*/
+fn starts_statement(statement: &[Token], token: &Token) -> bool {
+    let Some(prev) = statement.last() else {
+        return false;
+    };
+    if token.row <= prev.end_row || !STATEMENT_STARTS.iter().any(|k| token.is(k)) {
+        return false;
+    }
+    if prev.is("as") || prev.is("grant") || prev.is("revoke") {
+        return false;
+    }
 
-                    let split: Vec<&str> = lines[idx].split(' ').collect();
+    !(statement[0].is("alter") && (token.is("alter") || token.is("drop")))
+}
 
-                    for sp in split.into_iter() {
-                        if sp.to_lowercase() != "select" {
-                            working_buf.push(sp.to_string());
-                        }
-                    }
+/*
+    Top level comments and statements. `BEGIN BATCH` runs until `APPLY BATCH ...;`,
+    a statement missing its `;` ends at the line break before the next one.
+*/
+fn split_chunks(tokens: Vec<Token>) -> Vec<Chunk> {
+    let mut chunks = vec![];
+    let mut tokens = tokens.into_iter().peekable();
+
+    while let Some(token) = tokens.next() {
+        if token.is_comment() {
+            chunks.push(Chunk::Comment(token));
+            continue;
+        }
 
-                    idx += 1;
+        let is_batch = token.is("begin");
+        let mut depth = 0usize;
+        let mut applied = false;
+        let mut statement = vec![token];
+
+        while let Some(token) =
+            tokens.next_if(|t| is_batch || depth > 0 || !starts_statement(&statement, t))
+        {
+            if token.kind == TokenKind::Punct {
+                match token.text {
+                    "(" | "[" | "{" => depth += 1,
+                    ")" | "]" | "}" => depth = depth.saturating_sub(1),
+                    _ => {}
                 }
+            }
+            if depth == 0 && token.is("apply") {
+                applied = true;
+            }
 
-                let mut start_idx = index + 1;
-                for kw in working_buf.iter_mut() {
-                    kw.retain(|c| c != '\n' && c != '\r');
-                    kw.push('\n');
+            let ends = depth == 0
+                && token.kind == TokenKind::Punct
+                && token.text == ";"
+                && (!is_batch || applied);
+            let row = token.end_row;
+            statement.push(token);
 
-                    if start_idx < lines.len() {
-                        lines.insert(start_idx, kw.clone());
-                    } else {
-                        lines.push(kw.clone());
-                    }
-                    start_idx += 1;
+            if ends {
+                while let Some(comment) = tokens.next_if(|t| t.is_comment() && t.row == row) {
+                    statement.push(comment);
                 }
+                break;
+            }
+        }
 
-                if lines[index].chars().next().unwrap_or('s') == 'S' {
-                    lines[index] = "SELECT".to_string();
-                } else {
-                    lines[index] = "select".to_string();
-                }
+        chunks.push(Chunk::Statement(statement));
+    }
 
-                index += working_buf.len();
-            } else {
-                index += 1;
-            }
+    chunks
+}
+
+/*
+    Where the `;` of each top level statement without one goes, after its last token
+    that isn't a comment. The statements are split the same way `format_text` splits them.
+*/
+pub(crate) fn missing_semicolons(root: Node, source: &str) -> Vec<usize> {
+    let mut tokens = vec![];
+    collect_tokens(root, source, &mut tokens);
+    let tokens = merge_string_bodies(merge_code_blocks(fill_gaps(tokens, source), source), source);
+
+    split_chunks(tokens)
+        .into_iter()
+        .filter_map(|chunk| match chunk {
+            Chunk::Statement(tokens) => tokens.into_iter().rfind(|t| !t.is_comment()),
+            Chunk::Comment(_) => None,
+        })
+        .filter(|last| !(last.kind == TokenKind::Punct && last.text == ";"))
+        .map(|last| last.end)
+        .collect()
+}
+
+/*
+    The code of `s` without whitespace and comments, and the comments on their own.
+    A comment may move past a `,`, but neither the code nor a comment may change.
+*/
+fn code_and_comments(s: &str) -> (String, Vec<&str>) {
+    let mut code = String::new();
+    let mut comments = vec![];
+    let mut quote: Option<&str> = None;
+    let mut index = 0;
+
+    while index < s.len() {
+        let rest = &s[index..];
+
+        if let Some(q) = quote {
+            let end = rest.find(q).map_or(rest.len(), |end| end + q.len());
+            code.extend(rest[..end].chars().filter(|c| !c.is_whitespace()));
+            quote = None;
+            index += end;
+            continue;
+        }
+
+        let comment_end = if rest.starts_with("--") || rest.starts_with("//") {
+            Some(rest.find('\n').unwrap_or(rest.len()))
+        } else if rest.starts_with("/*") {
+            Some(rest.find("*/").map_or(rest.len(), |end| end + 2))
+        } else {
+            None
+        };
+        if let Some(end) = comment_end {
+            comments.push(rest[..end].trim_end());
+            index += end;
+            continue;
+        }
+
+        let c = rest.chars().next().unwrap();
+        let opening = ["$$", "'", "\""].into_iter().find(|q| rest.starts_with(q));
+        if let Some(q) = opening {
+            code.push_str(q);
+            quote = Some(q);
+            index += q.len();
+            continue;
+        }
+        if !c.is_whitespace() {
+            code.push(c);
         }
+        index += c.len_utf8();
     }
 
-    pub async fn align_types_inside_create_statement(
-        &self,
-        lines: &mut Vec<String>,
-        document_url: &Url,
-    ) {
-        let mut working_blocks: Vec<Vec<usize>> = Vec::new();
-        let mut current_block: Vec<usize> = Vec::new();
-        let mut in_table = false;
-        let mut in_function_or_aggregate = false;
-        let mut parenthesis_depth = 0;
-
-        for (i, line) in lines.iter().enumerate() {
-            let trimmed_lower = line.trim().to_lowercase();
-
-            if trimmed_lower.starts_with("create function")
-                || trimmed_lower.starts_with("create or replace function")
-                || trimmed_lower.starts_with("create aggregate")
-                || trimmed_lower.starts_with("create or replace aggregate")
-            {
-                in_function_or_aggregate = true;
+    (code, comments)
+}
+
+impl Backend {
+    /*
+        Formats the whole text, statements are separated by a blank line
+        and the result always ends with a new line.
+    */
+    pub async fn format_text(&self, source: &str) -> String {
+        let tree = {
+            let mut parser = TS_CQL.lock().await;
+            match parser.parse(source, None) {
+                Some(t) => t,
+                None => return source.to_string(),
             }
+        };
 
-            if in_function_or_aggregate {
-                if line.contains(";") {
-                    in_function_or_aggregate = false;
-                }
+        let mut tokens = vec![];
+        collect_tokens(tree.root_node(), source, &mut tokens);
+        drop(tree);
+        let tokens =
+            merge_string_bodies(merge_code_blocks(fill_gaps(tokens, source), source), source);
+
+        /* the formatted bodies are spliced into the source the result is checked against */
+        let mut bodies = HashMap::new();
+        let mut expected = String::with_capacity(source.len());
+        let mut last_end = 0;
+        for (language, body) in function_bodies(&tokens) {
+            let Some(command) = self.config.udf_formatters.get(&language) else {
                 continue;
-            }
+            };
 
-            let is_create_st_start = trimmed_lower.starts_with("create table")
-                || trimmed_lower.starts_with("create type");
-            let contains_cql_type = self.line_contains_cql_type(line);
-            let has_open_brace = line.contains('(');
-            let has_close_brace = line.contains(')');
+            let quoted = body.parent != "code_block";
+            let content = if quoted {
+                &body.text[1..body.text.len() - 1]
+            } else {
+                body.text
+            };
+            let formatted = if quoted {
+                run_external_formatter(command, &content.replace("''", "'"))
+                    .await
+                    .map(|f| f.replace('\'', "''"))
+            } else {
+                run_external_formatter(command, content).await
+            };
+            let Some(formatted) = formatted else {
+                continue;
+            };
 
-            if has_open_brace {
-                parenthesis_depth += line.matches('(').count();
-            }
-            if has_close_brace {
-                parenthesis_depth = parenthesis_depth.saturating_sub(line.matches(')').count());
-            }
+            let (start, end) = if quoted {
+                (body.start + 1, body.end - 1)
+            } else {
+                (body.start, body.end)
+            };
+            expected.push_str(&source[last_end..start]);
+            expected.push_str(&formatted);
+            last_end = end;
+            bodies.insert(body.start, formatted);
+        }
+        expected.push_str(&source[last_end..]);
+
+        let chunks = split_chunks(tokens);
+
+        let printer = Printer {
+            max_width: self.config.max_width as usize,
+            indent: self.config.indent as usize,
+            type_padding: self.config.type_padding as usize,
+            keyword_case: self.config.keyword_case,
+            type_case: self.config.type_case,
+            function_case: self.config.function_case,
+            bodies,
+        };
 
-            if is_create_st_start {
-                if in_table && !current_block.is_empty() {
-                    working_blocks.push(current_block.clone());
-                    current_block.clear();
-                }
-                in_table = true;
-                parenthesis_depth = 0;
-            }
+        let mut docs = vec![];
+        for (index, chunk) in chunks.iter().enumerate() {
+            if let Some(prev) = index.checked_sub(1).map(|i| &chunks[i]) {
+                docs.push(hardline());
 
-            if in_table && contains_cql_type {
-                current_block.push(i);
+                let both_statements =
+                    matches!(prev, Chunk::Statement(_)) && matches!(chunk, Chunk::Statement(_));
+                if both_statements || chunk.first_row() > prev.last_row() + 1 {
+                    docs.push(hardline());
+                }
             }
 
-            if in_table && parenthesis_depth == 0 && has_close_brace {
-                if !current_block.is_empty() {
-                    working_blocks.push(current_block.clone());
-                    current_block.clear();
+            docs.push(match chunk {
+                Chunk::Comment(comment) => text(comment.text),
+                Chunk::Statement(tokens) if tokens.iter().any(|t| t.suspicious) => {
+                    text(&source[tokens[0].start..tokens[tokens.len() - 1].end])
                 }
-                in_table = false;
-                parenthesis_depth = 0;
-            }
+                Chunk::Statement(tokens) => printer.statement(&build_items(tokens.clone())),
+            });
         }
 
-        if !current_block.is_empty() {
-            working_blocks.push(current_block);
+        let mut formatted = concat(docs).pretty(printer.max_width);
+        if !formatted.is_empty() {
+            formatted.push('\n');
         }
 
-        info!("Size of working blocks: {}", working_blocks.len());
+        /* only whitespace and casing may change, anything else means the printer lost a token */
+        let (code, comments) = code_and_comments(&formatted);
+        let (expected_code, expected_comments) = code_and_comments(&expected);
+        if code.to_lowercase() != expected_code.to_lowercase() || comments != expected_comments {
+            error!("Formatting would change the content, leaving the document as is");
+            return source.to_string();
+        }
 
-        for vec in working_blocks.iter() {
-            let mut max_offset_x = 0;
-            let mut offsets: Vec<(usize, usize)> = Vec::new();
+        formatted
+    }
 
-            for index in vec {
-                let line = &lines[*index].to_lowercase();
-                let lower = line.to_lowercase();
-                let split: Vec<&str> = lower.split_whitespace().collect();
-                let mut line_type = String::from("");
+    pub async fn format_test(&self, lines: &Vec<&str>, _document_url: &Url) -> String {
+        self.format_text(&lines.join("\n")).await
+    }
 
-                info!("\n\nSplit: {:?}\n\n", split);
+    pub async fn format_file(&self, lines: &Vec<&str>, _document_url: &Url) -> Vec<TextEdit> {
+        let formatted_content = self.format_text(&lines.join("\n")).await;
 
-                for w in split {
-                    let wlw = w.to_lowercase();
-                    info!("\n\nWLw: {}\n\n", wlw);
-                    if CQL_TYPES_LWC.contains(&wlw.replace(",", "").trim().to_string())
-                        || wlw.starts_with("set")
-                        || wlw.starts_with("map")
-                        || wlw.starts_with("list")
-                        || wlw.starts_with("frozen")
-                        || wlw.starts_with("\'")
-                    {
-                        line_type = w.to_string();
-                        info!("\n\nTYPE: {}\n\n", line_type);
-                        break;
-                    }
-                }
+        self.diff_edits(&lines.join("\n"), &formatted_content)
+    }
 
-                if let Some(offset_x) = lower.to_lowercase().rfind(&line_type.to_lowercase()) {
-                    info!("\n\nOFFSET: {}, LINE_TYPE: {}\n\n", offset_x, line_type);
-                    offsets.push((*index, offset_x));
-                    if max_offset_x < offset_x {
-                        max_offset_x = offset_x;
-                    }
-                }
-            }
+    /*
+        Replacing the whole document resets cursors, folds and undo history
+        in most editors, so only the changed parts are sent back.
 
-            info!("Size of offsets: {}", offsets.len());
+        Lines are diffed first, then every changed hunk is narrowed down
+        to the chars that actually differ.
+    */
+    pub fn diff_edits(&self, original: &str, formatted: &str) -> Vec<TextEdit> {
+        if original == formatted {
+            return vec![];
+        }
 
-            for offset in offsets {
-                if offset.1 < max_offset_x && !lines[offset.0].trim().starts_with("--") {
-                    let diff = max_offset_x - offset.1;
-                    info!("\n\nediting line: {}, {}\n\n", lines[offset.0], offset.1);
-                    lines[offset.0].insert_str(offset.1 - 1, &" ".repeat(diff));
-                }
+        let old_lines: Vec<&str> = original.split_inclusive('\n').collect();
+        let new_lines: Vec<&str> = formatted.split_inclusive('\n').collect();
+        let line_offsets: Vec<usize> = std::iter::once(0)
+            .chain(old_lines.iter().scan(0, |offset, line| {
+                *offset += line.len();
+                Some(*offset)
+            }))
+            .collect();
+
+        let mut edits = vec![];
+
+        for (old, new) in changed_spans(&old_lines, &new_lines) {
+            let hunk_start = line_offsets[old.start];
+            let old_chars: Vec<(usize, char)> = original[hunk_start..line_offsets[old.end]]
+                .char_indices()
+                .map(|(i, c)| (hunk_start + i, c))
+                .collect();
+            let new_chars: Vec<char> = new_lines[new].concat().chars().collect();
+
+            let old_offset = |index: usize| {
+                old_chars
+                    .get(index)
+                    .map_or(line_offsets[old.end], |(offset, _)| *offset)
+            };
+            let plain_old: Vec<char> = old_chars.iter().map(|(_, c)| *c).collect();
+
+            for (old_span, new_span) in changed_spans(&plain_old, &new_chars) {
+                edits.push(TextEdit {
+                    range: Range::new(
+                        offset_to_position(original, old_offset(old_span.start)),
+                        offset_to_position(original, old_offset(old_span.end)),
+                    ),
+                    new_text: new_chars[new_span].iter().collect(),
+                });
             }
         }
+
+        edits
     }
 
-    pub async fn format_test(&self, lines: &Vec<&str>, document_url: &Url) -> String {
-        let mut working_vec: Vec<String> = lines.into_iter().map(|s| s.to_string()).collect();
-
-        for index in 0..working_vec.len() {
-            working_vec[index] = working_vec[index].trim().to_string();
-            self.fix_spacing(&mut working_vec[index]);
-            self.fix_duplicate_semi_colon(&mut working_vec[index]);
-        }
-
-        self.fix_semi_colon(&mut working_vec);
-        self.fix_string_literals(&mut working_vec);
-        self.fix_new_lines(&mut working_vec);
-        self.remove_new_lines_from_code_block(&mut working_vec);
-        self.apply_semi_colon(&mut working_vec);
-        self.add_spacing_new_lines(&mut working_vec);
-        self.add_spacing_after_comma(&mut working_vec);
-        self.add_tabs_to_args(&mut working_vec, document_url).await;
-        self.add_new_line_before_pk(&mut working_vec);
-        self.add_tabs_to_cql_types(&mut working_vec, document_url)
-            .await;
-        self.align_types_inside_create_statement(&mut working_vec, document_url)
-            .await;
-        self.format_insert(&mut working_vec, document_url).await;
-        self.add_tabs_to_function_keywords(&mut working_vec, document_url)
-            .await;
-        self.indent_comments_inside_crteate_table(&mut working_vec);
-
-        info!("Working Vec Size: {}", working_vec.len());
-        info!("Original lines: {}", lines.len());
-
-        working_vec.join("\n")
-    }
-
-    pub async fn format_file(&self, lines: &Vec<&str>, document_url: &Url) -> Vec<TextEdit> {
-        let mut working_vec: Vec<String> = lines.into_iter().map(|s| s.to_string()).collect();
-
-        for index in 0..working_vec.len() {
-            working_vec[index] = working_vec[index].trim().to_string();
-            self.fix_spacing(&mut working_vec[index]);
-            self.fix_duplicate_semi_colon(&mut working_vec[index]);
-        }
-
-        self.fix_semi_colon(&mut working_vec);
-        self.fix_string_literals(&mut working_vec);
-        self.fix_new_lines(&mut working_vec);
-        self.remove_new_lines_from_code_block(&mut working_vec);
-        self.apply_semi_colon(&mut working_vec);
-        self.add_spacing_new_lines(&mut working_vec);
-        self.add_spacing_after_comma(&mut working_vec);
-        self.add_tabs_to_args(&mut working_vec, document_url).await;
-        self.add_new_line_before_pk(&mut working_vec);
-        self.add_tabs_to_cql_types(&mut working_vec, document_url)
-            .await;
-        self.align_types_inside_create_statement(&mut working_vec, document_url)
-            .await;
-        self.format_insert(&mut working_vec, document_url).await;
-        self.add_tabs_to_function_keywords(&mut working_vec, document_url)
-            .await;
-        self.indent_comments_inside_crteate_table(&mut working_vec);
-
-        info!("Working Vec Size: {}", working_vec.len());
-        info!("Original lines: {}", lines.len());
-
-        let formatted_content = working_vec.join("\n");
-
-        let last_line_idx = if lines.is_empty() { 0 } else { lines.len() - 1 };
-        let last_line_len = if lines.is_empty() {
-            0
-        } else {
-            lines[last_line_idx].len()
+    /*
+        Line spans of the top level statements
+    */
+    async fn statement_line_spans(&self, text: &str) -> Vec<(usize, usize)> {
+        let tree = {
+            let mut parser = TS_CQL.lock().await;
+            match parser.parse(text, None) {
+                Some(t) => t,
+                None => return vec![],
+            }
         };
 
-        let full_edit = TextEdit {
-            range: Range {
-                start: Position {
-                    line: 0,
-                    character: 0,
-                },
-                end: Position {
-                    line: last_line_idx as u32,
-                    character: last_line_len as u32,
-                },
-            },
-            new_text: formatted_content,
+        let root = tree.root_node();
+        let mut cursor = root.walk();
+
+        root.children(&mut cursor)
+            .filter(|n| n.kind() == "cql_commands")
+            .map(|n| (n.start_position().row, n.end_position().row))
+            .collect()
+    }
+
+    async fn format_chunk(&self, chunk: &[&str]) -> Vec<String> {
+        self.format_text(&chunk.join("\n"))
+            .await
+            .trim_end_matches('\n')
+            .split('\n')
+            .map(String::from)
+            .collect()
+    }
+
+    /*
+        Formats whole lines of every statement overlapping `range`,
+        statements sharing a line are formatted together.
+        Comments and blank lines between them are left as is.
+    */
+    pub async fn format_range(
+        &self,
+        text: &str,
+        _document_url: &Url,
+        range: Range,
+    ) -> Vec<TextEdit> {
+        let lines: Vec<&str> = text.split('\n').collect();
+
+        let mut groups: Vec<(usize, usize)> = vec![];
+        for (start, end) in self.statement_line_spans(text).await {
+            match groups.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => groups.push((start, end)),
+            }
+        }
+
+        let mut formatted_lines: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+
+        for (start, end) in groups.into_iter().rev() {
+            if start > range.end.line as usize
+                || end < range.start.line as usize
+                || end >= lines.len()
+            {
+                continue;
+            }
+
+            let formatted = self.format_chunk(&lines[start..=end]).await;
+
+            if !formatted.is_empty() {
+                formatted_lines.splice(start..=end, formatted);
+            }
+        }
+
+        self.diff_edits(text, &formatted_lines.join("\n"))
+    }
+
+    /*
+        `;` formats the statement it terminates,
+        a new line realigns the column types of the CREATE TABLE/TYPE being typed.
+    */
+    pub async fn format_on_type(
+        &self,
+        text: &str,
+        document_url: &Url,
+        position: Position,
+        ch: &str,
+    ) -> Vec<TextEdit> {
+        match ch {
+            ";" => {
+                let at = Position::new(position.line, position.character.saturating_sub(1));
+                self.format_range(text, document_url, Range::new(at, at))
+                    .await
+            }
+            "\n" => self.align_create_on_new_line(text, position).await,
+            _ => vec![],
+        }
+    }
+
+    /*
+        Realigns the columns typed so far the way `format_text` aligns them,
+        by formatting the statement closed right after the previous line.
+        The statement is the one the parser puts the cursor in, so it may
+        start anywhere on a line and a `create table` inside a string, comment
+        or function body doesn't count. The header and anything that would move
+        to other lines are left alone.
+    */
+    async fn align_create_on_new_line(&self, text: &str, position: Position) -> Vec<TextEdit> {
+        let lines: Vec<&str> = text.split('\n').collect();
+        let last = position.line as usize;
+
+        if last == 0 || last > lines.len() {
+            return vec![];
+        }
+
+        let typed = lines[..last].join("\n");
+        let closed = format!("{typed}\n);");
+        let end = typed.trim_end().len();
+
+        let (statement, columns_start) = {
+            let tree = {
+                let mut parser = TS_CQL.lock().await;
+                match parser.parse(&closed, None) {
+                    Some(t) => t,
+                    None => return vec![],
+                }
+            };
+
+            let root = tree.root_node();
+            let mut cursor = root.walk();
+            let Some(node) = root
+                .children(&mut cursor)
+                .filter(|n| n.kind() == "cql_commands")
+                .find(|n| n.start_byte() < end && end < n.end_byte())
+            else {
+                return vec![];
+            };
+
+            let mut cursor = node.walk();
+            let children: Vec<Node> = node
+                .children(&mut cursor)
+                .filter(|n| n.kind() != "comment")
+                .collect();
+            let is = |i: usize, words: &[&str]| {
+                children
+                    .get(i)
+                    .is_some_and(|n| words.iter().any(|w| n.kind().eq_ignore_ascii_case(w)))
+            };
+            if !is(0, &["create"]) || !is(1, &["table", "type"]) {
+                return vec![];
+            }
+            let Some(open) = children.iter().find(|n| n.kind() == "(") else {
+                return vec![];
+            };
+
+            (
+                closed[node.start_byte()..].to_string(),
+                open.start_position().row + 1,
+            )
         };
 
-        vec![full_edit]
+        let formatted = self.format_text(&statement).await;
+        let mut aligned: Vec<&str> = formatted.trim_end().split('\n').collect();
+
+        if aligned.pop() != Some(");") || aligned.len() != last + 1 - columns_start {
+            return vec![];
+        }
+
+        let mut formatted_lines: Vec<&str> = lines.clone();
+        formatted_lines.splice(columns_start..last, aligned[1..].iter().copied());
+
+        self.diff_edits(text, &formatted_lines.join("\n"))
     }
 }
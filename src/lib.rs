@@ -2,18 +2,28 @@
     Copyright (c) 2026 アクゼスティア. All Rights Reserved.
 */
 
+pub mod check;
 pub mod cmd;
+pub mod code_actions;
 pub mod completions;
 pub mod config;
+pub mod config_editing;
+pub mod config_toml;
+pub mod connection;
 pub mod consts;
 pub mod cqlsh;
+pub mod cqlshrc;
 pub mod diagnostics;
+pub mod doc;
 pub mod formatting;
 pub mod handlers;
+pub mod lint;
 pub mod logger;
 pub mod lsp;
+pub mod schema;
 #[cfg(any(test, debug_assertions))]
 pub mod test_base;
 pub mod tree_sitter;
 pub mod utils;
 pub mod version;
+pub mod workspace;
@@ -0,0 +1,1046 @@
+/*
+    Copyright (c) 2026 アクゼスティア. All Rights Reserved.
+*/
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use tower_lsp::lsp_types::*;
+use tree_sitter::Node;
+
+use crate::code_actions::{bracket_content, column_name, split_top_level, top_level_matches};
+use crate::consts::*;
+use crate::cqlsh::Table;
+use crate::formatting::{missing_semicolons, offset_to_position};
+use crate::lsp::Backend;
+use crate::schema;
+
+pub const LINT_DOCS_URL: &str = "https://github.com/Akzestia/cqlls";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintLevel {
+    Off,
+    Hint,
+    Warning,
+    Error,
+}
+
+impl LintLevel {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "off" => Some(LintLevel::Off),
+            "hint" => Some(LintLevel::Hint),
+            "warning" => Some(LintLevel::Warning),
+            "error" => Some(LintLevel::Error),
+            _ => None,
+        }
+    }
+
+    pub fn severity(&self) -> Option<DiagnosticSeverity> {
+        match self {
+            LintLevel::Off => None,
+            LintLevel::Hint => Some(DiagnosticSeverity::HINT),
+            LintLevel::Warning => Some(DiagnosticSeverity::WARNING),
+            LintLevel::Error => Some(DiagnosticSeverity::ERROR),
+        }
+    }
+}
+
+impl fmt::Display for LintLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LintLevel::Off => "off",
+            LintLevel::Hint => "hint",
+            LintLevel::Warning => "warning",
+            LintLevel::Error => "error",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug)]
+pub struct LintRule {
+    pub id: &'static str,
+    pub default_level: LintLevel,
+    pub description: &'static str,
+}
+
+pub const LINT_RULES: &[LintRule] = &[
+    LintRule {
+        id: "syntax-error",
+        default_level: LintLevel::Error,
+        description: "Statement could not be parsed",
+    },
+    LintRule {
+        id: "allow-filtering",
+        default_level: LintLevel::Warning,
+        description: "`ALLOW FILTERING` makes the query scan every partition",
+    },
+    LintRule {
+        id: "drop-without-if-exists",
+        default_level: LintLevel::Warning,
+        description: "`DROP` without `IF EXISTS` fails when the object is missing",
+    },
+    LintRule {
+        id: "select-star",
+        default_level: LintLevel::Hint,
+        description: "`SELECT *` fetches every column of the table",
+    },
+    LintRule {
+        id: "unlogged-batch-multi-partition",
+        default_level: LintLevel::Warning,
+        description: "Unlogged batch writes to more than one partition",
+    },
+    LintRule {
+        id: "missing-semicolon",
+        default_level: LintLevel::Warning,
+        description: "Statement is not terminated with `;`",
+    },
+    LintRule {
+        id: "create-without-if-not-exists",
+        default_level: LintLevel::Hint,
+        description: "`CREATE` without `IF NOT EXISTS` fails when the object exists",
+    },
+    LintRule {
+        id: "reserved-identifier",
+        default_level: LintLevel::Error,
+        description: "Reserved keyword used as an unquoted identifier",
+    },
+    LintRule {
+        id: "unqualified-table",
+        default_level: LintLevel::Hint,
+        description: "Table is referenced without a keyspace and no `USE` precedes it",
+    },
+    LintRule {
+        id: "unknown-column",
+        default_level: LintLevel::Warning,
+        description: "Column does not exist in the table schema",
+    },
+];
+
+/*
+    Quick fix attached to a finding.
+
+    Fixes are serialized into `Diagnostic.data`, so the code action
+    handler doesn't have to re-run the rules (or the schema queries).
+*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LintFix {
+    pub title: String,
+    pub edits: Vec<TextEdit>,
+}
+
+impl LintFix {
+    pub fn new(title: impl Into<String>, edits: Vec<TextEdit>) -> Self {
+        Self {
+            title: title.into(),
+            edits,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LintFinding {
+    pub rule: &'static str,
+    pub range: Range,
+    pub message: String,
+    /*
+        First line of the statement the finding belongs to,
+        so `cqlls-disable-next-line` placed above a multi-line
+        statement covers all of it.
+    */
+    pub statement_line: u32,
+    pub fixes: Vec<LintFix>,
+}
+
+/*
+    Table reference collected from the tree, resolved against the
+    schema after the tree is dropped (tree-sitter nodes aren't `Send`).
+*/
+#[derive(Debug)]
+pub struct TableRef {
+    pub keyspace: Option<String>,
+    pub table: String,
+    /*
+        `keyspace` is also filled from the preceding `USE`,
+        `qualified` tells whether it was written in the source.
+    */
+    pub qualified: bool,
+    pub use_keyspace: Option<String>,
+    pub range: Range,
+    pub columns: Vec<(String, Range)>,
+    pub statement_line: u32,
+}
+
+/*
+    What `written_values` keeps of a value, `= 1` and `IN (1, 2)`,
+    whitespace doesn't tell two partitions apart
+*/
+fn normalized(operator: &str, value: &str) -> String {
+    format!(
+        "{operator} {}",
+        value.split_whitespace().collect::<Vec<_>>().join(" ")
+    )
+}
+
+/*
+    `column -> value` of the columns an INSERT writes, or of the `=` and `IN`
+    restrictions of the WHERE of an UPDATE or DELETE
+*/
+fn written_values(statement: &str) -> Option<HashMap<String, String>> {
+    let statement = statement.trim().trim_end_matches(';');
+    let mut values = HashMap::new();
+
+    if statement
+        .get(..6)
+        .is_some_and(|head| head.eq_ignore_ascii_case("insert"))
+    {
+        let (columns, after) = bracket_content(statement, statement.find('(')?)?;
+        let rest = &statement[after..];
+        let values_at = *top_level_matches(rest, "values").first()?;
+        let open = values_at + rest[values_at..].find('(')?;
+        let (written, _) = bracket_content(rest, open)?;
+
+        let columns = split_top_level(columns, ",");
+        let written = split_top_level(written, ",");
+        if columns.len() != written.len() {
+            return None;
+        }
+        for (column, value) in columns.into_iter().zip(written) {
+            values.insert(column_name(column), normalized("=", value));
+        }
+        return Some(values);
+    }
+
+    let where_at = *top_level_matches(statement, "where").first()?;
+    let conditions = &statement[where_at + "where".len()..];
+    let conditions = match top_level_matches(conditions, "if").first() {
+        Some(&if_at) => &conditions[..if_at],
+        None => conditions,
+    };
+
+    for condition in split_top_level(conditions, "and") {
+        if let Some(&in_at) = top_level_matches(condition, "in").first() {
+            let value = &condition[in_at + "in".len()..];
+            values.insert(
+                column_name(condition[..in_at].trim()),
+                normalized("IN", value),
+            );
+        } else if let Some((column, value)) = condition.split_once('=')
+            && !column.ends_with(['<', '>', '!'])
+        {
+            values.insert(column_name(column.trim()), normalized("=", value));
+        }
+    }
+
+    Some(values)
+}
+
+/*
+    An `UNLOGGED` batch, the partitions its statements write to are told
+    apart once the partition key of each table is known from the schema.
+*/
+#[derive(Debug)]
+pub struct UnloggedBatch {
+    pub range: Range,
+    pub statement_line: u32,
+    pub writes: Vec<BatchWrite>,
+}
+
+#[derive(Debug)]
+pub struct BatchWrite {
+    pub keyspace: Option<String>,
+    pub table: String,
+    /* `column -> value` of the inserted columns and the `=`/`IN` restrictions, `None` when it can't be told */
+    pub values: Option<HashMap<String, String>>,
+}
+
+/*
+    Inline suppression comments
+
+    -- cqlls-disable-next-line rule-a, rule-b
+    -- cqlls-disable-line rule-a
+    -- cqlls-disable rule-a
+
+    No rule ids means every rule.
+*/
+#[derive(Debug, Default)]
+pub struct Suppressions {
+    lines: HashMap<u32, HashSet<String>>,
+    from_line: Vec<(u32, HashSet<String>)>,
+}
+
+impl Suppressions {
+    pub fn from_tree(root: Node, text: &str) -> Self {
+        let mut suppressions = Suppressions::default();
+        suppressions.collect(root, text);
+        suppressions
+    }
+
+    fn collect(&mut self, node: Node, text: &str) {
+        if node.kind() == "line_comment" || node.kind() == "block_comment" {
+            let comment = node.utf8_text(text.as_bytes()).unwrap_or("");
+            self.add_directive(
+                comment,
+                node.start_position().row as u32,
+                node.end_position().row as u32,
+            );
+            return;
+        }
+
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.collect(child, text);
+        }
+    }
+
+    fn add_directive(&mut self, comment: &str, start_line: u32, end_line: u32) {
+        let body = comment
+            .trim()
+            .trim_start_matches("--")
+            .trim_start_matches("//")
+            .trim_start_matches("/*")
+            .trim_end_matches("*/")
+            .trim();
+
+        let parse_rules = |rest: &str| -> HashSet<String> {
+            rest.split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|r| !r.is_empty())
+                .map(|r| r.to_string())
+                .collect()
+        };
+
+        if let Some(rest) = body.strip_prefix("cqlls-disable-next-line") {
+            self.lines
+                .entry(end_line + 1)
+                .or_default()
+                .extend(parse_rules(rest));
+        } else if let Some(rest) = body.strip_prefix("cqlls-disable-line") {
+            self.lines
+                .entry(start_line)
+                .or_default()
+                .extend(parse_rules(rest));
+        } else if let Some(rest) = body.strip_prefix("cqlls-disable") {
+            self.from_line.push((start_line, parse_rules(rest)));
+        }
+    }
+
+    fn covers(rules: &HashSet<String>, rule: &str) -> bool {
+        rules.is_empty() || rules.contains(rule)
+    }
+
+    pub fn is_suppressed(&self, rule: &str, line: u32) -> bool {
+        if self
+            .lines
+            .get(&line)
+            .is_some_and(|rules| Self::covers(rules, rule))
+        {
+            return true;
+        }
+
+        self.from_line
+            .iter()
+            .any(|(start, rules)| *start <= line && Self::covers(rules, rule))
+    }
+}
+
+fn keyword_is(node: Node, keyword: &str) -> bool {
+    !node.is_named() && node.kind().eq_ignore_ascii_case(keyword)
+}
+
+fn children_of(node: Node) -> Vec<Node> {
+    let mut cursor = node.walk();
+    node.children(&mut cursor).collect()
+}
+
+/* LSP positions count UTF-16 code units, tree-sitter columns count bytes */
+fn span(text: &str, start: Node, end: Node) -> Range {
+    Range::new(
+        offset_to_position(text, start.start_byte()),
+        offset_to_position(text, end.end_byte()),
+    )
+}
+
+fn node_text<'a>(node: Node, text: &'a str) -> &'a str {
+    node.utf8_text(text.as_bytes()).unwrap_or("")
+}
+
+/*
+    Last keyword of the leading keyword run, e.g.
+
+    |CREATE MATERIALIZED VIEW| name ...
+    |DROP TABLE| name ...
+
+    `IF [NOT] EXISTS` is inserted after it.
+*/
+fn object_keyword_end<'tree>(children: &[Node<'tree>]) -> Option<Node<'tree>> {
+    children
+        .iter()
+        .take_while(|c| !c.is_named() && !keyword_is(**c, "on"))
+        .last()
+        .copied()
+}
+
+impl Backend {
+    pub fn lint_statements(&self, root: Node, text: &str, out: &mut Vec<LintFinding>) {
+        let mut cursor = root.walk();
+        for statement in root.children(&mut cursor) {
+            if statement.kind() != "cql_commands" {
+                continue;
+            }
+
+            let children = children_of(statement);
+            let statement_line = statement.start_position().row as u32;
+
+            Self::lint_allow_filtering(&children, text, statement_line, out);
+            Self::lint_drop_without_if_exists(statement, &children, text, statement_line, out);
+            Self::lint_create_without_if_not_exists(&children, text, statement_line, out);
+            Self::lint_select_star(&children, text, statement_line, out);
+            Self::lint_reserved_identifiers(statement, text, statement_line, out);
+        }
+
+        Self::lint_missing_semicolon(root, text, out);
+    }
+
+    fn lint_allow_filtering(
+        children: &[Node],
+        text: &str,
+        statement_line: u32,
+        out: &mut Vec<LintFinding>,
+    ) {
+        for (i, pair) in children.windows(2).enumerate() {
+            if keyword_is(pair[0], "allow") && keyword_is(pair[1], "filtering") {
+                let remove_from = if i > 0 {
+                    offset_to_position(text, children[i - 1].end_byte())
+                } else {
+                    offset_to_position(text, pair[0].start_byte())
+                };
+
+                out.push(LintFinding {
+                    rule: "allow-filtering",
+                    range: span(text, pair[0], pair[1]),
+                    message: "`ALLOW FILTERING` scans every partition; query by partition key or add an index instead".to_string(),
+                    statement_line,
+                    fixes: vec![LintFix::new(
+                        "Remove `ALLOW FILTERING`",
+                        vec![TextEdit {
+                            range: Range::new(remove_from, offset_to_position(text, pair[1].end_byte())),
+                            new_text: String::new(),
+                        }],
+                    )],
+                });
+            }
+        }
+    }
+
+    fn lint_drop_without_if_exists(
+        statement: Node,
+        children: &[Node],
+        text: &str,
+        statement_line: u32,
+        out: &mut Vec<LintFinding>,
+    ) {
+        let Some(first) = children.first() else {
+            return;
+        };
+
+        if !keyword_is(*first, "drop") || children.iter().any(|c| keyword_is(*c, "exists")) {
+            return;
+        }
+
+        let object = children
+            .get(1)
+            .filter(|c| !c.is_named())
+            .map(|c| c.kind().to_uppercase())
+            .unwrap_or_default();
+        let end = children
+            .iter()
+            .find(|c| c.kind() == "table_keyspace_name")
+            .copied()
+            .unwrap_or(statement);
+
+        let fixes = object_keyword_end(children)
+            .map(|kw| {
+                let at = offset_to_position(text, kw.end_byte());
+                vec![LintFix::new(
+                    "Add `IF EXISTS`",
+                    vec![TextEdit {
+                        range: Range::new(at, at),
+                        new_text: " IF EXISTS".to_string(),
+                    }],
+                )]
+            })
+            .unwrap_or_default();
+
+        out.push(LintFinding {
+            rule: "drop-without-if-exists",
+            range: span(text, *first, end),
+            message: format!("`DROP {object}` without `IF EXISTS` fails when it does not exist"),
+            statement_line,
+            fixes,
+        });
+    }
+
+    fn lint_create_without_if_not_exists(
+        children: &[Node],
+        text: &str,
+        statement_line: u32,
+        out: &mut Vec<LintFinding>,
+    ) {
+        let Some(first) = children.first() else {
+            return;
+        };
+
+        if !keyword_is(*first, "create")
+            || children
+                .iter()
+                .any(|c| keyword_is(*c, "exists") || keyword_is(*c, "replace"))
+        {
+            return;
+        }
+
+        let Some(kw) = object_keyword_end(children) else {
+            return;
+        };
+
+        let object = children
+            .iter()
+            .skip(1)
+            .take_while(|c| !c.is_named() && !keyword_is(**c, "on"))
+            .map(|c| c.kind().to_uppercase())
+            .collect::<Vec<String>>()
+            .join(" ");
+        let at = offset_to_position(text, kw.end_byte());
+
+        out.push(LintFinding {
+            rule: "create-without-if-not-exists",
+            range: span(text, *first, kw),
+            message: format!(
+                "`CREATE {object}` without `IF NOT EXISTS` fails when it already exists"
+            ),
+            statement_line,
+            fixes: vec![LintFix::new(
+                "Add `IF NOT EXISTS`",
+                vec![TextEdit {
+                    range: Range::new(at, at),
+                    new_text: " IF NOT EXISTS".to_string(),
+                }],
+            )],
+        });
+    }
+
+    fn lint_select_star(
+        children: &[Node],
+        text: &str,
+        statement_line: u32,
+        out: &mut Vec<LintFinding>,
+    ) {
+        if !children.first().is_some_and(|c| keyword_is(*c, "select")) {
+            return;
+        }
+
+        for selectors in children.iter().filter(|c| c.kind() == "selectors") {
+            if let Some(wild_card) = selectors.named_child(0).filter(|c| c.kind() == "wild_card") {
+                out.push(LintFinding {
+                    rule: "select-star",
+                    range: span(text, wild_card, wild_card),
+                    message: "`SELECT *` fetches every column; list the columns you need"
+                        .to_string(),
+                    statement_line,
+                    fixes: vec![],
+                });
+            }
+        }
+    }
+
+    fn lint_reserved_identifiers(
+        node: Node,
+        text: &str,
+        statement_line: u32,
+        out: &mut Vec<LintFinding>,
+    ) {
+        // Keywords inside ERROR nodes are usually a missing `;`, not identifiers
+        if node.is_error() {
+            return;
+        }
+
+        if node.kind() == "identifier" {
+            let name = node_text(node, text);
+            if CQL_RESERVED_KEYWORDS_LWC.contains(&name.to_lowercase()) {
+                let quoted = format!("\"{}\"", name.to_lowercase());
+                out.push(LintFinding {
+                    rule: "reserved-identifier",
+                    range: span(text, node, node),
+                    message: format!("`{name}` is a reserved keyword and must be quoted"),
+                    statement_line,
+                    fixes: vec![LintFix::new(
+                        format!("Quote as `{quoted}`"),
+                        vec![TextEdit {
+                            range: span(text, node, node),
+                            new_text: quoted.clone(),
+                        }],
+                    )],
+                });
+            }
+            return;
+        }
+
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            Self::lint_reserved_identifiers(child, text, statement_line, out);
+        }
+    }
+
+    /*
+        Statements are split the same way the formatter splits them,
+        a statement missing its `;` ends at the line break before the next one.
+    */
+    fn lint_missing_semicolon(root: Node, text: &str, out: &mut Vec<LintFinding>) {
+        for offset in missing_semicolons(root, text) {
+            let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+            let content = &text[line_start..offset];
+            let start = line_start + content.len() - content.trim_start().len();
+            let end = offset_to_position(text, offset);
+
+            out.push(LintFinding {
+                rule: "missing-semicolon",
+                range: Range::new(offset_to_position(text, start), end),
+                message: "Missing `;` at the end of the statement".to_string(),
+                statement_line: end.line,
+                fixes: vec![LintFix::new(
+                    "Add missing `;`",
+                    vec![TextEdit {
+                        range: Range::new(end, end),
+                        new_text: ";".to_string(),
+                    }],
+                )],
+            });
+        }
+    }
+
+    pub fn collect_unlogged_batches(root: Node, text: &str) -> Vec<UnloggedBatch> {
+        let mut batches = Vec::new();
+        let mut use_keyspace: Option<String> = None;
+
+        let mut cursor = root.walk();
+        for statement in root.children(&mut cursor) {
+            if statement.kind() != "cql_commands" {
+                continue;
+            }
+
+            let children = children_of(statement);
+
+            if children.first().is_some_and(|c| keyword_is(*c, "use")) {
+                use_keyspace = children
+                    .iter()
+                    .find(|c| c.is_named() && c.kind() != "semi_colon")
+                    .map(|c| node_text(*c, text).trim_matches('"').to_string());
+                continue;
+            }
+
+            if children.len() < 3
+                || !keyword_is(children[0], "begin")
+                || !keyword_is(children[1], "unlogged")
+                || !keyword_is(children[2], "batch")
+            {
+                continue;
+            }
+
+            let writes = children
+                .iter()
+                .filter(|c| c.kind() == "dml_statement")
+                .filter_map(|dml| {
+                    let table_ref = Self::table_ref_of(&children_of(*dml), 0, &use_keyspace, text)?;
+                    Some(BatchWrite {
+                        keyspace: table_ref.keyspace,
+                        table: table_ref.table,
+                        values: written_values(node_text(*dml, text)),
+                    })
+                })
+                .collect();
+
+            batches.push(UnloggedBatch {
+                range: span(text, children[0], children[2]),
+                statement_line: statement.start_position().row as u32,
+                writes,
+            });
+        }
+
+        batches
+    }
+
+    pub fn collect_table_refs(root: Node, text: &str) -> Vec<TableRef> {
+        let mut refs = Vec::new();
+        let mut use_keyspace: Option<String> = None;
+
+        let mut cursor = root.walk();
+        for statement in root.children(&mut cursor) {
+            if statement.kind() != "cql_commands" {
+                continue;
+            }
+
+            let children = children_of(statement);
+
+            if children.first().is_some_and(|c| keyword_is(*c, "use")) {
+                use_keyspace = children
+                    .iter()
+                    .find(|c| c.is_named() && c.kind() != "semi_colon")
+                    .map(|c| node_text(*c, text).trim_matches('"').to_string());
+                continue;
+            }
+
+            if children.first().is_some_and(|c| keyword_is(*c, "begin")) {
+                for dml in children.iter().filter(|c| c.kind() == "dml_statement") {
+                    refs.extend(Self::table_ref_of(
+                        &children_of(*dml),
+                        dml.start_position().row as u32,
+                        &use_keyspace,
+                        text,
+                    ));
+                }
+                continue;
+            }
+
+            refs.extend(Self::table_ref_of(
+                &children,
+                statement.start_position().row as u32,
+                &use_keyspace,
+                text,
+            ));
+        }
+
+        refs
+    }
+
+    fn table_ref_of(
+        children: &[Node],
+        statement_line: u32,
+        use_keyspace: &Option<String>,
+        text: &str,
+    ) -> Option<TableRef> {
+        let index = children
+            .iter()
+            .position(|c| c.kind() == "table_keyspace_name")?;
+
+        if index > 0 && keyword_is(children[index - 1], "keyspace") {
+            return None;
+        }
+
+        let table_node = children[index];
+        let (keyspace, table, qualified) = match table_node.named_child(0) {
+            Some(ksp) if ksp.kind() == "key_space_name" => {
+                let name = node_text(ksp, text);
+                match name.split_once('.') {
+                    Some((k, t)) => (Some(k.to_string()), t.to_string(), true),
+                    None => (use_keyspace.clone(), name.to_string(), false),
+                }
+            }
+            _ => (
+                use_keyspace.clone(),
+                node_text(table_node, text).to_string(),
+                false,
+            ),
+        };
+
+        Some(TableRef {
+            keyspace,
+            table,
+            qualified,
+            use_keyspace: use_keyspace.clone(),
+            range: span(text, table_node, table_node),
+            columns: Self::collect_column_refs(children, index, text),
+            statement_line,
+        })
+    }
+
+    /*
+        Columns are only collected where the grammar makes them
+        unambiguous: SELECT selectors, WHERE conditions and the
+        INSERT column list.
+    */
+    fn collect_column_refs(
+        children: &[Node],
+        table_index: usize,
+        text: &str,
+    ) -> Vec<(String, Range)> {
+        let mut columns = Vec::new();
+        let mut push = |node: Node| {
+            if node.kind() == "identifier" || node.kind() == "list_identifier" {
+                columns.push((node_text(node, text).to_string(), span(text, node, node)));
+            }
+        };
+
+        let Some(first) = children.first() else {
+            return columns;
+        };
+
+        if keyword_is(*first, "select") {
+            for child in children {
+                match child.kind() {
+                    "selectors" => {
+                        if let Some(name) = child
+                            .named_child(0)
+                            .filter(|c| c.kind() == "literal")
+                            .and_then(|c| c.named_child(0))
+                        {
+                            push(name);
+                        }
+                    }
+                    "selector_conditions" => {
+                        if let Some(name) = child
+                            .named_child(0)
+                            .filter(|c| c.kind() == "table_keyspace_name")
+                            .and_then(|c| c.named_child(0))
+                        {
+                            push(name);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        } else if keyword_is(*first, "insert") {
+            for child in children
+                .iter()
+                .skip(table_index + 1)
+                .take_while(|c| !keyword_is(**c, "values"))
+            {
+                push(*child);
+            }
+        }
+
+        columns
+    }
+
+    /*
+        The findings that need the schema. Without `fetch` only the cached
+        schema is used, `false` is returned when something wasn't cached.
+    */
+    pub async fn lint_schema(
+        &self,
+        refs: Vec<TableRef>,
+        out: &mut Vec<LintFinding>,
+        fetch: bool,
+    ) -> bool {
+        use crate::lint::LintLevel::Off;
+
+        let online = self.config.has_feature("context_aware_completions");
+        let check_tables = self.config.lint_level("unqualified-table") != Off;
+        let check_columns = self.config.lint_level("unknown-column") != Off && online;
+
+        let mut complete = true;
+        let mut tables_read = false;
+        let mut all_tables: Option<Vec<Table>> = None;
+        let mut columns_cache: HashMap<(String, String), Vec<String>> = HashMap::new();
+
+        for table_ref in refs {
+            let Some(keyspace) = table_ref.keyspace.clone() else {
+                if !check_tables {
+                    continue;
+                }
+
+                if !tables_read && online {
+                    tables_read = true;
+                    all_tables = match fetch {
+                        true => Some(schema::tables(&self.config).await),
+                        false => schema::cached_tables(&self.config).await,
+                    };
+                    complete &= all_tables.is_some();
+                }
+
+                let fixes = all_tables
+                    .iter()
+                    .flatten()
+                    .filter(|t| t.table_name.eq_ignore_ascii_case(&table_ref.table))
+                    .map(|t| {
+                        LintFix::new(
+                            format!("Qualify with `{}`", t.keyspace_name),
+                            vec![TextEdit {
+                                range: table_ref.range,
+                                new_text: t.united(),
+                            }],
+                        )
+                    })
+                    .collect();
+
+                out.push(LintFinding {
+                    rule: "unqualified-table",
+                    range: table_ref.range,
+                    message: format!(
+                        "`{}` has no keyspace and no `USE` statement precedes it",
+                        table_ref.table
+                    ),
+                    statement_line: table_ref.statement_line,
+                    fixes,
+                });
+                continue;
+            };
+
+            if !check_columns || table_ref.columns.is_empty() {
+                continue;
+            }
+
+            let key = (keyspace.clone(), table_ref.table.clone());
+            if !columns_cache.contains_key(&key) {
+                let columns = match fetch {
+                    true => Some(schema::columns(&self.config, &keyspace, &table_ref.table).await),
+                    false => {
+                        schema::cached_columns(&self.config, &keyspace, &table_ref.table).await
+                    }
+                };
+                complete &= columns.is_some();
+
+                let names = columns
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|c| c.column_name)
+                    .collect();
+                columns_cache.insert(key.clone(), names);
+            }
+
+            let known = &columns_cache[&key];
+            if known.is_empty() {
+                continue;
+            }
+
+            for (name, range) in &table_ref.columns {
+                if known.iter().any(|k| k.eq_ignore_ascii_case(name)) {
+                    continue;
+                }
+
+                let fixes = Self::closest_match(name, known)
+                    .map(|closest| {
+                        vec![LintFix::new(
+                            format!("Replace with `{closest}`"),
+                            vec![TextEdit {
+                                range: *range,
+                                new_text: closest.clone(),
+                            }],
+                        )]
+                    })
+                    .unwrap_or_default();
+
+                out.push(LintFinding {
+                    rule: "unknown-column",
+                    range: *range,
+                    message: format!(
+                        "Unknown column `{name}` in `{keyspace}.{}`",
+                        table_ref.table
+                    ),
+                    statement_line: table_ref.statement_line,
+                    fixes,
+                });
+            }
+        }
+
+        complete
+    }
+
+    /*
+        Two writes are to the same partition when they're to the same table
+        with the same values for every column of its partition key. Nothing
+        is reported when a table, its schema or one of those values is unknown.
+        `fetch` and what's returned as for `lint_schema`.
+    */
+    pub async fn lint_unlogged_batches(
+        &self,
+        batches: Vec<UnloggedBatch>,
+        out: &mut Vec<LintFinding>,
+        fetch: bool,
+    ) -> bool {
+        if self.config.lint_level("unlogged-batch-multi-partition") == LintLevel::Off
+            || !self.config.has_feature("context_aware_completions")
+        {
+            return true;
+        }
+
+        let mut complete = true;
+        let mut keys_cache: HashMap<(String, String), Vec<String>> = HashMap::new();
+
+        'batches: for batch in batches {
+            let mut partitions: HashSet<(String, String, Vec<String>)> = HashSet::new();
+
+            for write in &batch.writes {
+                let (Some(keyspace), Some(values)) = (&write.keyspace, &write.values) else {
+                    continue 'batches;
+                };
+
+                let key = (keyspace.to_lowercase(), write.table.to_lowercase());
+                if !keys_cache.contains_key(&key) {
+                    let columns = match fetch {
+                        true => Some(schema::columns(&self.config, keyspace, &write.table).await),
+                        false => schema::cached_columns(&self.config, keyspace, &write.table).await,
+                    };
+                    complete &= columns.is_some();
+
+                    let partition_key = columns
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter(|c| c.kind == "partition_key")
+                        .map(|c| c.column_name)
+                        .collect();
+                    keys_cache.insert(key.clone(), partition_key);
+                }
+
+                let partition_key = &keys_cache[&key];
+                let Some(partition) = partition_key
+                    .iter()
+                    .map(|column| values.get(column).cloned())
+                    .collect::<Option<Vec<String>>>()
+                    .filter(|_| !partition_key.is_empty())
+                else {
+                    continue 'batches;
+                };
+                partitions.insert((key.0, key.1, partition));
+            }
+
+            if partitions.len() > 1 {
+                out.push(LintFinding {
+                    rule: "unlogged-batch-multi-partition",
+                    range: batch.range,
+                    message: format!(
+                        "Unlogged batch writes to {} partitions; send the statements separately or use a logged batch",
+                        partitions.len()
+                    ),
+                    statement_line: batch.statement_line,
+                    fixes: vec![],
+                });
+            }
+        }
+
+        complete
+    }
+
+    pub fn finding_to_diagnostic(
+        &self,
+        finding: LintFinding,
+        suppressions: &Suppressions,
+    ) -> Option<Diagnostic> {
+        let severity = self.config.lint_level(finding.rule).severity()?;
+
+        if suppressions.is_suppressed(finding.rule, finding.range.start.line)
+            || suppressions.is_suppressed(finding.rule, finding.statement_line)
+        {
+            return None;
+        }
+
+        let data = if finding.fixes.is_empty() {
+            None
+        } else {
+            serde_json::to_value(&finding.fixes).ok()
+        };
+
+        Some(Diagnostic {
+            range: finding.range,
+            severity: Some(severity),
+            code: Some(NumberOrString::String(finding.rule.to_string())),
+            code_description: Url::parse(&format!("{LINT_DOCS_URL}#{}", finding.rule))
+                .ok()
+                .map(|href| CodeDescription { href }),
+            source: Some("cql".to_string()),
+            message: finding.message,
+            data,
+            ..Default::default()
+        })
+    }
+}
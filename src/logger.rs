@@ -5,7 +5,7 @@
 use dirs::data_dir;
 use std::path::PathBuf;
 
-pub fn setup_logger() -> Result<(), fern::InitError> {
+pub fn setup_logger(level: log::LevelFilter) -> Result<(), fern::InitError> {
     let mut log_path = data_dir().unwrap_or_else(|| PathBuf::from("."));
     log_path.push("cqlls");
     std::fs::create_dir_all(&log_path).expect("Failed to create log directory");
@@ -21,8 +21,9 @@ pub fn setup_logger() -> Result<(), fern::InitError> {
                 message
             ))
         })
-        .level(log::LevelFilter::Info)
-        .chain(std::io::stdout())
+        .level(level)
+        /* stdout is the LSP channel and the output of `cqlls fmt --stdin` */
+        .chain(std::io::stderr())
         .chain(fern::log_file(log_path)?)
         .apply()?;
 
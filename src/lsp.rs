@@ -2,19 +2,63 @@
     Copyright (c) 2026 アクゼスティア. All Rights Reserved.
 */
 
-use crate::config::CqllsConfig;
+use crate::config::{CqllsConfig, DbProfile};
+use crate::config_toml::is_toml;
+use crate::connection::{self, ConnectionState, ConnectionStatus};
+use crate::cqlsh::forget_passwords;
+use crate::schema;
+use crate::workspace::{CONFIG_FILE, TOML_CONFIG_FILE, Workspace, is_config_file, user_config_dir};
 use log::info;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
 use tokio::sync::RwLock;
+use tower_lsp::jsonrpc::Error;
+use tower_lsp::lsp_types::notification::Notification;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer};
 
-#[derive(Debug)]
+pub const SWITCH_PROFILE_COMMAND: &str = "cqlls.switchProfile";
+
+/* `cqlls/profile`, sent on start and whenever the active profile may have changed */
+pub enum ProfileNotification {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProfileParams {
+    pub profile: Option<String>,
+    pub profiles: Vec<String>,
+}
+
+impl Notification for ProfileNotification {
+    type Params = ProfileParams;
+    const METHOD: &'static str = "cqlls/profile";
+}
+
+/* `cqlls/status`, sent whenever the connection to the database changed */
+pub enum StatusNotification {}
+
+impl Notification for StatusNotification {
+    type Params = ConnectionStatus;
+    const METHOD: &'static str = "cqlls/status";
+}
+
+/*
+    Cloning is cheap, the documents and the workspace are shared.
+    A clone with the config of a workspace folder is made for every
+    request, see `for_document`.
+*/
+#[derive(Debug, Clone)]
 pub struct Backend {
     pub client: Client,
-    pub documents: RwLock<HashMap<Url, String>>,
-    pub current_document: RwLock<Option<RwLock<Document>>>,
+    pub documents: Arc<RwLock<HashMap<Url, String>>>,
+    pub current_document: Arc<RwLock<Option<RwLock<Document>>>>,
     pub config: CqllsConfig,
+    pub workspace: Arc<RwLock<Workspace>>,
+    /* what the editor announced in `initialize` */
+    pub capabilities: Arc<RwLock<ClientCapabilities>>,
+    /* the connection status the editor was told about last, per database */
+    pub reported_status: Arc<RwLock<HashMap<DbProfile, ConnectionStatus>>>,
 }
 
 #[derive(Debug, Clone)]
@@ -35,6 +79,257 @@ impl Document {
 }
 
 impl Backend {
+    pub fn new(client: Client, config: CqllsConfig) -> Self {
+        let workspace = Workspace::new(config.clone());
+
+        Self {
+            client,
+            documents: Default::default(),
+            current_document: Default::default(),
+            config,
+            workspace: Arc::new(RwLock::new(workspace)),
+            capabilities: Default::default(),
+            reported_status: Default::default(),
+        }
+    }
+
+    /* the backend with the config of the workspace folder `uri` belongs to */
+    pub async fn for_document(&self, uri: &Url) -> Backend {
+        let mut backend = self.clone();
+
+        let workspace = self.workspace.read().await;
+        backend.config = match workspace.folder_for(uri) {
+            Some(folder) => folder.config.clone(),
+            None => workspace.config.clone(),
+        };
+
+        backend
+    }
+
+    /*
+        Reads every config again and swaps the workspace in one go.
+        Connections are made per request from the config in effect,
+        so new `db` settings are picked up by the next query.
+    */
+    pub async fn reload_config(&self) {
+        forget_passwords().await;
+        connection::reset().await;
+        schema::clear().await;
+
+        let errors = {
+            let mut workspace = self.workspace.write().await;
+            let reloaded = workspace.reloaded();
+            let old = std::mem::replace(&mut *workspace, reloaded);
+
+            /* only what's new, a config broken before was reported already */
+            &workspace.config_errors() - &old.config_errors()
+        };
+
+        self.client
+            .log_message(MessageType::INFO, "Config reloaded")
+            .await;
+        self.show_config_errors(errors).await;
+        self.notify_profile().await;
+        self.report_status().await;
+
+        let documents = self.documents.read().await.clone();
+        for (uri, text) in documents {
+            self.publish_document_diagnostics(uri, text, None).await;
+        }
+    }
+
+    /*
+        Tells the editor about a new connection status, after whatever may have
+        queried the database. Failures are shown as a message once, until the
+        connection works again, a working connection only when it failed before.
+        Each database a config in effect uses is followed on its own.
+    */
+    async fn report_status(&self) {
+        let databases = {
+            let workspace = self.workspace.read().await;
+            let mut databases = vec![];
+            for config in std::iter::once(&workspace.config)
+                .chain(workspace.folders.iter().map(|folder| &folder.config))
+                .filter(|config| config.has_feature("context_aware_completions"))
+            {
+                if !databases.iter().any(|(db, _)| *db == DbProfile::of(config)) {
+                    databases.push((DbProfile::of(config), config.clone()));
+                }
+            }
+            databases
+        };
+
+        for (db, config) in databases {
+            let status = connection::status(&config).await;
+            let previous = {
+                let mut reported = self.reported_status.write().await;
+                if reported.get(&db) == Some(&status) {
+                    continue;
+                }
+                reported.insert(db, status.clone())
+            };
+            let previous = previous.map_or(ConnectionState::Connecting, |p| p.state);
+
+            match status.state {
+                state if state == previous => {}
+                ConnectionState::Connecting => {}
+                ConnectionState::Connected => {
+                    if previous != ConnectionState::Connecting {
+                        let cluster = status.cluster_name.as_deref().unwrap_or("the database");
+                        self.client
+                            .show_message(
+                                MessageType::INFO,
+                                format!("Connected to {cluster} again"),
+                            )
+                            .await;
+                    }
+                }
+                state => {
+                    let error = status.error.as_deref().unwrap_or_default();
+                    self.client
+                        .show_message(MessageType::ERROR, format!("Database {state}: {error}"))
+                        .await;
+                }
+            }
+
+            self.client
+                .send_notification::<StatusNotification>(status)
+                .await;
+        }
+    }
+
+    async fn notify_profile(&self) {
+        let params = {
+            let workspace = self.workspace.read().await;
+            ProfileParams {
+                profile: workspace.active_profile(),
+                profiles: workspace.profile_names().into_iter().collect(),
+            }
+        };
+
+        self.client
+            .send_notification::<ProfileNotification>(params)
+            .await;
+    }
+
+    /* the `cqlls` section of the editor settings, if the editor can be asked */
+    async fn pull_settings(&self) -> Option<serde_json::Value> {
+        let supported = self
+            .capabilities
+            .read()
+            .await
+            .workspace
+            .as_ref()
+            .and_then(|w| w.configuration)
+            .unwrap_or(false);
+        if !supported {
+            return None;
+        }
+
+        let item = ConfigurationItem {
+            scope_uri: None,
+            section: Some("cqlls".to_string()),
+        };
+        match self.client.configuration(vec![item]).await {
+            Ok(mut values) => values.pop().filter(|v| !v.is_null()),
+            Err(e) => {
+                log::error!("Failed to get the editor settings: {e}");
+                None
+            }
+        }
+    }
+
+    /* asks the editor to tell about changes of `.cqlls` and the user level config */
+    async fn watch_config_files(&self) {
+        let dynamic = self
+            .capabilities
+            .read()
+            .await
+            .workspace
+            .as_ref()
+            .and_then(|w| w.did_change_watched_files)
+            .and_then(|w| w.dynamic_registration)
+            .unwrap_or(false);
+        if !dynamic {
+            return;
+        }
+
+        let mut patterns = vec![
+            format!("**/{CONFIG_FILE}"),
+            format!("**/{TOML_CONFIG_FILE}"),
+        ];
+        /* both, a new `config.toml` takes over from `config` */
+        if let Some(dir) = user_config_dir() {
+            patterns.push(dir.join("config").display().to_string());
+            patterns.push(dir.join("config.toml").display().to_string());
+        }
+        let watchers = patterns
+            .into_iter()
+            .map(|pattern| FileSystemWatcher {
+                glob_pattern: GlobPattern::String(pattern),
+                kind: None,
+            })
+            .collect();
+
+        let registration = Registration {
+            id: "cqlls-config-files".to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: serde_json::to_value(DidChangeWatchedFilesRegistrationOptions {
+                watchers,
+            })
+            .ok(),
+        };
+
+        if let Err(e) = self.client.register_capability(vec![registration]).await {
+            log::error!("Failed to watch the config files: {e}");
+        }
+    }
+
+    /* a broken config means the defaults are used, which mustn't go unnoticed */
+    async fn show_config_errors(&self, errors: BTreeSet<String>) {
+        for error in errors {
+            self.client.show_message(MessageType::ERROR, error).await;
+        }
+    }
+
+    /*
+        CQL diagnostics, or the parse error of the text when `uri` is a config.
+        They're published right away with the schema that's cached, the schema
+        lints waiting for the database follow once it answered, unless the
+        document changed in the meantime.
+    */
+    async fn publish_document_diagnostics(&self, uri: Url, text: String, version: Option<i32>) {
+        if is_config_file(&uri) {
+            let diagnostics = Self::config_diagnostics(&uri, &text);
+            self.client
+                .publish_diagnostics(uri, diagnostics, version)
+                .await;
+            return;
+        }
+
+        let backend = self.for_document(&uri).await;
+        let (diagnostics, complete) = backend.cached_diagnostics(&text).await;
+        self.client
+            .publish_diagnostics(uri.clone(), diagnostics, version)
+            .await;
+        if complete {
+            return;
+        }
+
+        let server = self.clone();
+        tokio::spawn(async move {
+            let diagnostics = backend.compute_diagnostics(&text).await;
+            server.report_status().await;
+
+            if server.documents.read().await.get(&uri) == Some(&text) {
+                server
+                    .client
+                    .publish_diagnostics(uri, diagnostics, version)
+                    .await;
+            }
+        });
+    }
+
     // -----------------------------[Helper Functions]-----------------------------
 
     // utils.rs
@@ -54,14 +349,40 @@ impl Backend {
     // -----------------------------[Diagnostics]-----------------------------
 
     // diagnostics.rs
+
+    // -----------------------------[Lint]-----------------------------
+
+    // lint.rs
+
+    // -----------------------------[Code Actions]-----------------------------
+
+    // code_actions.rs
 }
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
     async fn initialize(
         &self,
-        _: InitializeParams,
+        params: InitializeParams,
     ) -> tower_lsp::jsonrpc::Result<InitializeResult> {
+        *self.capabilities.write().await = params.capabilities.clone();
+
+        #[allow(deprecated)]
+        let roots: Vec<Url> = match params.workspace_folders {
+            Some(folders) if !folders.is_empty() => folders.into_iter().map(|f| f.uri).collect(),
+            _ => params.root_uri.into_iter().collect(),
+        };
+
+        {
+            let mut workspace = self.workspace.write().await;
+            workspace.initialization_options = params.initialization_options;
+            *workspace = workspace.reloaded();
+
+            for root in roots.iter().filter_map(|uri| uri.to_file_path().ok()) {
+                workspace.add_folder(root);
+            }
+        }
+
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
@@ -77,13 +398,61 @@ impl LanguageServer for Backend {
                     ]),
                     ..Default::default()
                 }),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
                 document_formatting_provider: Some(OneOf::Left(true)),
+                document_range_formatting_provider: Some(OneOf::Left(true)),
+                document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+                    first_trigger_character: ";".to_string(),
+                    more_trigger_character: Some(vec!["\n".to_string()]),
+                }),
+                code_action_provider: Some(CodeActionProviderCapability::Options(
+                    CodeActionOptions {
+                        code_action_kinds: Some(vec![
+                            CodeActionKind::QUICKFIX,
+                            CodeActionKind::REFACTOR_REWRITE,
+                        ]),
+                        ..Default::default()
+                    },
+                )),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: vec![SWITCH_PROFILE_COMMAND.to_string()],
+                    ..Default::default()
+                }),
+                workspace: Some(WorkspaceServerCapabilities {
+                    workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                        supported: Some(true),
+                        change_notifications: Some(OneOf::Left(true)),
+                    }),
+                    file_operations: None,
+                }),
                 ..Default::default()
             },
             ..Default::default()
         })
     }
 
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        let errors = {
+            let mut workspace = self.workspace.write().await;
+            let old_errors = workspace.config_errors();
+
+            for removed in params.event.removed {
+                if let Ok(root) = removed.uri.to_file_path() {
+                    workspace.remove_folder(&root);
+                }
+            }
+            for added in params.event.added {
+                if let Ok(root) = added.uri.to_file_path() {
+                    workspace.add_folder(root);
+                }
+            }
+
+            &workspace.config_errors() - &old_errors
+        };
+
+        self.show_config_errors(errors).await;
+    }
+
     async fn formatting(
         &self,
         params: DocumentFormattingParams,
@@ -93,16 +462,152 @@ impl LanguageServer for Backend {
         if let Some(current_doc) = self.documents.read().await.get(&document) {
             let lines: Vec<&str> = current_doc.split('\n').collect();
 
-            return Ok(Some(self.format_file(&lines, &document).await));
+            let backend = self.for_document(&document).await;
+            return Ok(Some(backend.format_file(&lines, &document).await));
         } else {
             return Ok(Some(vec![]));
         }
     }
 
+    async fn range_formatting(
+        &self,
+        params: DocumentRangeFormattingParams,
+    ) -> tower_lsp::jsonrpc::Result<Option<Vec<TextEdit>>> {
+        let document = params.text_document.uri;
+        let text = self.documents.read().await.get(&document).cloned();
+
+        match text {
+            Some(text) => Ok(Some(
+                self.for_document(&document)
+                    .await
+                    .format_range(&text, &document, params.range)
+                    .await,
+            )),
+            None => Ok(Some(vec![])),
+        }
+    }
+
+    async fn on_type_formatting(
+        &self,
+        params: DocumentOnTypeFormattingParams,
+    ) -> tower_lsp::jsonrpc::Result<Option<Vec<TextEdit>>> {
+        let position = params.text_document_position.position;
+        let document = params.text_document_position.text_document.uri;
+        let text = self.documents.read().await.get(&document).cloned();
+
+        match text {
+            Some(text) => Ok(Some(
+                self.for_document(&document)
+                    .await
+                    .format_on_type(&text, &document, position, &params.ch)
+                    .await,
+            )),
+            None => Ok(Some(vec![])),
+        }
+    }
+
+    async fn code_action(
+        &self,
+        params: CodeActionParams,
+    ) -> tower_lsp::jsonrpc::Result<Option<CodeActionResponse>> {
+        let uri = params.text_document.uri;
+        let backend = self.for_document(&uri).await;
+        let wants = |kind: &CodeActionKind| match &params.context.only {
+            Some(only) => only.iter().any(|o| kind.as_str().starts_with(o.as_str())),
+            None => true,
+        };
+
+        let mut actions = vec![];
+
+        if wants(&CodeActionKind::QUICKFIX) {
+            actions.extend(backend.quick_fixes(&uri, &params.context.diagnostics));
+        }
+
+        if wants(&CodeActionKind::REFACTOR_REWRITE) {
+            let text = self.documents.read().await.get(&uri).cloned();
+            if let Some(text) = text {
+                actions.extend(backend.refactor_actions(&uri, &text, params.range).await);
+            }
+        }
+        self.report_status().await;
+
+        Ok(Some(actions))
+    }
+
     async fn initialized(&self, _: InitializedParams) {
         self.client
             .log_message(MessageType::INFO, "LSP initialized!")
             .await;
+
+        if let Some(settings) = self.pull_settings().await {
+            let mut workspace = self.workspace.write().await;
+            workspace.settings = Some(settings);
+            *workspace = workspace.reloaded();
+        }
+
+        {
+            let errors = self.workspace.read().await.config_errors();
+            self.show_config_errors(errors).await;
+        }
+        self.notify_profile().await;
+        self.report_status().await;
+
+        self.watch_config_files().await;
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        if params
+            .changes
+            .iter()
+            .any(|change| is_config_file(&change.uri))
+        {
+            self.reload_config().await;
+        }
+    }
+
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        /* editors that don't answer `workspace/configuration` push the settings instead */
+        let settings = match self.pull_settings().await {
+            Some(settings) => Some(settings),
+            None => params
+                .settings
+                .get("cqlls")
+                .filter(|s| !s.is_null())
+                .cloned(),
+        };
+        if let Some(settings) = settings {
+            self.workspace.write().await.settings = Some(settings);
+        }
+
+        self.reload_config().await;
+    }
+
+    async fn execute_command(
+        &self,
+        params: ExecuteCommandParams,
+    ) -> tower_lsp::jsonrpc::Result<Option<Value>> {
+        if params.command != SWITCH_PROFILE_COMMAND {
+            return Err(Error::invalid_params(format!(
+                "unknown command `{}`",
+                params.command
+            )));
+        }
+
+        let Some(name) = params.arguments.first().and_then(Value::as_str) else {
+            return Err(Error::invalid_params("expected the name of a profile"));
+        };
+        {
+            let mut workspace = self.workspace.write().await;
+            if !workspace.profile_names().contains(name) {
+                return Err(Error::invalid_params(format!("unknown profile `{name}`")));
+            }
+            workspace.profile = Some(name.to_string());
+        }
+
+        /* the new `db` settings apply to the schema lints of the open documents too */
+        self.reload_config().await;
+
+        Ok(Some(json!({ "profile": name })))
     }
 
     async fn shutdown(&self) -> tower_lsp::jsonrpc::Result<()> {
@@ -127,10 +632,12 @@ impl LanguageServer for Backend {
                 }
             }
 
-            let diags = self.compute_diagnostics(&change.text).await;
-            self.client
-                .publish_diagnostics(uri.clone(), diags, Some(params.text_document.version))
-                .await;
+            self.publish_document_diagnostics(
+                uri,
+                change.text.clone(),
+                Some(params.text_document.version),
+            )
+            .await;
         }
     }
 
@@ -157,9 +664,7 @@ impl LanguageServer for Backend {
             .log_message(MessageType::INFO, format!("Opened: {}", uri))
             .await;
 
-        let diags = self.compute_diagnostics(&text).await;
-        self.client
-            .publish_diagnostics(uri.clone(), diags, Some(params.text_document.version))
+        self.publish_document_diagnostics(uri.clone(), text, Some(params.text_document.version))
             .await;
 
         self.client
@@ -171,135 +676,45 @@ impl LanguageServer for Backend {
         &self,
         params: CompletionParams,
     ) -> tower_lsp::jsonrpc::Result<Option<CompletionResponse>> {
-        let uri = params.text_document_position.text_document.uri;
-        let position = params.text_document_position.position;
-
-        let documents = self.documents.read().await;
-        let text = match documents.get(&uri) {
-            Some(text) => text,
-            None => return Ok(None),
-        };
-
-        let line = match text.lines().nth(position.line as usize) {
-            Some(line) => line,
-            None => return Ok(None),
-        };
-
-        let in_string = Self::is_in_string_literal(line, position.character);
-        let ssh_keyspaces = self.should_suggest_keyspaces(line, &position);
-        let ssh_graph_types = self.should_suggest_graph_engine_types(line, &position);
-        let ssh_keywords = self.should_suggest_keywords(line, &position).await;
-        let ssh_fields = self.should_suggest_fields(line, &position);
-        let ssh_from = self.should_suggest_from(line, &position);
-        let ssh_table_completions = self.should_suggest_table_completions(line, &position);
-        let ssh_if_not_exists = self.should_suggest_if_not_exists(line, &position);
-        let ssh_create_keywords = self.should_suggest_create_keywords(line, &position);
-        let ssh_alter_keywords = self.should_suggest_alter_keywords(line, &position);
-
-        let ssh_drop_keywords = self.should_suggest_drop_keywords(line, &position);
-        let ssh_drop_keyspaces = self.should_suggest_drop_keyspaces(line, &position);
-        let ssh_drop_tables = self.should_suggest_drop_tables(line, &position);
-
-        let ssh_drop_aggregate = self.should_suggest_drop_aggregate(line, &position);
-        let ssh_drop_function = self.should_suggest_drop_function(line, &position);
-        let ssh_drop_index = self.should_suggest_drop_indexes(line, &position);
-        let ssh_drop_type = self.should_suggest_drop_types(line, &position);
-        let ssh_drop_view = self.should_suggest_drop_views(line, &position);
-
-        let ssh_types = self
-            .should_suggest_types_completions(line, &position, &uri)
-            .await;
-        let ssh_type_modifiers = self
-            .should_suggest_type_modifiers(line, &position, &uri)
-            .await;
-
-        if ssh_keyspaces {
-            return if in_string {
-                self.handle_in_string_keyspace_completion(line, &position)
-                    .await
-            } else {
-                self.handle_out_of_string_keyspace_completion(line, &position)
-                    .await
+        let uri = &params.text_document_position.text_document.uri;
+        if let Some(toml) = config_format(uri) {
+            let documents = self.documents.read().await;
+            let Some(text) = documents.get(uri) else {
+                return Ok(None);
             };
-        }
-
-        if ssh_create_keywords {
-            return self.handle_create_keywords();
-        }
+            let position = params.text_document_position.position;
 
-        if ssh_alter_keywords {
-            return self.handle_alter_keywords();
+            return Ok(Some(CompletionResponse::Array(Self::config_completions(
+                text, position, toml,
+            ))));
         }
 
-        if ssh_drop_keywords {
-            return self.handle_drop_keywords();
-        }
+        let backend = self.for_document(uri).await;
+        let response = Backend::completion(&backend, params).await;
+        self.report_status().await;
 
-        if ssh_drop_keyspaces {
-            return self.handle_drop_keyspace_completions(line, &position).await;
-        }
-
-        if ssh_drop_tables {
-            return self.handle_table_completion(&position).await;
-        }
-
-        if ssh_drop_aggregate {
-            return self.handle_drop_aggregate_completions().await;
-        }
-
-        if ssh_drop_function {
-            return self.handle_drop_function_completions().await;
-        }
-
-        if ssh_drop_index {
-            return self.handle_drop_index_completions().await;
-        }
-
-        if ssh_drop_type {
-            return self.handle_drop_type_completions().await;
-        }
-
-        if ssh_drop_view {
-            return self.handle_drop_view_completions().await;
-        }
-
-        if ssh_fields {
-            return self.handle_fields_completion(line, &position).await;
-        }
-
-        if ssh_from {
-            return self.handle_from_completion();
-        }
-
-        if ssh_table_completions {
-            return self.handle_table_completion(&position).await;
-        }
-
-        if ssh_types {
-            return self.handle_types_completion();
-        }
-
-        if ssh_type_modifiers {
-            return self.handle_type_modifiers_completion(line);
-        }
-
-        if ssh_if_not_exists {
-            return self.handle_if_not_exists();
-        }
+        response
+    }
 
-        if ssh_graph_types {
-            return if in_string {
-                self.handle_in_string_graph_engine_completion(line, &position)
-                    .await
-            } else {
-                self.handle_out_of_string_graph_engine_completion().await
-            };
-        }
+    /* only the config files have something to show */
+    async fn hover(&self, params: HoverParams) -> tower_lsp::jsonrpc::Result<Option<Hover>> {
+        let uri = &params.text_document_position_params.text_document.uri;
+        let Some(toml) = config_format(uri) else {
+            return Ok(None);
+        };
 
-        if ssh_keywords && !in_string {
-            return self.handle_keywords_completion();
-        }
+        let documents = self.documents.read().await;
+        Ok(documents.get(uri).and_then(|text| {
+            Self::config_hover(text, params.text_document_position_params.position, toml)
+        }))
+    }
+}
 
-        Ok(Some(CompletionResponse::Array(vec![])))
+/* `Some(true)` for a TOML config, `Some(false)` for a `.cqlls` one, `None` for CQL */
+fn config_format(uri: &Url) -> Option<bool> {
+    if !is_config_file(uri) {
+        return None;
     }
+
+    uri.to_file_path().ok().map(|path| is_toml(&path))
 }
@@ -2,45 +2,151 @@
     Copyright (c) 2026 アクゼスティア. All Rights Reserved.
 */
 
+use clap::Parser;
+use cqlls::cmd::{self, Cli, Command, LspArgs};
+use cqlls::config::CqllsConfig;
 use cqlls::logger::setup_logger;
 use cqlls::lsp::Backend;
-use cqlls::{cmd, config::*};
-use std::collections::HashMap;
+use cqlls::version::version;
+use cqlls::workspace::Workspace;
+use log::{error, info};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
 use std::panic;
-use tokio::io::{stdin, stdout};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite, stdin, stdout};
+use tokio::net::TcpListener;
 use tokio::sync::RwLock;
 use tower_lsp::{LspService, Server};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = std::env::args().collect();
+    /* clap would read the `-wdc` of older versions as `-w -d -c` */
+    let cli = Cli::parse_from(std::env::args().map(|arg| match arg.as_str() {
+        "-wdc" => "--write-default-config".to_string(),
+        _ => arg,
+    }));
 
-    if args.len() >= 2 {
-        return cmd::exec(&args).await;
+    if cli.version {
+        println!("{}", version());
+        return Ok(());
     }
 
-    let config = CqllsConfig::try_from_config_file();
+    /* the commands load the config themselves and warn about it, this one is for the logger and the server */
+    let (config, ignored, config_error) =
+        match cli.try_read_distrusted(cli.config_path().as_deref()) {
+            Ok((config, ignored)) => (config, ignored, None),
+            Err(e) => (CqllsConfig::default(), None, Some(e)),
+        };
 
-    if config.logging {
-        println!("Setting up logger");
-        setup_logger().unwrap_or_else(|e| println!("{e}"));
+    let level = match cli.log_level {
+        Some(level) => level.into(),
+        None if config.logging => log::LevelFilter::Info,
+        None => log::LevelFilter::Off,
+    };
+    if level != log::LevelFilter::Off {
+        setup_logger(level).unwrap_or_else(|e| eprintln!("{e}"));
         panic::set_hook(Box::new(|info| {
             let msg = format!("{info}\n");
             let _ = std::fs::write("panic.log", msg);
         }));
     }
 
-    let stdin = stdin();
-    let stdout = stdout();
+    let lsp = match &cli.command {
+        Some(Command::Lsp(args)) => args,
+        None if !cli.write_default_config => &LspArgs::default(),
+        _ => {
+            if let Err(e) = cmd::exec(&cli).await {
+                eprintln!("error: {e}");
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+    };
 
-    let (service, socket) = LspService::new(|client| Backend {
-        client,
-        documents: RwLock::new(HashMap::new()),
-        current_document: RwLock::new(None),
-        config,
-    });
+    if let Some(ignored) = &ignored {
+        eprintln!("warning: {ignored}");
+    }
+    if let Some(e) = &config_error {
+        error!("{e}");
+    }
+
+    /* an explicit `--config` is used for every workspace folder */
+    let workspace = Workspace {
+        discover: cli.config.is_none(),
+        trust: cli.trust(),
+        config_path: cli.config_path(),
+        config_error,
+        ..Workspace::new(config)
+    };
+
+    #[cfg(unix)]
+    if let Some(path) = &lsp.socket {
+        /* a socket left behind by a previous run would make the bind fail, anything else isn't ours to delete */
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+            Ok(_) => return Err(format!("`{}` exists and isn't a socket", path.display()).into()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let listener = tokio::net::UnixListener::bind(path)?;
+        info!("Listening on {}", path.display());
+
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    info!("Client connected");
+                    let (read, write) = tokio::io::split(stream);
+                    serve(read, write, remote(&workspace)).await;
+                    info!("Client disconnected");
+                }
+                Err(e) => error!("Failed to accept a client: {e}"),
+            }
+        }
+    }
+
+    if let Some(address) = lsp.tcp {
+        let listener = TcpListener::bind(address).await?;
+        info!("Listening on {}", listener.local_addr()?);
 
-    Server::new(stdin, stdout, socket).serve(service).await;
+        /* one client at a time, the next one is accepted once it disconnects */
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    info!("Client {peer} connected");
+                    let (read, write) = tokio::io::split(stream);
+                    serve(read, write, remote(&workspace)).await;
+                    info!("Client {peer} disconnected");
+                }
+                Err(e) => error!("Failed to accept a client: {e}"),
+            }
+        }
+    }
+
+    serve(stdin(), stdout(), workspace).await;
 
     Ok(())
 }
+
+/* the `initializationOptions` of a client that connected aren't the user's own settings */
+fn remote(workspace: &Workspace) -> Workspace {
+    Workspace {
+        remote: true,
+        ..workspace.clone()
+    }
+}
+
+async fn serve<I, O>(read: I, write: O, workspace: Workspace)
+where
+    I: AsyncRead + Unpin,
+    O: AsyncWrite,
+{
+    let (service, socket) = LspService::new(|client| {
+        let mut backend = Backend::new(client, workspace.config.clone());
+        backend.workspace = Arc::new(RwLock::new(workspace));
+        backend
+    });
+
+    Server::new(read, write, socket).serve(service).await;
+}
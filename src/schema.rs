@@ -0,0 +1,136 @@
+/*
+    Copyright (c) 2026 アクゼスティア. All Rights Reserved.
+*/
+
+/*
+    What cqlls read of the schema, so the diagnostics of every change don't
+    build sessions and query `system_schema` again.
+
+    Entries are per database (see `CqllsConfig::db_key`) and kept for `TTL`.
+    A read that failed is kept as empty too, an unreachable cluster then costs
+    one connect timeout per `TTL` instead of one per keystroke. Reloading the
+    config drops everything.
+*/
+
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use crate::config::CqllsConfig;
+use crate::cqlsh::{self, ColumnKind, Table};
+
+const TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Tables(String),
+    /* database, keyspace and table */
+    Columns(String, String, String),
+}
+
+#[derive(Clone)]
+enum Cached {
+    Tables(Vec<Table>),
+    Columns(Vec<ColumnKind>),
+}
+
+static CACHE: Lazy<Mutex<HashMap<Key, (Instant, Cached)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+async fn get(key: &Key) -> Option<Cached> {
+    let cache = CACHE.lock().await;
+
+    cache
+        .get(key)
+        .filter(|(read_at, _)| read_at.elapsed() < TTL)
+        .map(|(_, cached)| cached.clone())
+}
+
+async fn put(key: Key, cached: Cached) {
+    CACHE.lock().await.insert(key, (Instant::now(), cached));
+}
+
+fn columns_key(config: &CqllsConfig, keyspace: &str, table: &str) -> Key {
+    Key::Columns(
+        config.db_key(),
+        keyspace.to_lowercase(),
+        table.to_lowercase(),
+    )
+}
+
+/* every table of the database, `None` when they have to be read first */
+pub async fn cached_tables(config: &CqllsConfig) -> Option<Vec<Table>> {
+    match get(&Key::Tables(config.db_key())).await? {
+        Cached::Tables(tables) => Some(tables),
+        Cached::Columns(_) => None,
+    }
+}
+
+/* the columns of `keyspace.table`, primary key first, `None` when they have to be read first */
+pub async fn cached_columns(
+    config: &CqllsConfig,
+    keyspace: &str,
+    table: &str,
+) -> Option<Vec<ColumnKind>> {
+    match get(&columns_key(config, keyspace, table)).await? {
+        Cached::Columns(columns) => Some(columns),
+        Cached::Tables(_) => None,
+    }
+}
+
+/* nothing is read, or kept, without `context_aware_completions` */
+pub async fn tables(config: &CqllsConfig) -> Vec<Table> {
+    if !config.has_feature("context_aware_completions") {
+        return vec![];
+    }
+    if let Some(tables) = cached_tables(config).await {
+        return tables;
+    }
+
+    let tables = cqlsh::query_g_tables(config).await.unwrap_or_default();
+    put(Key::Tables(config.db_key()), Cached::Tables(tables.clone())).await;
+    tables
+}
+
+pub async fn columns(config: &CqllsConfig, keyspace: &str, table: &str) -> Vec<ColumnKind> {
+    if !config.has_feature("context_aware_completions") {
+        return vec![];
+    }
+    if let Some(columns) = cached_columns(config, keyspace, table).await {
+        return columns;
+    }
+
+    let columns = cqlsh::query_column_kinds(config, keyspace, table)
+        .await
+        .unwrap_or_default();
+    put(
+        columns_key(config, keyspace, table),
+        Cached::Columns(columns.clone()),
+    )
+    .await;
+    columns
+}
+
+/* the schema as if it was read just now, for tests and tools that know it already */
+pub async fn insert_tables(config: &CqllsConfig, tables: Vec<Table>) {
+    put(Key::Tables(config.db_key()), Cached::Tables(tables)).await;
+}
+
+pub async fn insert_columns(
+    config: &CqllsConfig,
+    keyspace: &str,
+    table: &str,
+    columns: Vec<ColumnKind>,
+) {
+    put(
+        columns_key(config, keyspace, table),
+        Cached::Columns(columns),
+    )
+    .await;
+}
+
+/* the `db` settings changed, or the schema did */
+pub async fn clear() {
+    CACHE.lock().await.clear();
+}
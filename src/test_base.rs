@@ -3,10 +3,8 @@
 */
 
 use crate::config::*;
-use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
-use tokio::sync::RwLock;
 use tower_lsp::lsp_types::*;
 
 use crate::lsp::Backend;
@@ -24,12 +22,7 @@ pub async fn debug_completion(debug_target: &str, line: u32, character: u32) {
         _ => Default::default(),
     };
 
-    let (service, _) = LspService::new(|client| Backend {
-        client,
-        documents: RwLock::new(HashMap::new()),
-        current_document: RwLock::new(None),
-        config,
-    });
+    let (service, _) = LspService::new(|client| Backend::new(client, config));
 
     let test_url = Url::from_file_path(&pt).unwrap();
     let text_test = fs::read_to_string(test_url.to_file_path().unwrap()).unwrap();
@@ -83,12 +76,7 @@ pub async fn debug_format(debug_target: &str) {
         _ => Default::default(),
     };
 
-    let (service, _) = LspService::new(|client| Backend {
-        client,
-        documents: RwLock::new(HashMap::new()),
-        current_document: RwLock::new(None),
-        config,
-    });
+    let (service, _) = LspService::new(|client| Backend::new(client, config));
 
     let test_url = Url::from_file_path(&pt).unwrap();
 
@@ -136,39 +124,21 @@ pub async fn debug_format(debug_target: &str) {
     println!("Formatted: {formatted_str_test}");
 }
 
-pub async fn run_format(cql_test: &Url, cql_expected: &Url) -> bool {
-    let config = match std::fs::read_to_string(".cqlls") {
-        Ok(contents) => parse_config(&contents).unwrap_or_default(),
-        _ => Default::default(),
-    };
-
-    let (service, _) = LspService::new(|client| Backend {
-        client,
-        documents: RwLock::new(HashMap::new()),
-        current_document: RwLock::new(None),
-        config,
-    });
+/*
+    The expected file is exactly what formatting the input
+    gives with the default config
+*/
+pub async fn run_format_exact(cql_test: &Url, cql_expected: &Url) -> bool {
+    let (service, _) = LspService::new(|client| Backend::new(client, Default::default()));
 
     let text_test = fs::read_to_string(cql_test.to_file_path().unwrap()).unwrap();
     let text_expected = fs::read_to_string(cql_expected.to_file_path().unwrap()).unwrap();
-    let backend = service.inner();
 
-    {
-        let mut docs = backend.documents.write().await;
-        docs.insert(cql_test.clone(), text_test.clone());
-        docs.insert(cql_expected.clone(), text_expected.clone());
+    let formatted = service.inner().format_text(&text_test).await;
+    if formatted != text_expected {
+        println!("--- expected\n{text_expected}\n--- formatted\n{formatted}");
     }
-
-    let formatted_str_test = backend
-        .format_test(&text_test.lines().collect(), cql_test)
-        .await;
-
-    let formatted_str_expected = backend
-        .format_test(&text_expected.lines().collect(), cql_expected)
-        .await;
-
-    println!("Eq: {}", formatted_str_test == formatted_str_expected);
-    formatted_str_test == formatted_str_expected
+    formatted == text_expected
 }
 
 pub fn get_pt_pe(test_name: &str) -> (Url, Url) {
@@ -188,3 +158,161 @@ pub fn get_pt_pe(test_name: &str) -> (Url, Url) {
 
     (test_url, expected_url)
 }
+
+pub async fn run_diagnostics(text: &str, config: CqllsConfig) -> Vec<Diagnostic> {
+    let (service, _) = LspService::new(|client| Backend::new(client, config));
+
+    service.inner().compute_diagnostics(text).await
+}
+
+pub async fn run_code_actions(text: &str, config: CqllsConfig) -> Vec<CodeAction> {
+    let (service, _) = LspService::new(|client| Backend::new(client, config));
+
+    let backend = service.inner();
+    let uri = Url::parse("file:///test.cql").unwrap();
+    let diagnostics = backend.compute_diagnostics(text).await;
+
+    backend
+        .quick_fixes(&uri, &diagnostics)
+        .into_iter()
+        .filter_map(|action| match action {
+            CodeActionOrCommand::CodeAction(action) => Some(action),
+            CodeActionOrCommand::Command(_) => None,
+        })
+        .collect()
+}
+
+pub async fn run_refactor_actions(
+    text: &str,
+    range: Range,
+    config: CqllsConfig,
+) -> Vec<CodeAction> {
+    let (service, _) = LspService::new(|client| Backend::new(client, config));
+
+    let backend = service.inner();
+    let uri = Url::parse("file:///test.cql").unwrap();
+
+    backend
+        .refactor_actions(&uri, text, range)
+        .await
+        .into_iter()
+        .filter_map(|action| match action {
+            CodeActionOrCommand::CodeAction(action) => Some(action),
+            CodeActionOrCommand::Command(_) => None,
+        })
+        .collect()
+}
+
+pub fn apply_text_edits(text: &str, edits: &[TextEdit]) -> String {
+    /* positions count UTF-16 code units */
+    let offset = |text: &str, position: Position| {
+        let line_start: usize = text
+            .split('\n')
+            .take(position.line as usize)
+            .map(|l| l.len() + 1)
+            .sum();
+        let mut units = 0;
+        let column = text[line_start..]
+            .char_indices()
+            .find(|(_, c)| {
+                units += c.len_utf16();
+                units > position.character as usize
+            })
+            .map_or(text.len() - line_start, |(i, _)| i);
+        line_start + column
+    };
+
+    let mut edits = edits.to_vec();
+    edits.sort_by_key(|e| std::cmp::Reverse(e.range.start));
+
+    let mut text = text.to_string();
+    for edit in edits {
+        let start = offset(&text, edit.range.start);
+        let end = offset(&text, edit.range.end);
+        text.replace_range(start..end, &edit.new_text);
+    }
+
+    text
+}
+
+pub async fn run_range_format(text: &str, range: Range) -> String {
+    let (service, _) = LspService::new(|client| Backend::new(client, Default::default()));
+
+    let uri = Url::parse("file:///test.cql").unwrap();
+    let edits = service.inner().format_range(text, &uri, range).await;
+
+    apply_text_edits(text, &edits)
+}
+
+pub async fn run_on_type_format(
+    text: &str,
+    position: Position,
+    ch: &str,
+    config: CqllsConfig,
+) -> String {
+    let (service, _) = LspService::new(|client| Backend::new(client, config));
+
+    let uri = Url::parse("file:///test.cql").unwrap();
+    let edits = service
+        .inner()
+        .format_on_type(text, &uri, position, ch)
+        .await;
+
+    apply_text_edits(text, &edits)
+}
+
+pub async fn run_format_edits(text: &str) -> (Vec<TextEdit>, String) {
+    let (service, _) = LspService::new(|client| Backend::new(client, Default::default()));
+
+    let backend = service.inner();
+    let uri = Url::parse("file:///test.cql").unwrap();
+
+    backend
+        .documents
+        .write()
+        .await
+        .insert(uri.clone(), text.to_string());
+
+    let lines: Vec<&str> = text.split('\n').collect();
+    let edits = backend.format_file(&lines, &uri).await;
+    let formatted = backend.format_test(&lines, &uri).await;
+
+    (edits, formatted)
+}
+
+pub async fn run_format_text(text: &str, config: CqllsConfig) -> String {
+    let (service, _) = LspService::new(|client| Backend::new(client, config));
+
+    service.inner().format_text(text).await
+}
+
+pub async fn run_completions(text: &str, position: Position, config: CqllsConfig) -> Vec<String> {
+    let (service, _) = LspService::new(|client| Backend::new(client, config));
+
+    let backend = service.inner();
+    let uri = Url::parse("file:///test.cql").unwrap();
+
+    backend
+        .documents
+        .write()
+        .await
+        .insert(uri.clone(), text.to_string());
+
+    let params = CompletionParams {
+        text_document_position: TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri },
+            position,
+        },
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+        context: None,
+    };
+
+    match backend.completion(params).await {
+        Ok(Some(CompletionResponse::Array(items))) => items.into_iter().map(|i| i.label).collect(),
+        Ok(Some(CompletionResponse::List(list))) => {
+            list.items.into_iter().map(|i| i.label).collect()
+        }
+        _ => vec![],
+    }
+}
@@ -4,7 +4,6 @@
 
 use crate::consts::*;
 use crate::lsp::Backend;
-use log::info;
 
 impl Backend {
     pub fn is_in_string_literal(line: &str, position: u32) -> bool {
@@ -39,28 +38,41 @@ impl Backend {
         in_double_quotes || in_single_quotes
     }
 
-    pub fn line_contains_cql_type(&self, line: &str) -> bool {
-        let split: Vec<&str> = line.split_whitespace().collect();
+    /*
+        Levenshtein distance, used to suggest the closest
+        schema name for a misspelled identifier
+    */
+    pub fn edit_distance(a: &str, b: &str) -> usize {
+        let b_chars: Vec<char> = b.chars().collect();
+        let mut prev: Vec<usize> = (0..=b_chars.len()).collect();
+        let mut curr = vec![0; b_chars.len() + 1];
 
-        info!("{:?} Split", split);
-        let mut is_type = false;
-
-        for w in split {
-            let wlw = w.to_lowercase();
-            if CQL_TYPES_LWC.contains(&wlw.replace(",", "").trim().to_string())
-                || wlw.starts_with("set")
-                || wlw.starts_with("map")
-                || wlw.starts_with("list")
-                || wlw.starts_with("frozen")
-                || wlw.starts_with("\'")
-            {
-                info!("{} ^^", w.to_lowercase().to_string());
-                is_type = true;
-                break;
+        for (i, ca) in a.chars().enumerate() {
+            curr[0] = i + 1;
+            for (j, cb) in b_chars.iter().enumerate() {
+                let cost = if ca == *cb { 0 } else { 1 };
+                curr[j + 1] = (prev[j + 1] + 1).min(curr[j] + 1).min(prev[j] + cost);
             }
+            std::mem::swap(&mut prev, &mut curr);
         }
 
-        is_type
+        prev[b_chars.len()]
+    }
+
+    pub fn closest_match<'a>(name: &str, candidates: &'a [String]) -> Option<&'a String> {
+        let max_distance = (name.chars().count() / 3).max(2);
+
+        candidates
+            .iter()
+            .map(|c| {
+                (
+                    Self::edit_distance(&name.to_lowercase(), &c.to_lowercase()),
+                    c,
+                )
+            })
+            .filter(|(d, _)| *d <= max_distance)
+            .min_by_key(|(d, _)| *d)
+            .map(|(_, c)| c)
     }
 
     pub fn line_contains_cql_kw(&self, line: &str) -> bool {
@@ -76,74 +88,6 @@ impl Backend {
         false
     }
 
-    pub fn is_line_inside_selectors(&self, line: &str, index: usize, lines: &Vec<String>) -> bool {
-        if self.line_contains_cql_kw(line) || line.contains(";") || line.is_empty() {
-            return false;
-        }
-
-        if index == 0 || index == lines.len() - 1 {
-            return false;
-        }
-
-        let lw = line.to_lowercase();
-
-        if lw.contains("values") || lw.contains("from") {
-            return false;
-        }
-
-        let mut index_up = index - 1;
-        let mut index_down = index + 1;
-
-        let mut top_bracket = false;
-        let mut bottom_bracket = false;
-
-        while index_up > 0 {
-            let up_line = &lines[index_up].to_lowercase();
-            if !top_bracket && up_line.contains("select") {
-                top_bracket = true;
-            }
-            if !top_bracket {
-                index_up -= 1;
-            } else {
-                break;
-            }
-        }
-
-        let up_line = &lines[index_up].to_lowercase();
-        if !top_bracket && up_line.contains("select") {
-            top_bracket = true;
-        }
-
-        while index_down < lines.len() {
-            let down_line = &lines[index_down].to_lowercase();
-            if !bottom_bracket && down_line.contains("from") {
-                bottom_bracket = true;
-            }
-
-            if !bottom_bracket && down_line.contains(";") {
-                return false;
-            }
-            if !bottom_bracket {
-                index_down += 1;
-            } else {
-                break;
-            }
-        }
-
-        if top_bracket && bottom_bracket {
-            return true;
-        }
-
-        false
-    }
-
-    pub fn is_multi_line_comment_clause(&self, line: &str) -> bool {
-        if line.contains("/*") || line.contains("*/") {
-            return true;
-        }
-        false
-    }
-
     pub fn is_line_in_multiline_comment_ref(
         &self,
         line: &str,
@@ -205,140 +149,4 @@ impl Backend {
 
         false
     }
-
-    pub fn is_line_in_multiline_comment(
-        &self,
-        line: &str,
-        index: usize,
-        lines: &Vec<String>,
-    ) -> bool {
-        if index == 0 || index == lines.len() - 1 || line.contains("/*") || line.contains("*/") {
-            return false;
-        }
-
-        let mut up_index = index - 1;
-        let mut down_index = index + 1;
-
-        let mut top_line = false;
-        let mut bottom_line = false;
-
-        while up_index > 0 {
-            if !top_line && lines[up_index].contains("/*") {
-                top_line = true;
-            }
-
-            if !top_line && lines[up_index].contains("*/") {
-                return false;
-            }
-
-            if top_line {
-                break;
-            }
-            up_index -= 1;
-        }
-
-        if up_index < line.len() && !top_line && lines[up_index].contains("/*") {
-            top_line = true;
-        }
-
-        if up_index < line.len() && !top_line && lines[up_index].contains("*/") {
-            return false;
-        }
-
-        while down_index < lines.len() {
-            if !bottom_line && lines[down_index].contains("*/") {
-                bottom_line = true;
-            }
-
-            if !bottom_line && lines[down_index].contains("/*") {
-                return false;
-            }
-
-            if bottom_line {
-                break;
-            }
-
-            down_index += 1;
-        }
-
-        if top_line && bottom_line {
-            return true;
-        }
-
-        false
-    }
-
-    pub fn is_line_inside_init_args(&self, line: &str, index: usize, lines: &Vec<String>) -> bool {
-        if self.line_contains_cql_kw(line)
-            || line.contains(";")
-            || line.contains("{")
-            || line.contains("}")
-            || line.contains("(")
-            || line.contains(")")
-        {
-            return false;
-        }
-
-        if index == 0 || index >= lines.len() - 1 {
-            return false;
-        }
-
-        let lw = line.to_lowercase();
-
-        if lw.contains("values") || lw.contains("from") {
-            return false;
-        }
-
-        let mut index_up = index - 1;
-        let mut index_down = index + 1;
-
-        let mut top_bracket = false;
-        let mut bottom_bracket = false;
-
-        loop {
-            let up_line = &lines[index_up];
-            if up_line.contains("{") || up_line.contains("(") {
-                top_bracket = true;
-                break;
-            }
-
-            if up_line.contains("}") || up_line.contains(")") {
-                return false;
-            }
-
-            if self.line_contains_cql_kw(up_line) {
-                return false;
-            }
-
-            if index_up == 0 {
-                break;
-            }
-            index_up -= 1;
-        }
-
-        while index_down < lines.len() {
-            let down_line = &lines[index_down];
-
-            if down_line.contains("}") || down_line.contains(")") {
-                bottom_bracket = true;
-                break;
-            }
-
-            if down_line.contains("{") || down_line.contains("(") {
-                return false;
-            }
-
-            if down_line.contains(";") {
-                return false;
-            }
-
-            if self.line_contains_cql_kw(down_line) {
-                return false;
-            }
-
-            index_down += 1;
-        }
-
-        top_bracket && bottom_bracket
-    }
 }
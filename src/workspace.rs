@@ -0,0 +1,471 @@
+/*
+    Copyright (c) 2026 アクゼスティア. All Rights Reserved.
+*/
+
+/*
+    Where the config of a document comes from.
+
+    Every workspace folder the editor opened gets the nearest `.cqlls.toml`
+    or `.cqlls` at or above its root, or the user level `~/.config/cqlls/config.toml`
+    or `~/.config/cqlls/config` when there's none. Where both formats are
+    next to each other the TOML one is used. Documents outside of every folder use the config
+    the server was started with.
+
+    A config that can't be read or parsed falls back to the defaults,
+    the error is kept around so the editor can be told about it.
+
+    A config found in a folder came with the project. Unless cqlls was
+    started with `--trust-secrets` it doesn't get to read a secret of the
+    machine: `pswd_file` is dropped, so is a `from_cqlshrc` that isn't a
+    relative path inside the project and every value with `${VAR}` in it,
+    the rest of the config is used. The project's own cqlshrc and `pswd_env`
+    are how a committed config takes the connection, they're always read. Unless
+    it was started with `--trust-workspace` it doesn't get to run anything
+    either (`pswd_command`, `udf_formatters`), that also trusts it with the
+    secrets. The user level config and `--config`
+    are the user's own and always trusted. So are `initializationOptions`
+    over stdio, they come from the user's editor. Other editor settings
+    aren't: the ones of a folder come with the project and the
+    `initializationOptions` of `--tcp` and `--socket` clients with whoever
+    connected, they're trusted just as far.
+
+    Settings from the editor go over every config, `initializationOptions`
+    first, then what `workspace/configuration` returned. A setting that
+    doesn't validate leaves the config as the file has it.
+
+    When a config changes the whole workspace is reloaded and swapped,
+    a request never sees some folders with the old config and some with
+    the new one.
+*/
+
+use log::{error, info};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use tower_lsp::lsp_types::Url;
+
+use crate::config::{
+    CqllsConfig, ParseError, apply_settings, apply_untrusted_settings, parse_config_file,
+    parse_project_config_file,
+};
+use crate::cqlshrc::{Cqlshrc, user_cqlshrc_path};
+
+pub const CONFIG_FILE: &str = ".cqlls";
+pub const TOML_CONFIG_FILE: &str = ".cqlls.toml";
+
+/* what a config that came with a project may do */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Trust {
+    #[default]
+    Nothing,
+    /* `--trust-secrets`, read `pswd_file`, a `from_cqlshrc` outside the project and `${VAR}` */
+    Secrets,
+    /* `--trust-workspace`, run `pswd_command` and `udf_formatters` too */
+    Everything,
+}
+
+#[derive(Debug, Clone)]
+pub struct Folder {
+    pub root: PathBuf,
+    /* `None` when no config was found, the defaults are used */
+    pub config_path: Option<PathBuf>,
+    pub config: CqllsConfig,
+    /* why `config_path` couldn't be used, `path:line: message` */
+    pub error: Option<String>,
+    /* what of `config_path` was dropped as it isn't trusted */
+    pub ignored: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    /* `false` when the config was given explicitly (`--config`) */
+    pub discover: bool,
+    /* how far the configs found in folders are trusted */
+    pub trust: Trust,
+    /* served over `--tcp` or `--socket`, `initializationOptions` come from whoever connected */
+    pub remote: bool,
+    pub folders: Vec<Folder>,
+    /* the config the server was started with, for documents outside of every folder */
+    pub config: CqllsConfig,
+    /* `config` without the editor settings */
+    pub base_config: CqllsConfig,
+    pub config_path: Option<PathBuf>,
+    pub config_error: Option<String>,
+    pub config_ignored: Option<String>,
+    pub initialization_options: Option<Value>,
+    /* the `cqlls` section of `workspace/configuration` */
+    pub settings: Option<Value>,
+    pub settings_error: Option<String>,
+    /* what of the editor settings was dropped as they aren't trusted */
+    pub settings_ignored: Option<String>,
+    /* picked with `cqlls.switchProfile`, over every `default_profile` */
+    pub profile: Option<String>,
+}
+
+impl Default for Workspace {
+    fn default() -> Self {
+        Self {
+            discover: true,
+            trust: Trust::Nothing,
+            remote: false,
+            folders: vec![],
+            config: CqllsConfig::default(),
+            base_config: CqllsConfig::default(),
+            config_path: None,
+            config_error: None,
+            config_ignored: None,
+            initialization_options: None,
+            settings: None,
+            settings_error: None,
+            settings_ignored: None,
+            profile: None,
+        }
+    }
+}
+
+impl Workspace {
+    pub fn new(config: CqllsConfig) -> Self {
+        Self {
+            config: config.clone(),
+            base_config: config,
+            ..Default::default()
+        }
+    }
+
+    /* the added folder, `None` when it's known already or discovery is off */
+    pub fn add_folder(&mut self, root: PathBuf) -> Option<&Folder> {
+        if !self.discover || self.folders.iter().any(|f| f.root == root) {
+            return None;
+        }
+
+        let config_path = discover_config(&root, user_config_path().as_deref());
+        let trust = config_path
+            .as_deref()
+            .map_or(Trust::Everything, |path| self.trust_of(path));
+        let (mut config, error) = load_config(config_path.as_deref(), trust);
+        let ignored = config_path
+            .as_deref()
+            .and_then(|path| distrust(&mut config, path, trust));
+        let config = self.with_settings(config);
+
+        info!(
+            "Workspace folder {} uses {}",
+            root.display(),
+            config_path
+                .as_deref()
+                .map_or("the default config".to_string(), |p| p
+                    .display()
+                    .to_string())
+        );
+
+        self.folders.push(Folder {
+            root,
+            config_path,
+            config,
+            error,
+            ignored,
+        });
+        self.folders.last()
+    }
+
+    pub fn remove_folder(&mut self, root: &Path) {
+        self.folders.retain(|f| f.root != root);
+    }
+
+    /* the same folders with every config read again */
+    pub fn reloaded(&self) -> Workspace {
+        /* without a path the config was given as it is */
+        let trust = self
+            .config_path
+            .as_deref()
+            .map_or(Trust::Everything, |path| self.trust_of(path));
+        let (mut base_config, config_error) = match &self.config_path {
+            Some(path) => load_config(Some(path), trust),
+            None => (self.base_config.clone(), None),
+        };
+        let config_ignored = self
+            .config_path
+            .as_deref()
+            .and_then(|path| distrust(&mut base_config, path, trust));
+        let mut workspace = Workspace {
+            discover: self.discover,
+            trust: self.trust,
+            remote: self.remote,
+            folders: vec![],
+            config: base_config.clone(),
+            base_config,
+            config_path: self.config_path.clone(),
+            config_error,
+            config_ignored,
+            initialization_options: self.initialization_options.clone(),
+            settings: self.settings.clone(),
+            settings_error: None,
+            settings_ignored: None,
+            profile: self.profile.clone(),
+        };
+        workspace.config = workspace.with_settings(workspace.config.clone());
+
+        for folder in &self.folders {
+            workspace.add_folder(folder.root.clone());
+        }
+
+        workspace
+    }
+
+    /* how far the config at `path` is trusted */
+    fn trust_of(&self, path: &Path) -> Trust {
+        match !self.discover || is_user_config(path) {
+            true => Trust::Everything,
+            false => self.trust,
+        }
+    }
+
+    /* the innermost folder containing the document */
+    pub fn folder_for(&self, uri: &Url) -> Option<&Folder> {
+        let path = uri.to_file_path().ok()?;
+
+        self.folders
+            .iter()
+            .filter(|f| path.starts_with(&f.root))
+            .max_by_key(|f| f.root.components().count())
+    }
+
+    /* the editor settings over `config`, then the profile picked at runtime */
+    fn with_settings(&mut self, mut config: CqllsConfig) -> CqllsConfig {
+        self.settings_error = None;
+        self.settings_ignored = None;
+
+        let mut ignored = vec![];
+        let initialization_trust = match self.remote {
+            true => self.trust,
+            false => Trust::Everything,
+        };
+        let editor = [
+            (&self.initialization_options, initialization_trust),
+            (&self.settings, self.trust),
+        ];
+        for (settings, trust) in editor {
+            let Some(settings) = settings else {
+                continue;
+            };
+            let applied = match trust {
+                Trust::Everything => {
+                    apply_settings(config.clone(), settings).map(|applied| (applied, vec![]))
+                }
+                trust => {
+                    apply_untrusted_settings(config.clone(), settings, trust == Trust::Secrets)
+                }
+            };
+            match applied {
+                Ok((applied, dropped)) => {
+                    config = applied;
+                    for key in dropped {
+                        if !ignored.contains(&key) {
+                            ignored.push(key);
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!("Editor settings: {e}");
+                    self.settings_error = Some(format!("editor settings: {e}"));
+                }
+            }
+        }
+        if !ignored.is_empty() {
+            self.settings_ignored = Some(ignoring("editor settings", &ignored));
+        }
+
+        /* a folder without that profile keeps its own */
+        if let Some(name) = &self.profile {
+            config.use_profile(name);
+        }
+
+        config
+    }
+
+    /* every profile of every config */
+    pub fn profile_names(&self) -> BTreeSet<String> {
+        self.folders
+            .iter()
+            .map(|f| &f.config)
+            .chain([&self.config])
+            .flat_map(|config| config.profiles.keys().cloned())
+            .collect()
+    }
+
+    /* for the status line, the one of the first folder when they differ */
+    pub fn active_profile(&self) -> Option<String> {
+        self.folders
+            .first()
+            .map_or(&self.config, |f| &f.config)
+            .profile
+            .clone()
+    }
+
+    /* every config error for the editor, once even when several folders share the config */
+    pub fn config_errors(&self) -> BTreeSet<String> {
+        self.config_error
+            .iter()
+            .chain(self.folders.iter().filter_map(|f| f.error.as_ref()))
+            .map(|e| format!("{e}, using the default config"))
+            .chain(
+                self.config_ignored
+                    .iter()
+                    .chain(self.folders.iter().filter_map(|f| f.ignored.as_ref()))
+                    .chain(&self.settings_ignored)
+                    .cloned(),
+            )
+            .chain(
+                self.settings_error
+                    .iter()
+                    .map(|e| format!("{e}, ignoring the editor settings")),
+            )
+            .collect()
+    }
+}
+
+/* `.cqlls` anywhere, or the user level config */
+pub fn is_config_file(uri: &Url) -> bool {
+    let Ok(path) = uri.to_file_path() else {
+        return false;
+    };
+
+    path.file_name()
+        .is_some_and(|name| name == CONFIG_FILE || name == TOML_CONFIG_FILE)
+        || user_config_dir().is_some_and(|dir| {
+            path.parent() == Some(dir.as_path())
+                && path
+                    .file_name()
+                    .is_some_and(|name| name == "config" || name == "config.toml")
+        })
+}
+
+/* `~/.config/cqlls/config` or `~/.config/cqlls/config.toml` */
+pub fn is_user_config(path: &Path) -> bool {
+    user_config_dir().is_some_and(|dir| path.parent() == Some(dir.as_path()))
+}
+
+/*
+    Drops what a config trusted only as far as `trust` may not use,
+    what was dropped as a message for the user.
+*/
+pub fn distrust(config: &mut CqllsConfig, path: &Path, trust: Trust) -> Option<String> {
+    let mut dropped: Vec<String> = vec![];
+    if trust < Trust::Secrets {
+        dropped.extend(config.drop_secrets().into_iter().map(String::from));
+    }
+    if trust < Trust::Everything {
+        dropped.extend(config.drop_commands().into_iter().map(String::from));
+    }
+    /* what used `${VAR}` or a cqlshrc outside the project, already left out when it was read */
+    for key in std::mem::take(&mut config.left_out) {
+        if !dropped.contains(&key) {
+            dropped.push(key);
+        }
+    }
+    if dropped.is_empty() {
+        return None;
+    }
+
+    Some(ignoring(&path.display().to_string(), &dropped))
+}
+
+/* what of `source` was dropped, for the user, with the flag that allows all of it */
+fn ignoring(source: &str, dropped: &[String]) -> String {
+    let keys: Vec<String> = dropped.iter().map(|key| format!("`{key}`")).collect();
+    let listed = match keys.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} and {last}", rest.join(", ")),
+        _ => keys.concat(),
+    };
+    let flag = match dropped
+        .iter()
+        .any(|key| ["pswd_command", "udf_formatters"].contains(&key.as_str()))
+    {
+        true => "--trust-workspace",
+        false => "--trust-secrets",
+    };
+
+    format!("{source}: ignoring {listed}, start cqlls with `{flag}` to use them")
+}
+
+/* `~/.config/cqlls` */
+pub fn user_config_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".config").join("cqlls"))
+}
+
+/* `~/.config/cqlls/config.toml` if there's one, otherwise `~/.config/cqlls/config` */
+pub fn user_config_path() -> Option<PathBuf> {
+    let dir = user_config_dir()?;
+    let toml = dir.join("config.toml");
+
+    match toml.is_file() {
+        true => Some(toml),
+        false => Some(dir.join("config")),
+    }
+}
+
+/*
+    The nearest `.cqlls.toml` or `.cqlls` in `start` or one of its parents,
+    otherwise `user_config` if it exists.
+*/
+pub fn discover_config(start: &Path, user_config: Option<&Path>) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .flat_map(|dir| [dir.join(TOML_CONFIG_FILE), dir.join(CONFIG_FILE)])
+        .find(|path| path.is_file())
+        .or_else(|| user_config.filter(|p| p.is_file()).map(Path::to_path_buf))
+}
+
+/* the config at `path`, or why it can't be used as `path:line: message` */
+pub fn read_config(path: &Path) -> Result<CqllsConfig, String> {
+    read_with(path, parse_config_file)
+}
+
+/* the same for a config that may not read secrets, `distrust` it after */
+pub fn read_project_config(path: &Path) -> Result<CqllsConfig, String> {
+    read_with(path, parse_project_config_file)
+}
+
+fn read_with(
+    path: &Path,
+    parse: fn(&str, &Path) -> Result<CqllsConfig, ParseError>,
+) -> Result<CqllsConfig, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("{}: can't read the config: {e}", path.display()))?;
+
+    parse(&contents, path).map_err(|e| format!("{}:{}: {}", path.display(), e.line, e.msg))
+}
+
+/* the defaults when there's no config or it's broken, with the error */
+fn load_config(path: Option<&Path>, trust: Trust) -> (CqllsConfig, Option<String>) {
+    let read = match trust {
+        Trust::Nothing => read_project_config,
+        _ => read_config,
+    };
+
+    match path.map(read) {
+        Some(Ok(config)) => (config, None),
+        Some(Err(e)) => {
+            error!("{e}");
+            (CqllsConfig::default(), Some(e))
+        }
+        None => (default_config(), None),
+    }
+}
+
+/* without any config, the connection cqlsh uses from `~/.cassandra/cqlshrc` */
+pub fn default_config() -> CqllsConfig {
+    let mut config = CqllsConfig::default();
+
+    if let Some(path) = user_cqlshrc_path().filter(|p| p.is_file()) {
+        match Cqlshrc::read(&path) {
+            Ok(cqlshrc) => {
+                info!("No config found, connecting as {}", path.display());
+                cqlshrc.apply(&mut config);
+            }
+            Err(e) => error!("Failed to read {}: {e}", path.display()),
+        }
+    }
+
+    config
+}
@@ -3,7 +3,13 @@
 */
 
 use cqlls::config::*;
-use cqlls::test_base::{get_pt_pe, run_format};
+use cqlls::lint::LintLevel;
+use cqlls::test_base::{
+    apply_text_edits, get_pt_pe, run_code_actions, run_completions, run_diagnostics,
+    run_format_edits, run_format_exact, run_format_text, run_on_type_format, run_range_format,
+    run_refactor_actions,
+};
+use tower_lsp::lsp_types::{DiagnosticSeverity, NumberOrString, Position, Range, TextEdit};
 
 const SAMPLE: &str = r#"
     db {
@@ -64,272 +70,3183 @@ fn test_defaults_preserved() {
     assert!(!cfg.logging);
 }
 
+#[test]
+fn test_parse_lint() {
+    let cfg = parse_config("lint {\nallow-filtering: error\nselect-star: \"off\"\n}\n").unwrap();
+    assert_eq!(cfg.lint_level("allow-filtering"), LintLevel::Error);
+    assert_eq!(cfg.lint_level("select-star"), LintLevel::Off);
+    assert_eq!(cfg.lint_level("drop-without-if-exists"), LintLevel::Warning);
+}
+
+#[test]
+fn test_unknown_lint_rule() {
+    let err = parse_config("lint {\nno-such-rule: warning\n}\n").unwrap_err();
+    assert!(err.msg.contains("unknown lint rule"));
+}
+
+fn codes(diags: &[tower_lsp::lsp_types::Diagnostic]) -> Vec<String> {
+    diags
+        .iter()
+        .filter_map(|d| match &d.code {
+            Some(NumberOrString::String(code)) => Some(code.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_lint_rules() {
+    let mut cfg = parse_config("features {\ndiagnostics: true\n}\n").unwrap();
+    cfg.lint
+        .insert("allow-filtering".to_string(), LintLevel::Error);
+
+    let text = "SELECT * FROM ks.users WHERE id = 1 ALLOW FILTERING;\n\
+                DROP TABLE ks.users;\n\
+                BEGIN UNLOGGED BATCH\n\
+                INSERT INTO ks.a (id) VALUES (1);\n\
+                UPDATE ks.b SET v = 1 WHERE id = 2;\n\
+                APPLY BATCH;\n";
+    let diags = run_diagnostics(text, cfg).await;
+
+    /* without the schema the partitions of the batch are unknown */
+    assert_eq!(
+        codes(&diags),
+        vec!["allow-filtering", "select-star", "drop-without-if-exists"]
+    );
+    assert_eq!(diags[0].severity, Some(DiagnosticSeverity::ERROR));
+    assert_eq!(diags[1].severity, Some(DiagnosticSeverity::HINT));
+    assert!(diags[0].code_description.is_some());
+}
+
+#[tokio::test]
+async fn test_lint_unlogged_batch_partitions() {
+    use cqlls::cqlsh::ColumnKind;
+    use cqlls::schema;
+
+    let column = |name: &str, kind: &str| ColumnKind {
+        column_name: name.to_string(),
+        kind: kind.to_string(),
+        position: 0,
+    };
+    let mut cfg = CqllsConfig::with_knodes(vec!["unlogged-batch.invalid:9042".to_string()]);
+    cfg.features.push("diagnostics".to_string());
+    schema::insert_columns(
+        &cfg,
+        "ks",
+        "events",
+        vec![
+            column("id", "partition_key"),
+            column("day", "partition_key"),
+            column("seq", "clustering"),
+            column("v", "regular"),
+        ],
+    )
+    .await;
+
+    let batch = |statements: &str| format!("BEGIN UNLOGGED BATCH\n{statements}APPLY BATCH;\n");
+    let cases = [
+        /* the clustering column and the rest of the WHERE don't make another partition */
+        (
+            "INSERT INTO ks.events (id, day, seq, v) VALUES (1, 'mon', 1, 'a');\n\
+             UPDATE ks.events SET v = 'b' WHERE id = 1 AND day =  'mon' AND seq = 2;\n\
+             DELETE FROM ks.events WHERE day = 'mon' AND id = 1 AND seq > 3;\n",
+            None,
+        ),
+        (
+            "INSERT INTO ks.events (id, day, seq, v) VALUES (1, 'mon', 1, 'a');\n\
+             UPDATE ks.events SET v = 'b' WHERE id = 1 AND day = 'tue' AND seq = 2;\n\
+             DELETE FROM ks.events WHERE id = 2 AND day = 'mon';\n",
+            Some(
+                "Unlogged batch writes to 3 partitions; send the statements separately or use a logged batch",
+            ),
+        ),
+        /* a value that isn't known or a table without a keyspace, nothing is reported */
+        (
+            "UPDATE ks.events SET v = 'b' WHERE token(id, day) = 1;\n\
+             DELETE FROM ks.events WHERE id = 2 AND day = 'mon';\n",
+            None,
+        ),
+        (
+            "UPDATE events SET v = 'b' WHERE id = 1 AND day = 'mon';\n\
+             DELETE FROM ks.events WHERE id = 2 AND day = 'mon';\n",
+            None,
+        ),
+    ];
+
+    for (statements, expected) in cases {
+        let text = batch(statements);
+        let diags = run_diagnostics(&text, cfg.clone()).await;
+        let messages: Vec<&str> = diags
+            .iter()
+            .filter(|d| {
+                d.code
+                    == Some(NumberOrString::String(
+                        "unlogged-batch-multi-partition".into(),
+                    ))
+            })
+            .map(|d| d.message.as_str())
+            .collect();
+        assert_eq!(messages, expected.into_iter().collect::<Vec<_>>(), "{text}");
+    }
+}
+
+#[tokio::test]
+async fn test_lint_suppression() {
+    let cfg = parse_config("features {\ndiagnostics: true\n}\n").unwrap();
+
+    let text = "-- cqlls-disable-next-line allow-filtering\n\
+                SELECT id FROM ks.users\n\
+                WHERE name = 'x' ALLOW FILTERING;\n\
+                DROP TABLE IF EXISTS ks.users;\n\
+                /* cqlls-disable */\n\
+                SELECT * FROM ks.users;\n";
+    let diags = run_diagnostics(text, cfg).await;
+
+    assert!(diags.is_empty(), "{:?}", codes(&diags));
+}
+
+fn apply_fix(text: &str, action: &tower_lsp::lsp_types::CodeAction) -> String {
+    let changes = action.edit.as_ref().unwrap().changes.as_ref().unwrap();
+    apply_text_edits(text, changes.values().next().unwrap())
+}
+
+#[tokio::test]
+async fn test_quick_fixes() {
+    let cfg = parse_config("features {\ndiagnostics: true\n}\n").unwrap();
+
+    let cases = [
+        (
+            "USE ks;\nSELECT id FROM users\n\nSELECT id FROM users;\n",
+            "Add missing `;`",
+            "USE ks;\nSELECT id FROM users;\n\nSELECT id FROM users;\n",
+        ),
+        (
+            "SELECT id FROM ks.t WHERE v = 'héllo' -- note\nSELECT id FROM ks.t;\n",
+            "Add missing `;`",
+            "SELECT id FROM ks.t WHERE v = 'héllo'; -- note\nSELECT id FROM ks.t;\n",
+        ),
+        (
+            "CREATE TABLE ks.t (id int PRIMARY KEY);\n",
+            "Add `IF NOT EXISTS`",
+            "CREATE TABLE IF NOT EXISTS ks.t (id int PRIMARY KEY);\n",
+        ),
+        (
+            "DROP MATERIALIZED VIEW ks.v;\n",
+            "Add `IF EXISTS`",
+            "DROP MATERIALIZED VIEW IF EXISTS ks.v;\n",
+        ),
+        (
+            "SELECT id FROM ks.t WHERE v = 1 ALLOW FILTERING;\n",
+            "Remove `ALLOW FILTERING`",
+            "SELECT id FROM ks.t WHERE v = 1;\n",
+        ),
+        (
+            "SELECT id FROM ks.t WHERE v = 'héllo' ALLOW FILTERING;\n",
+            "Remove `ALLOW FILTERING`",
+            "SELECT id FROM ks.t WHERE v = 'héllo';\n",
+        ),
+        (
+            "CREATE INDEX IF NOT EXISTS idx ON ks.t (token);\n",
+            "Quote as `\"token\"`",
+            "CREATE INDEX IF NOT EXISTS idx ON ks.t (\"token\");\n",
+        ),
+    ];
+
+    for (text, title, expected) in cases {
+        let actions = run_code_actions(text, cfg.clone()).await;
+        let action = actions
+            .iter()
+            .find(|a| a.title == title)
+            .unwrap_or_else(|| panic!("no `{title}` fix for {text:?}"));
+        assert_eq!(action.diagnostics.as_ref().map(|d| d.len()), Some(1));
+        assert_eq!(apply_fix(text, action), expected);
+    }
+}
+
 #[tokio::test]
 async fn test_alter_keyspace() {
     let (pt, pe) = get_pt_pe("alter_keyspace");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_alter_materialized_view() {
     let (pt, pe) = get_pt_pe("alter_materialized_view");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_alter_role() {
     let (pt, pe) = get_pt_pe("alter_role");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_alter_table() {
     let (pt, pe) = get_pt_pe("alter_table");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_alter_type() {
     let (pt, pe) = get_pt_pe("alter_type");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_alter_user() {
     let (pt, pe) = get_pt_pe("alter_user");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_batch() {
     let (pt, pe) = get_pt_pe("batch");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
+}
+
+#[tokio::test]
+async fn test_batch_indent() {
+    let (pt, pe) = get_pt_pe("batch_indent");
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_comments() {
     let (pt, pe) = get_pt_pe("comments");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
+}
+
+#[tokio::test]
+async fn test_comma_comments() {
+    let (pt, pe) = get_pt_pe("comma_comments");
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_commit_search_index() {
     let (pt, pe) = get_pt_pe("commit_search_index");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_consistency_level() {
     let (pt, pe) = get_pt_pe("consistency_level");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_create_aggregate() {
     let (pt, pe) = get_pt_pe("create_aggregate");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_create_function() {
     let (pt, pe) = get_pt_pe("create_function");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_create_index() {
     let (pt, pe) = get_pt_pe("create_index");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_create_keyspace() {
     let (pt, pe) = get_pt_pe("create_keyspace");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_create_materialized_view() {
     let (pt, pe) = get_pt_pe("create_materialized_view");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_create_role() {
     let (pt, pe) = get_pt_pe("create_role");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_create_search_index() {
     let (pt, pe) = get_pt_pe("create_search_index");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_create_table() {
     let (pt, pe) = get_pt_pe("create_table");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_create_type() {
     let (pt, pe) = get_pt_pe("create_type");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_create_user() {
     let (pt, pe) = get_pt_pe("create_user");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_delete() {
     let (pt, pe) = get_pt_pe("delete");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
+}
+
+#[tokio::test]
+async fn test_dml_layout() {
+    let (pt, pe) = get_pt_pe("dml_layout");
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_drop_aggregate() {
     let (pt, pe) = get_pt_pe("drop_aggregate");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_drop_function() {
     let (pt, pe) = get_pt_pe("drop_function");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_drop_index() {
     let (pt, pe) = get_pt_pe("drop_index");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_drop_keyspace() {
     let (pt, pe) = get_pt_pe("drop_keyspace");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_drop_materialized_view() {
     let (pt, pe) = get_pt_pe("drop_materialized_view");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_drop_role() {
     let (pt, pe) = get_pt_pe("drop_role");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_drop_search_index() {
     let (pt, pe) = get_pt_pe("drop_search_index");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_drop_table() {
     let (pt, pe) = get_pt_pe("drop_table");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_drop_type() {
     let (pt, pe) = get_pt_pe("drop_type");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_drop_user() {
     let (pt, pe) = get_pt_pe("drop_user");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_grant_permission() {
     let (pt, pe) = get_pt_pe("grant_permission");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_grant_role() {
     let (pt, pe) = get_pt_pe("grant_role");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_having() {
     let (pt, pe) = get_pt_pe("having");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_insert() {
     let (pt, pe) = get_pt_pe("insert");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
+}
+
+#[tokio::test]
+async fn test_keyspace_options() {
+    let (pt, pe) = get_pt_pe("keyspace_options");
+    assert!(run_format_exact(&pt, &pe).await)
+}
+
+#[tokio::test]
+async fn test_literals_comments() {
+    let (pt, pe) = get_pt_pe("literals_comments");
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_list_permissions() {
     let (pt, pe) = get_pt_pe("list_permissions");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_list_roles() {
     let (pt, pe) = get_pt_pe("list_roles");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_list_users() {
     let (pt, pe) = get_pt_pe("list_users");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_primary_key() {
     let (pt, pe) = get_pt_pe("primary_key");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_revoke_permissions() {
     let (pt, pe) = get_pt_pe("revoke_permissions");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_revoke_role() {
     let (pt, pe) = get_pt_pe("revoke_role");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
+}
+
+#[tokio::test]
+async fn test_max_width() {
+    let (pt, pe) = get_pt_pe("max_width");
+    assert!(run_format_exact(&pt, &pe).await)
+}
+
+#[tokio::test]
+async fn test_missing_semicolon() {
+    let (pt, pe) = get_pt_pe("missing_semicolon");
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_select() {
     let (pt, pe) = get_pt_pe("select");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
+}
+
+#[tokio::test]
+async fn test_table_options() {
+    let (pt, pe) = get_pt_pe("table_options");
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_truncate() {
     let (pt, pe) = get_pt_pe("truncate");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_update() {
     let (pt, pe) = get_pt_pe("update");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
+}
+
+#[tokio::test]
+async fn test_update_set() {
+    let (pt, pe) = get_pt_pe("update_set");
+    assert!(run_format_exact(&pt, &pe).await)
 }
 
 #[tokio::test]
 async fn test_use() {
     let (pt, pe) = get_pt_pe("use");
-    assert!(run_format(&pt, &pe).await)
+    assert!(run_format_exact(&pt, &pe).await)
+}
+
+#[tokio::test]
+async fn test_using_if() {
+    let (pt, pe) = get_pt_pe("using_if");
+    assert!(run_format_exact(&pt, &pe).await)
+}
+
+#[tokio::test]
+async fn test_where_and() {
+    let (pt, pe) = get_pt_pe("where_and");
+    assert!(run_format_exact(&pt, &pe).await)
+}
+
+#[tokio::test]
+async fn test_refactor_actions() {
+    let whole = Range::new(Position::new(0, 0), Position::new(100, 0));
+
+    let cases = [
+        (
+            "USE ks;\nSELECT id FROM users;\nSELECT id FROM other.users;\n",
+            "Qualify table names with the `USE` keyspace",
+            "USE ks;\nSELECT id FROM ks.users;\nSELECT id FROM other.users;\n",
+        ),
+        (
+            "USE ks;\nSELECT id FROM ks.users;\nSELECT id FROM other.users;\n",
+            "Remove keyspace qualifiers matching `USE`",
+            "USE ks;\nSELECT id FROM users;\nSELECT id FROM other.users;\n",
+        ),
+        (
+            "UPDATE ks.users SET name = 'and' WHERE id = 1 AND day = '2026-01-01';\n",
+            "Convert to `INSERT INTO ... VALUES`",
+            "INSERT INTO ks.users (id, day, name) VALUES (1, '2026-01-01', 'and');\n",
+        ),
+        (
+            "INSERT INTO ks.t (id, v) VALUES (1, 2);\n\nDELETE FROM ks.t WHERE id = 1;\n",
+            "Wrap in `BEGIN BATCH ... APPLY BATCH`",
+            "BEGIN BATCH\n    INSERT INTO ks.t (id, v) VALUES (1, 2);\n\n    DELETE FROM ks.t WHERE id = 1;\nAPPLY BATCH;\n",
+        ),
+        (
+            "DELETE FROM ks.t WHERE id = 1;\nINSERT INTO ks.t (id, v) VALUES (1, 'é');\n",
+            "Wrap in `BEGIN BATCH ... APPLY BATCH`",
+            "BEGIN BATCH\n    DELETE FROM ks.t WHERE id = 1;\n    INSERT INTO ks.t (id, v) VALUES (1, 'é');\nAPPLY BATCH;\n",
+        ),
+    ];
+
+    for (text, title, expected) in cases {
+        let actions = run_refactor_actions(text, whole, CqllsConfig::default()).await;
+        let action = actions
+            .iter()
+            .find(|a| a.title == title)
+            .unwrap_or_else(|| panic!("no `{title}` action for {text:?}"));
+
+        assert_eq!(apply_fix(text, action), expected);
+    }
+}
+
+#[tokio::test]
+async fn test_refactor_actions_not_offered() {
+    let whole = Range::new(Position::new(0, 0), Position::new(100, 0));
+
+    let cases = [
+        (
+            "INSERT INTO ks.t (id, v) VALUES (1, 2) IF NOT EXISTS;\n",
+            "Convert to `UPDATE ... SET`",
+        ),
+        (
+            "INSERT INTO ks.users (id, name) VALUES (1, 'a');\n",
+            "Convert to `UPDATE ... SET`",
+        ),
+        (
+            "UPDATE ks.t SET v = 2 WHERE id = 1 IF v = 1;\n",
+            "Convert to `INSERT INTO ... VALUES`",
+        ),
+        (
+            "UPDATE ks.t SET hits = hits + 1 WHERE id = 1;\n",
+            "Convert to `INSERT INTO ... VALUES`",
+        ),
+        (
+            "UPDATE ks.t SET v = 2 WHERE id IN (1, 2);\n",
+            "Convert to `INSERT INTO ... VALUES`",
+        ),
+        (
+            "INSERT INTO ks.t (id, v) VALUES (1, 2);\nSELECT * FROM ks.t;\n",
+            "Wrap in `BEGIN BATCH ... APPLY BATCH`",
+        ),
+        (
+            "SELECT id FROM users;\n",
+            "Qualify table names with the `USE` keyspace",
+        ),
+    ];
+
+    for (text, title) in cases {
+        let actions = run_refactor_actions(text, whole, CqllsConfig::default()).await;
+        assert!(
+            actions.iter().all(|a| a.title != title),
+            "unexpected `{title}` action for {text:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_refactor_actions_with_schema() {
+    use cqlls::cqlsh::ColumnKind;
+    use cqlls::schema;
+
+    let whole = Range::new(Position::new(0, 0), Position::new(100, 0));
+    let column = |name: &str, kind: &str| ColumnKind {
+        column_name: name.to_string(),
+        kind: kind.to_string(),
+        position: 0,
+    };
+
+    let cfg = CqllsConfig::with_knodes(vec!["refactor-schema.invalid:9042".to_string()]);
+    schema::insert_columns(
+        &cfg,
+        "ks",
+        "events",
+        vec![
+            column("id", "partition_key"),
+            column("day", "clustering"),
+            column("name", "regular"),
+            column("Display Name", "regular"),
+            column("select", "regular"),
+        ],
+    )
+    .await;
+
+    let cases = [
+        (
+            "INSERT INTO ks.events (id, day, name, tags) VALUES (1, '2026-01-01', 'a, b', {'x'}) USING TTL 10;\n",
+            "Convert to `UPDATE ... SET`",
+            "UPDATE ks.events USING TTL 10 SET name = 'a, b', tags = {'x'} WHERE id = 1 AND day = '2026-01-01';\n",
+        ),
+        (
+            "SELECT * FROM ks.events;\n",
+            "Expand `*` into the column list",
+            "SELECT id, day, name, \"Display Name\", \"select\" FROM ks.events;\n",
+        ),
+    ];
+
+    for (text, title, expected) in cases {
+        let actions = run_refactor_actions(text, whole, cfg.clone()).await;
+        let action = actions
+            .iter()
+            .find(|a| a.title == title)
+            .unwrap_or_else(|| panic!("no `{title}` action for {text:?}"));
+
+        assert_eq!(apply_fix(text, action), expected);
+    }
+
+    /* `day` is part of the key, an UPDATE without it isn't valid */
+    let text = "INSERT INTO ks.events (id, name) VALUES (1, 'a');\n";
+    let actions = run_refactor_actions(text, whole, cfg.clone()).await;
+    assert!(
+        actions
+            .iter()
+            .all(|a| a.title != "Convert to `UPDATE ... SET`")
+    );
+}
+
+#[tokio::test]
+async fn test_range_format() {
+    let text = "SELECT   id FROM ks.t;\n\n-- keep   me\nselect   id    from ks.t;\nSELECT   id FROM ks.t;\n";
+
+    let line = Range::new(Position::new(3, 2), Position::new(3, 2));
+    assert_eq!(
+        run_range_format(text, line).await,
+        "SELECT   id FROM ks.t;\n\n-- keep   me\nselect id from ks.t;\nSELECT   id FROM ks.t;\n"
+    );
+
+    let whole = Range::new(Position::new(0, 0), Position::new(100, 0));
+    assert_eq!(
+        run_range_format(text, whole).await,
+        "SELECT id FROM ks.t;\n\n-- keep   me\nselect id from ks.t;\nSELECT id FROM ks.t;\n"
+    );
+
+    let comment = Range::new(Position::new(2, 0), Position::new(2, 4));
+    assert_eq!(run_range_format(text, comment).await, text);
+}
+
+#[tokio::test]
+async fn test_on_type_format() {
+    let text = "SELECT id FROM ks.t;\nselect   id    from ks.t;";
+    assert_eq!(
+        run_on_type_format(text, Position::new(1, 25), ";", CqllsConfig::default()).await,
+        "SELECT id FROM ks.t;\nselect id from ks.t;"
+    );
+
+    let text = "CREATE TABLE ks.t (\n    id uuid PRIMARY KEY,\n    display_name text,\n\n";
+    assert_eq!(
+        run_on_type_format(text, Position::new(3, 0), "\n", CqllsConfig::default()).await,
+        "CREATE TABLE ks.t (\n    id                  uuid PRIMARY KEY,\n    display_name        text,\n\n"
+    );
+
+    /* aligned like `format_text` aligns the whole statement */
+    let cfg = parse_config("fmt {\ntype_padding: 2\n}\n").unwrap();
+    let text = "create table ks.t (\nid uuid primary key, -- the id\n    display_name frozen<list<int>>,\n\n";
+    assert_eq!(
+        run_on_type_format(text, Position::new(3, 0), "\n", cfg).await,
+        "create table ks.t (\n    id            uuid primary key, -- the id\n    display_name  frozen<list<int>>,\n\n"
+    );
+
+    let text = "SELECT id FROM ks.t;\n\n";
+    assert_eq!(
+        run_on_type_format(text, Position::new(1, 0), "\n", CqllsConfig::default()).await,
+        text
+    );
+
+    /* the statement comes from the parser, not from how its first line starts */
+    for (text, line, expected) in [
+        (
+            "CREATE   TABLE ks.t (\n    id uuid PRIMARY KEY,\n    display_name text,\n\n",
+            3,
+            "CREATE   TABLE ks.t (\n    id                  uuid PRIMARY KEY,\n    display_name        text,\n\n",
+        ),
+        (
+            "USE ks; CREATE TABLE t (\nid uuid PRIMARY KEY,\n    display_name text,\n\n",
+            3,
+            "USE ks; CREATE TABLE t (\n    id                  uuid PRIMARY KEY,\n    display_name        text,\n\n",
+        ),
+        (
+            "CREATE TYPE IF NOT EXISTS\n    ks.address (\n    street text,\n    zip_code int,\n\n",
+            4,
+            "CREATE TYPE IF NOT EXISTS\n    ks.address (\n    street          text,\n    zip_code        int,\n\n",
+        ),
+    ] {
+        assert_eq!(
+            run_on_type_format(text, Position::new(line, 0), "\n", CqllsConfig::default()).await,
+            expected
+        );
+    }
+
+    for (text, line) in [
+        (
+            "CREATE OR REPLACE FUNCTION ks.f(a int)\n    RETURNS NULL ON NULL INPUT\n    RETURNS text\n    LANGUAGE java AS $$\n    create table x (\n        id uuid,\n        display_name text,\n\n",
+            7,
+        ),
+        (
+            "-- create table ks.t (\nid uuid,\n    display_name text,\n\n",
+            3,
+        ),
+    ] {
+        assert_eq!(
+            run_on_type_format(text, Position::new(line, 0), "\n", CqllsConfig::default()).await,
+            text
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_format_edits_are_minimal() {
+    let (edits, _) = run_format_edits("SELECT id FROM ks.t;\nselect   id from ks.t;\n").await;
+    assert_eq!(
+        edits,
+        vec![
+            TextEdit {
+                range: Range::new(Position::new(1, 0), Position::new(1, 0)),
+                new_text: "\n".to_string(),
+            },
+            TextEdit {
+                range: Range::new(Position::new(1, 6), Position::new(1, 8)),
+                new_text: "".to_string(),
+            },
+        ]
+    );
+
+    let (edits, _) = run_format_edits("SELECT id FROM ks.t;\n").await;
+    assert!(edits.is_empty());
+}
+
+#[tokio::test]
+async fn test_format_edits_reproduce_formatter_output() {
+    let provided = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/cql/provided");
+
+    for entry in std::fs::read_dir(provided).unwrap() {
+        let text = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+        let (edits, formatted) = run_format_edits(&text).await;
+
+        assert_eq!(apply_text_edits(&text, &edits), formatted);
+
+        let (edits, reformatted) = run_format_edits(&formatted).await;
+        assert_eq!(edits.is_empty(), formatted == reformatted);
+    }
+}
+
+#[tokio::test]
+async fn test_format_is_idempotent() {
+    let provided = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/cql/provided");
+
+    let mut inputs: Vec<(String, String)> = std::fs::read_dir(provided)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            (
+                path.display().to_string(),
+                std::fs::read_to_string(&path).unwrap(),
+            )
+        })
+        .collect();
+    /* the parser recovers from the body, calls after it keep their spacing */
+    inputs.push((
+        "call after a recovered function".to_string(),
+        "CREATE FUNCTION f(a int) CALLED ON NULL INPUT RETURNS int LANGUAGE java AS $$ return a; $$;\n\
+         /* c */\n\
+         select token(a) from t;\n"
+            .to_string(),
+    ));
+    inputs.push((
+        "calls after a conditional update".to_string(),
+        "UPDATE ks.t SET l = l + [1] WHERE id = 1 IF EXISTS;\n\
+         SELECT token(id), writetime(v), cast(v as text) FROM ks.t WHERE token(id) > token(:p);\n"
+            .to_string(),
+    ));
+    inputs.push((
+        "calls after a conditional batch".to_string(),
+        "BEGIN BATCH UPDATE t SET a = 1 WHERE id = 1 IF EXISTS; APPLY BATCH;\n\
+         select count(*), ttl(v), max(v) from t where id in (1, 2);\n"
+            .to_string(),
+    ));
+    inputs.push((
+        "calls after a function without a body".to_string(),
+        "CREATE FUNCTION f(a int) RETURNS int LANGUAGE java AS\n\
+         select token(a), toJson(b) from t;\n"
+            .to_string(),
+    ));
+
+    for (name, text) in inputs {
+        let (_, formatted) = run_format_edits(&text).await;
+        let (edits, reformatted) = run_format_edits(&formatted).await;
+
+        assert_eq!(formatted, reformatted, "{name}");
+        assert!(edits.is_empty(), "{name}");
+        for call in [
+            "token",
+            "writetime",
+            "cast",
+            "count",
+            "ttl",
+            "max",
+            "toJson",
+        ] {
+            assert!(
+                !formatted
+                    .to_lowercase()
+                    .contains(&format!("{} (", call.to_lowercase())),
+                "{name}"
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_format_keeps_literals_and_comments() {
+    let text = "select   'a  b',\"Col  X\"   from ks.t where v='it''s'; --  two  spaces\n\
+                /*  block\n      comment  */\n\
+                create function ks.f(a int) returns null on null input returns int \
+                language lua as $$  return   a  $$;\n";
+    let (_, formatted) = run_format_edits(text).await;
+
+    assert_eq!(
+        formatted,
+        "select 'a  b', \"Col  X\" from ks.t where v = 'it''s'; --  two  spaces\n\
+         /*  block\n      comment  */\n\
+         create function ks.f(a int)\n    \
+         returns null on null input\n    \
+         returns int\n    \
+         language lua as $$  return   a  $$;\n"
+    );
+}
+
+#[tokio::test]
+async fn test_format_leaves_unterminated_statement() {
+    let text = "select  id from ks.t;\nselect  'oops from ks.t;\n";
+    let (_, formatted) = run_format_edits(text).await;
+
+    assert_eq!(
+        formatted,
+        "select id from ks.t;\n\nselect  'oops from ks.t;\n"
+    );
+}
+
+#[test]
+fn test_doc_groups_break_only_when_too_wide() {
+    use cqlls::doc::*;
+
+    let list = group(concat(vec![
+        text("("),
+        nest(
+            4,
+            concat(vec![softline(), join(vec![text("a,"), text("b")], line())]),
+        ),
+        softline(),
+        text(")"),
+    ]));
+
+    assert_eq!(list.pretty(80), "(a, b)");
+    assert_eq!(list.pretty(4), "(\n    a,\n    b\n)");
+
+    let commented = group(concat(vec![
+        text("a -- c"),
+        break_parent(),
+        line(),
+        text("b"),
+    ]));
+    assert_eq!(commented.pretty(80), "a -- c\nb");
+}
+
+#[test]
+fn test_parse_fmt_case() {
+    let cfg = parse_config("fmt {\nkeyword_case: upper\ntype_case: \"lower\"\n}\n").unwrap();
+    assert_eq!(cfg.keyword_case, Case::Upper);
+    assert_eq!(cfg.type_case, Case::Lower);
+    assert_eq!(cfg.function_case, Case::Preserve);
+
+    let err = parse_config("fmt {\nfunction_case: camel\n}\n").unwrap_err();
+    assert!(err.msg.contains("upper/lower/preserve"));
+}
+
+#[tokio::test]
+async fn test_format_case() {
+    let cfg =
+        parse_config("fmt {\nkeyword_case: upper\ntype_case: lower\nfunction_case: lower\n}\n")
+            .unwrap();
+    let text = "create table ks.t (id UUID primary key, tags SET<TEXT>);\n\
+                select ToJson(tags), \"Quoted\" from ks.t where id = NOW() limit 1;\n";
+
+    assert_eq!(
+        run_format_text(text, cfg).await,
+        "CREATE TABLE ks.t (\n    \
+         id          uuid PRIMARY KEY,\n    \
+         tags        set<text>\n\
+         );\n\n\
+         SELECT tojson(tags), \"Quoted\" FROM ks.t WHERE id = now() LIMIT 1;\n"
+    );
+}
+
+#[tokio::test]
+async fn test_completion_case() {
+    let text = "\n";
+    let position = Position::new(0, 0);
+
+    let both = run_completions(text, position, Default::default()).await;
+    assert!(both.contains(&"SELECT".to_string()) && both.contains(&"select".to_string()));
+
+    let cfg = parse_config("fmt {\nkeyword_case: lower\n}\n").unwrap();
+    let lower = run_completions(text, position, cfg).await;
+    assert!(lower.contains(&"select".to_string()));
+    assert!(!lower.contains(&"SELECT".to_string()));
+    assert_eq!(lower.len() * 2, both.len());
+}
+
+#[tokio::test]
+async fn test_completion_fields_before_from() {
+    let cfg = parse_config("features {\ndiagnostics: false\n}\n").unwrap();
+    /* both `FROM` and more fields fit after `select a, b`, the fields win */
+    let text = "select a, b\n";
+    let items = run_completions(text, Position::new(0, 11), cfg).await;
+    assert!(
+        !items.iter().any(|i| i.eq_ignore_ascii_case("from")),
+        "{items:?}"
+    );
+
+    /* after a space it's `FROM` */
+    let cfg = parse_config("features {\ndiagnostics: false\n}\n").unwrap();
+    let items = run_completions("select a, b \n", Position::new(0, 12), cfg).await;
+    assert!(
+        items.iter().any(|i| i.eq_ignore_ascii_case("from")),
+        "{items:?}"
+    );
+}
+
+#[tokio::test]
+async fn test_format_max_width() {
+    let cfg = parse_config("fmt {\nmax_width: 40\n}\n").unwrap();
+    assert_eq!(cfg.max_width, 40);
+
+    let text = "select id from ks.t where id = 1;\n\
+                select id, name, email, created_at, last_login from ks.users where id = 1;\n\
+                select id from ks.users where name = 'bob' and age = 42 and city = 'Berlin';\n\
+                select id from ks.t where id in (1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12);\n";
+
+    assert_eq!(
+        run_format_text(text, cfg).await,
+        "select id from ks.t where id = 1;\n\n\
+         select\n    id,\n    name,\n    email,\n    created_at,\n    last_login\n\
+         from ks.users\n\
+         where id = 1;\n\n\
+         select id\n\
+         from ks.users\n\
+         where name = 'bob'\n    and age = 42\n    and city = 'Berlin';\n\n\
+         select id\n\
+         from ks.t\n\
+         where id in (\n    1,\n    2,\n    3,\n    4,\n    5,\n    6,\n    7,\n    8,\n    9,\n    10,\n    11,\n    12\n);\n"
+    );
+}
+
+#[tokio::test]
+async fn test_format_table_options() {
+    let cfg = parse_config("fmt {\nindent: 2\ntype_padding: 1\n}\n").unwrap();
+
+    let text = "alter table ks.t with comment = 'users';\n\
+                create keyspace ks with replication = {'class': 'SimpleStrategy', 'replication_factor': 1};\n\
+                create table ks.t (id uuid primary key) with compaction = {'class': 'LeveledCompactionStrategy', 'sstable_size_in_mb': 160} and comment = 'users' and gc_grace_seconds = 0;\n";
+
+    assert_eq!(
+        run_format_text(text, cfg).await,
+        "alter table ks.t with comment = 'users';\n\n\
+         create keyspace ks\n\
+         with replication = {\n  'class':              'SimpleStrategy',\n  'replication_factor': 1\n};\n\n\
+         create table ks.t (\n  id uuid primary key\n)\n\
+         with compaction = {\n  'class':              'LeveledCompactionStrategy',\n  'sstable_size_in_mb': 160\n}\n  \
+         and comment = 'users'\n  \
+         and gc_grace_seconds = 0;\n"
+    );
+}
+
+#[tokio::test]
+async fn test_format_dml_fixtures() {
+    let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/cql");
+
+    for name in ["batch_indent", "update_set", "where_and", "using_if"] {
+        let provided = std::fs::read_to_string(root.join(format!("provided/{name}.txt"))).unwrap();
+        let expected = std::fs::read_to_string(root.join(format!("expected/{name}.cql"))).unwrap();
+
+        assert_eq!(
+            run_format_text(&provided, CqllsConfig::default()).await,
+            expected,
+            "{name}"
+        );
+    }
+}
+
+#[test]
+fn test_parse_udf_formatters() {
+    let cfg = parse_config(
+        "fmt {\n    udf_formatters {\n        Java: \"google-java-format -\"\n        lua: \"stylua --indent-width 2 -\" # comment\n    }\n    indent: 2\n}\n",
+    )
+    .unwrap();
+
+    assert_eq!(cfg.udf_formatters["java"], "google-java-format -");
+    assert_eq!(cfg.udf_formatters["lua"], "stylua --indent-width 2 -");
+    assert_eq!(cfg.indent, 2);
+}
+
+#[tokio::test]
+async fn test_format_function_body() {
+    let text = "create function ks.f (a int) returns null on null input returns int language java as $$\n\
+                \t\t    if (a > 1) {\n\
+                \t\t        return a;\n\
+                \t\t    }\n\n\
+                \t\t    return 0;  \n\
+                $$;\n\
+                create function ks.g (a int) returns null on null input returns int language lua as '\n\
+                \x20 return ''x''..a';\n\
+                create function ks.h (a int) returns null on null input returns int language java as $$ return a; $$;\n";
+
+    assert_eq!(
+        run_format_text(text, CqllsConfig::default()).await,
+        "create function ks.f(a int)\n    returns null on null input\n    returns int\n    language java as $$\n\
+         \x20       if (a > 1) {\n            return a;\n        }\n\n        return 0;\n    $$;\n\n\
+         create function ks.g(a int)\n    returns null on null input\n    returns int\n    language lua as '\n\
+         \x20       return ''x''..a\n    ';\n\n\
+         create function ks.h(a int)\n    returns null on null input\n    returns int\n    language java as $$ return a; $$;\n"
+    );
+
+    let cfg = parse_config(
+        "fmt {\nudf_formatters {\nlua: \"sed 's/x/y y/'\"\njava: \"no-such-formatter\"\n}\n}\n",
+    )
+    .unwrap();
+    let formatted = run_format_text(text, cfg).await;
+
+    assert!(formatted.contains("    language lua as '\n        return ''y y''..a\n    ';\n"));
+    assert!(formatted.contains("language java as $$ return a; $$;\n"));
+}
+
+#[test]
+fn test_fmt_cli() {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let dir = std::env::temp_dir().join(format!("cqlls_fmt_cli_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(".cqlls"), "fmt {\nkeyword_case: upper\n}\n").unwrap();
+    std::fs::write(dir.join("a.cql"), "select id from ks.t;\n").unwrap();
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    std::fs::write(dir.join("sub/.cqlls"), "fmt {\nkeyword_case: lower\n}\n").unwrap();
+    std::fs::write(dir.join("sub/b.cql"), "SELECT id FROM ks.t;\n").unwrap();
+
+    let cqlls = |args: &[&str], input: &str| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_cqlls"))
+            .arg("fmt")
+            .args(args)
+            .current_dir(&dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        (
+            output.status.code(),
+            String::from_utf8(output.stdout).unwrap(),
+        )
+    };
+
+    let (code, out) = cqlls(&["--stdin"], "select  1 from t;\n");
+    assert_eq!((code, out.as_str()), (Some(0), "SELECT 1 FROM t;\n"));
+
+    let (code, out) = cqlls(&["--check", "a.cql"], "");
+    assert_eq!(code, Some(1));
+    assert!(out.contains("--- a.cql\n+++ a.cql\n"));
+    assert!(out.contains("-select id from ks.t;\n+SELECT id FROM ks.t;\n"));
+
+    /* the config next to the file wins over the one in the current directory */
+    assert_eq!(cqlls(&["--check", "sub/b.cql"], "").0, Some(1));
+
+    assert_eq!(cqlls(&[], "").0, Some(0));
+    assert_eq!(
+        std::fs::read_to_string(dir.join("a.cql")).unwrap(),
+        "SELECT id FROM ks.t;\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(dir.join("sub/b.cql")).unwrap(),
+        "select id from ks.t;\n"
+    );
+    assert_eq!(cqlls(&["--check"], "").0, Some(0));
+
+    /* the formatters of a project config don't run, the user is told once */
+    std::fs::write(
+        dir.join(".cqlls"),
+        "fmt {\nudf_formatters {\nlua: \"sed s/a/pwned/\"\n}\n}\n",
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_cqlls"))
+        .args(["fmt", "--stdin"])
+        .current_dir(&dir)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(
+        stderr
+            .matches("ignoring `udf_formatters`, start cqlls with `--trust-workspace`")
+            .count(),
+        1,
+        "{stderr}"
+    );
+    let body = "create function f() returns null on null input returns int language lua as 'a';\n";
+    assert!(!cqlls(&["--stdin"], body).1.contains("pwned"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_check_cli() {
+    use std::process::Command;
+
+    let dir = std::env::temp_dir().join(format!("cqlls_check_cli_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("schema/v1")).unwrap();
+    std::fs::write(dir.join(".cqlls"), "lint {\nselect-star: off\n}\n").unwrap();
+    std::fs::write(
+        dir.join("schema/v1/a.cql"),
+        "SELECT * FROM ks.t WHERE v = 1 ALLOW FILTERING;\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("schema/b.cql"), "SELECT id FROM ks.t;\n").unwrap();
+    std::fs::write(dir.join("bad.cql"), "SELEC x;\n").unwrap();
+
+    let cqlls = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_cqlls"))
+            .arg("check")
+            .args(args)
+            .current_dir(&dir)
+            .output()
+            .unwrap();
+        (
+            output.status.code(),
+            String::from_utf8(output.stdout).unwrap(),
+        )
+    };
+
+    let (code, out) = cqlls(&["schema/**/*.cql"]);
+    assert_eq!(code, Some(1));
+    assert_eq!(
+        out,
+        "schema/v1/a.cql:1:32: warning[allow-filtering]: `ALLOW FILTERING` scans every partition; query by partition key or add an index instead\n"
+    );
+
+    assert_eq!(cqlls(&["schema/b.cql"]), (Some(0), String::new()));
+
+    let (code, out) = cqlls(&["--format", "json", "bad.cql"]);
+    let json: serde_json::Value = serde_json::from_str(&out).unwrap();
+    assert_eq!(code, Some(2));
+    assert_eq!(json[0]["rule"], "syntax-error");
+    assert_eq!(json[0]["line"], 1);
+
+    let (code, out) = cqlls(&["-f", "sarif"]);
+    let sarif: serde_json::Value = serde_json::from_str(&out).unwrap();
+    let results = sarif["runs"][0]["results"].as_array().unwrap();
+    assert_eq!(code, Some(2));
+    assert_eq!(sarif["version"], "2.1.0");
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0]["level"], "error");
+    assert_eq!(
+        results[0]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"],
+        "bad.cql"
+    );
+
+    /* the config next to the file wins over the one in the current directory */
+    std::fs::create_dir_all(dir.join("strict")).unwrap();
+    std::fs::write(dir.join("strict/.cqlls"), "lint {\nselect-star: error\n}\n").unwrap();
+    std::fs::write(dir.join("strict/c.cql"), "SELECT * FROM ks.t;\n").unwrap();
+    let (code, out) = cqlls(&["strict/c.cql", "schema/v1/a.cql"]);
+    assert_eq!(code, Some(2));
+    assert!(
+        out.starts_with("strict/c.cql:1:8: error[select-star]:"),
+        "{out}"
+    );
+    assert!(!out.contains("schema/v1/a.cql:1:8:"), "{out}");
+
+    /* what can't be checked isn't a finding */
+    assert_eq!(cqlls(&["missing.cql"]), (Some(3), String::new()));
+    std::fs::write(dir.join("strict/.cqlls"), "lint {\nselect-star: loud\n}\n").unwrap();
+    assert_eq!(cqlls(&["strict/c.cql"]), (Some(3), String::new()));
+    assert_eq!(cqlls(&["schema/b.cql"]), (Some(0), String::new()));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_skips_hidden_and_symlinked_dirs() {
+    use std::process::Command;
+
+    let dir = std::env::temp_dir().join(format!("cqlls_cli_walk_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join(".hidden")).unwrap();
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    std::fs::write(dir.join("a.cql"), "SELEC x;\n").unwrap();
+    std::fs::write(dir.join(".hidden/h.cql"), "SELEC y;\n").unwrap();
+    std::fs::write(dir.join("sub/b.cql"), "SELECT id FROM ks.t;\n").unwrap();
+    std::os::unix::fs::symlink(&dir, dir.join("sub/loop")).unwrap();
+
+    let files = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_cqlls"))
+            .arg("check")
+            .args(args)
+            .current_dir(&dir)
+            .output()
+            .unwrap();
+        let out = String::from_utf8(output.stdout).unwrap();
+        let mut files: Vec<String> = out
+            .lines()
+            .filter_map(|l| l.split(':').next())
+            .map(str::to_string)
+            .collect();
+        files.dedup();
+        files
+    };
+
+    assert_eq!(files(&[]), ["a.cql"]);
+    assert_eq!(files(&["**/*.cql"]), ["a.cql"]);
+    assert_eq!(files(&[".hidden/*.cql"]), [".hidden/h.cql"]);
+    assert_eq!(files(&["**/.hidden/*.cql"]), [".hidden/h.cql"]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_check_offline() {
+    use std::process::Command;
+
+    /* counts who tried to connect, and hangs up on them right away */
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let attempts = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counter = attempts.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            drop(stream);
+        }
+    });
+    let connected = || attempts.load(std::sync::atomic::Ordering::SeqCst) > 0;
+
+    let dir = std::env::temp_dir().join(format!("cqlls_check_offline_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join(".cqlls"),
+        format!("db {{\n    known_nodes {{\n        \"{address}\"\n    }}\n}}\n"),
+    )
+    .unwrap();
+    std::fs::write(dir.join("a.cql"), "SELECT id FROM ks.t;\n").unwrap();
+
+    let check = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_cqlls"))
+            .arg("check")
+            .args(args)
+            .current_dir(&dir)
+            .output()
+            .unwrap()
+            .status
+            .code()
+    };
+
+    assert_eq!(check(&["a.cql"]), Some(0));
+    assert!(!connected());
+    assert_eq!(check(&["--schema", "a.cql"]), Some(0));
+    assert!(connected());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_cli_args() {
+    use clap::Parser;
+    use cqlls::cmd::{Cli, Command, ConfigCommand};
+
+    let cli =
+        Cli::try_parse_from(["cqlls", "lsp", "--tcp", "9257", "--log-level", "debug"]).unwrap();
+    let Some(Command::Lsp(args)) = &cli.command else {
+        panic!("expected `lsp`");
+    };
+    assert_eq!(args.tcp, "127.0.0.1:9257".parse().ok());
+    assert!(cli.log_level.is_some());
+
+    let cli = Cli::try_parse_from(["cqlls", "--config", "ci.cqlls", "config", "validate"]).unwrap();
+    assert!(matches!(
+        cli.command,
+        Some(Command::Config(ConfigCommand::Validate))
+    ));
+    assert_eq!(
+        cli.config_path(),
+        Some(std::path::PathBuf::from("ci.cqlls"))
+    );
+
+    assert!(Cli::try_parse_from(["cqlls"]).unwrap().command.is_none());
+    assert!(Cli::try_parse_from(["cqlls", "serve"]).is_err());
+    assert!(Cli::try_parse_from(["cqlls", "check", "--format", "xml"]).is_err());
+    assert!(Cli::try_parse_from(["cqlls", "lsp", "--stdio", "--tcp", "1"]).is_err());
+    assert!(Cli::try_parse_from(["cqlls", "lsp", "--tcp", "localhost"]).is_err());
+
+    /* no login, so nothing but this machine may connect */
+    let error = Cli::try_parse_from(["cqlls", "lsp", "--tcp", "0.0.0.0:9000"]).unwrap_err();
+    assert!(
+        error.to_string().contains("isn't a loopback address"),
+        "{error}"
+    );
+    let cli = Cli::try_parse_from(["cqlls", "lsp", "--tcp", "[::1]:9000"]).unwrap();
+    let Some(Command::Lsp(args)) = &cli.command else {
+        panic!("expected `lsp`");
+    };
+    assert_eq!(args.tcp, "[::1]:9000".parse().ok());
+}
+
+#[test]
+fn test_cli_compat() {
+    let dir = std::env::temp_dir().join(format!("cqlls_cli_compat_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    /* `-wdc` of older versions is `config init` */
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_cqlls"))
+        .arg("-wdc")
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("deprecated"));
+    let written = std::fs::read_to_string(dir.join(".cqlls")).unwrap();
+    assert!(parse_config(&written).is_ok());
+
+    /* the mask doesn't tell how long the password is */
+    std::fs::write(dir.join(".cqlls"), "db {\n    pswd: \"abc\"\n}\n").unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_cqlls"))
+        .args(["config", "show"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("pswd: \"********\""), "{stdout}");
+    assert!(!stdout.contains("abc"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_write_config_round_trip() {
+    let cfg = parse_config(
+        "db {\ntype: \"dynamo\"\nknown_nodes {\n\"10.0.0.1:9042\"\n}\n}\n\
+         fmt {\nindent: 2\nkeyword_case: upper\nudf_formatters {\nlua: \"stylua -\"\n}\n}\n\
+         features {\ndiagnostics: true\n}\n\
+         lint {\nselect-star: error\n}\n",
+    )
+    .unwrap();
+
+    let mut written = vec![];
+    cfg.write_config(&mut written).unwrap();
+    let reparsed = parse_config(&String::from_utf8(written).unwrap()).unwrap();
+
+    assert!(matches!(reparsed.db_type, DbType::Dynamo));
+    assert_eq!(reparsed.known_nodes, vec!["10.0.0.1:9042"]);
+    assert_eq!(reparsed.indent, 2);
+    assert_eq!(reparsed.keyword_case, Case::Upper);
+    assert_eq!(reparsed.udf_formatters["lua"], "stylua -");
+    assert!(reparsed.has_feature("diagnostics"));
+    assert!(!reparsed.has_feature("context_aware_completions"));
+    assert_eq!(reparsed.lint_level("select-star"), LintLevel::Error);
+}
+
+#[test]
+fn test_discover_config() {
+    use cqlls::workspace::{Workspace, discover_config};
+    use tower_lsp::lsp_types::Url;
+
+    let dir = std::env::temp_dir().join(format!("cqlls_discover_{}", std::process::id()));
+    let project = dir.join("project");
+    let nested = project.join("schema").join("users");
+    std::fs::create_dir_all(&nested).unwrap();
+
+    let user_config = dir.join("user_config");
+    std::fs::write(&user_config, "fmt {\nindent: 2\n}\n").unwrap();
+
+    /* nothing at or above the folder → user level config */
+    assert_eq!(
+        discover_config(&nested, Some(&user_config)),
+        Some(user_config.clone())
+    );
+    assert_eq!(discover_config(&nested, None), None);
+
+    /* the nearest `.cqlls` in a parent wins */
+    std::fs::write(project.join(".cqlls"), "fmt {\nindent: 8\n}\n").unwrap();
+    assert_eq!(
+        discover_config(&nested, Some(&user_config)),
+        Some(project.join(".cqlls"))
+    );
+
+    std::fs::write(nested.join(".cqlls"), "fmt {\nindent: 3\n}\n").unwrap();
+
+    let mut workspace = Workspace::default();
+    workspace.add_folder(project.clone());
+    workspace.add_folder(nested.clone());
+    workspace.add_folder(project.clone());
+    assert_eq!(workspace.folders.len(), 2);
+
+    /* a document belongs to the innermost folder */
+    let inner = Url::from_file_path(nested.join("table.cql")).unwrap();
+    let outer = Url::from_file_path(project.join("keyspace.cql")).unwrap();
+    let elsewhere = Url::from_file_path(dir.join("other.cql")).unwrap();
+    assert_eq!(workspace.folder_for(&inner).unwrap().config.indent, 3);
+    assert_eq!(workspace.folder_for(&outer).unwrap().config.indent, 8);
+    assert!(workspace.folder_for(&elsewhere).is_none());
+
+    workspace.remove_folder(&nested);
+    assert_eq!(workspace.folder_for(&inner).unwrap().config.indent, 8);
+
+    /* `--config` turns discovery off */
+    let mut explicit = Workspace {
+        discover: false,
+        ..Default::default()
+    };
+    explicit.add_folder(project.clone());
+    assert!(explicit.folders.is_empty());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn lsp_write(stream: &mut impl std::io::Write, body: &str) {
+    write!(stream, "Content-Length: {}\r\n\r\n{body}", body.len()).unwrap();
+}
+
+fn lsp_read(reader: &mut impl std::io::BufRead) -> serde_json::Value {
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).unwrap();
+        if header == "\r\n" {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length: ") {
+            length = value.trim().parse().unwrap();
+        }
+    }
+
+    let mut message = vec![0; length];
+    reader.read_exact(&mut message).unwrap();
+    serde_json::from_slice(&message).unwrap()
+}
+
+/* `initialize` over separate streams, like the ones of a child process */
+fn lsp_initialize_with(
+    stdin: &mut impl std::io::Write,
+    stdout: &mut impl std::io::BufRead,
+) -> serde_json::Value {
+    lsp_write(
+        stdin,
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"capabilities":{}}}"#,
+    );
+
+    loop {
+        let message = lsp_read(stdout);
+        if message["id"] == 1 {
+            return message;
+        }
+    }
+}
+
+/* sends an `initialize` request and waits for its response */
+fn lsp_initialize(stream: &mut (impl std::io::Read + std::io::Write)) -> serde_json::Value {
+    lsp_write(
+        stream,
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"capabilities":{}}}"#,
+    );
+
+    let mut reader = std::io::BufReader::new(stream);
+    loop {
+        let message = lsp_read(&mut reader);
+        if message["id"] == 1 {
+            return message;
+        }
+    }
+}
+
+/* a spawned `cqlls`, killed and waited for however the test ends */
+struct Server(std::process::Child);
+
+impl std::ops::Deref for Server {
+    type Target = std::process::Child;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for Server {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn connect_with_retry<T>(connect: impl Fn() -> std::io::Result<T>) -> T {
+    for _ in 0..100 {
+        if let Ok(stream) = connect() {
+            return stream;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    panic!("server didn't start");
+}
+
+#[test]
+fn test_lsp_tcp_sequential_clients() {
+    use std::net::{TcpListener, TcpStream};
+
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let _server = Server(
+        std::process::Command::new(env!("CARGO_BIN_EXE_cqlls"))
+            .args(["lsp", "--tcp", &format!("127.0.0.1:{port}")])
+            .current_dir(std::env::temp_dir())
+            .spawn()
+            .unwrap(),
+    );
+
+    for _ in 0..2 {
+        let mut stream = connect_with_retry(|| TcpStream::connect(("127.0.0.1", port)));
+        let response = lsp_initialize(&mut stream);
+        assert!(response["result"]["capabilities"].is_object());
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_lsp_unix_socket_sequential_clients() {
+    use std::os::unix::net::UnixStream;
+
+    let path = std::env::temp_dir().join(format!("cqlls_{}.sock", std::process::id()));
+    let server = Server(
+        std::process::Command::new(env!("CARGO_BIN_EXE_cqlls"))
+            .args(["lsp", "--socket", path.to_str().unwrap()])
+            .current_dir(std::env::temp_dir())
+            .spawn()
+            .unwrap(),
+    );
+
+    for _ in 0..2 {
+        let mut stream = connect_with_retry(|| UnixStream::connect(&path));
+        let response = lsp_initialize(&mut stream);
+        assert!(response["result"]["capabilities"].is_object());
+    }
+
+    drop(server);
+    let _ = std::fs::remove_file(&path);
+
+    /* a file that isn't a socket stays where it is */
+    let file = std::env::temp_dir().join(format!("cqlls_{}.not_a_socket", std::process::id()));
+    std::fs::write(&file, "keep me").unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_cqlls"))
+        .args(["lsp", "--socket", file.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("isn't a socket"));
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "keep me");
+    std::fs::remove_file(&file).unwrap();
+}
+
+#[test]
+fn test_config_errors() {
+    use cqlls::lsp::Backend;
+    use cqlls::workspace::{Workspace, read_config};
+    use tower_lsp::lsp_types::Url;
+
+    let text = "db {\n    user: \"cassandra\"\n    usr: \"typo\"\n}\n";
+    let uri = Url::parse("file:///project/.cqlls").unwrap();
+    let diagnostics = Backend::config_diagnostics(&uri, text);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        diagnostics[0].range,
+        Range::new(Position::new(2, 0), Position::new(2, 15))
+    );
+    assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::ERROR));
+    assert_eq!(diagnostics[0].message, "unknown db key `usr`");
+    assert!(Backend::config_diagnostics(&uri, "db {\n}\n").is_empty());
+
+    let toml = Url::parse("file:///project/.cqlls.toml").unwrap();
+    let diagnostics = Backend::config_diagnostics(&toml, "[db]\nusr = \"typo\"\n");
+    assert_eq!(diagnostics[0].range.start, Position::new(1, 0));
+    assert_eq!(diagnostics[0].message, "unknown db key `usr`");
+
+    let dir = std::env::temp_dir().join(format!("cqlls_config_errors_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(".cqlls");
+    std::fs::write(&path, text).unwrap();
+
+    let error = format!("{}:3: unknown db key `usr`", path.display());
+    assert_eq!(read_config(&path).unwrap_err(), error);
+
+    /* the folder falls back to the defaults and remembers why */
+    let mut workspace = Workspace::default();
+    let folder = workspace.add_folder(dir.clone()).unwrap();
+    assert_eq!(folder.error.as_deref(), Some(error.as_str()));
+    assert_eq!(folder.config.user, CqllsConfig::default().user);
+
+    /* the CLI warns */
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_cqlls"))
+        .args(["config", "show"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&error), "{stderr}");
+
+    /* the editor is told with `window/showMessage` */
+    let mut server = Server(
+        std::process::Command::new(env!("CARGO_BIN_EXE_cqlls"))
+            .args(["--config", path.to_str().unwrap()])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    let mut stdin = server.stdin.take().unwrap();
+    let mut stdout = std::io::BufReader::new(server.stdout.take().unwrap());
+
+    lsp_write(
+        &mut stdin,
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"capabilities":{}}}"#,
+    );
+    while lsp_read(&mut stdout)["id"] != 1 {}
+    lsp_write(
+        &mut stdin,
+        r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+    );
+
+    let message = loop {
+        let message = lsp_read(&mut stdout);
+        if message["method"] == "window/showMessage" {
+            break message;
+        }
+    };
+    assert_eq!(message["params"]["type"], 1);
+    assert!(
+        message["params"]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with(&error)
+    );
+
+    drop(server);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_untrusted_workspace_commands() {
+    use cqlls::workspace::{Trust, Workspace};
+
+    let dir = std::env::temp_dir().join(format!("cqlls_untrusted_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(".cqlls");
+    std::fs::write(
+        &path,
+        "db {\npswd_command: \"touch pwned\"\n}\ndb \"prod\" {\npswd_command: \"touch pwned\"\n}\n\
+         fmt {\nudf_formatters {\nlua: \"touch pwned\"\n}\n}\n",
+    )
+    .unwrap();
+
+    /* a config that came with the project runs nothing */
+    let mut workspace = Workspace::default();
+    let folder = workspace.add_folder(dir.clone()).unwrap();
+    assert_eq!(folder.config.pswd_command, "");
+    assert_eq!(folder.config.profiles["prod"].pswd_command, "");
+    let ignored = folder.ignored.clone().unwrap();
+    assert!(folder.config.udf_formatters.is_empty());
+    assert!(
+        ignored.contains("`pswd_command` and `udf_formatters`"),
+        "{ignored}"
+    );
+    assert!(workspace.config_errors().contains(&ignored));
+
+    /* reading secrets doesn't let it run anything */
+    let mut secrets = Workspace {
+        trust: Trust::Secrets,
+        ..Default::default()
+    };
+    let folder = secrets.add_folder(dir.clone()).unwrap();
+    assert_eq!(folder.config.pswd_command, "");
+    assert!(folder.config.udf_formatters.is_empty());
+    let ignored = folder.ignored.clone().unwrap();
+    assert!(ignored.contains("`--trust-workspace`"), "{ignored}");
+
+    /* unless the user said so */
+    let mut trusted = Workspace {
+        trust: Trust::Everything,
+        ..Default::default()
+    };
+    let folder = trusted.add_folder(dir.clone()).unwrap();
+    assert_eq!(folder.config.pswd_command, "touch pwned");
+    assert_eq!(folder.config.udf_formatters["lua"], "touch pwned");
+    assert!(folder.ignored.is_none());
+
+    /* `--config` is the user's own */
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_cqlls"))
+        .args(["config", "show"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&output.stderr).contains("`--trust-workspace`"));
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_cqlls"))
+        .args(["--config", path.to_str().unwrap(), "config", "show"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&output.stderr).is_empty());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("touch pwned"));
+
+    assert!(!dir.join("pwned").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_untrusted_workspace_secrets() {
+    use cqlls::workspace::{Trust, Workspace};
+
+    let dir = std::env::temp_dir().join(format!("cqlls_secrets_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join(".cqlls"),
+        "db {\npswd_env: \"HOME\"\n}\ndb \"prod\" {\npswd_file: \"/etc/hostname\"\n}\n",
+    )
+    .unwrap();
+
+    /* a file is dropped, in every profile, the variable a committed config names isn't */
+    let mut workspace = Workspace::default();
+    let folder = workspace.add_folder(dir.clone()).unwrap();
+    assert_eq!(folder.config.pswd_env, "HOME");
+    assert_eq!(folder.config.profiles["prod"].pswd_file, "");
+    let ignored = folder.ignored.clone().unwrap();
+    assert!(ignored.contains("ignoring `pswd_file`,"), "{ignored}");
+    assert!(ignored.contains("`--trust-secrets`"), "{ignored}");
+
+    /* `--trust-secrets` is enough for them */
+    let mut secrets = Workspace {
+        trust: Trust::Secrets,
+        ..Default::default()
+    };
+    let folder = secrets.add_folder(dir.clone()).unwrap();
+    assert_eq!(folder.config.pswd_env, "HOME");
+    assert_eq!(folder.config.profiles["prod"].pswd_file, "/etc/hostname");
+    assert!(folder.ignored.is_none());
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_cqlls"))
+        .args(["--trust-secrets", "config", "show"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&output.stderr).is_empty());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("pswd_env: \"HOME\""));
+
+    /* the environment isn't read, only the values that would read it are left out */
+    for (file, src, key) in [
+        (
+            ".cqlls",
+            "db {\nknown_nodes {\n\"${HOME}.example:9042\"\n\"10.0.0.1:9042\"\n}\n}\nfmt {\nindent: 2\n}\n",
+            "known_nodes",
+        ),
+        (
+            ".cqlls",
+            "db \"prod\" {\nuser: \"${HOME}\"\nknown_nodes {\n\"10.0.0.1:9042\"\n}\n}\nfmt {\nindent: 2\n}\n",
+            "user",
+        ),
+        (
+            ".cqlls.toml",
+            "[db]\npswd = \"${HOME}\"\nknown_nodes = [\"10.0.0.1:9042\"]\n[fmt]\nindent = 2\n",
+            "pswd",
+        ),
+        (
+            ".cqlls.toml",
+            "[profiles.prod]\nknown_nodes = [\"${HOME}:9042\", \"10.0.0.1:9042\"]\n[fmt]\nindent = 2\n",
+            "known_nodes",
+        ),
+    ] {
+        let _ = std::fs::remove_file(dir.join(".cqlls"));
+        std::fs::write(dir.join(file), src).unwrap();
+
+        let mut workspace = Workspace::default();
+        let folder = workspace.add_folder(dir.clone()).unwrap();
+        assert!(folder.error.is_none(), "{:?}", folder.error);
+        let ignored = folder.ignored.clone().unwrap();
+        assert!(ignored.contains(&format!("ignoring `{key}`,")), "{ignored}");
+        assert!(ignored.contains("`--trust-secrets`"), "{ignored}");
+        assert_eq!(folder.config.indent, 2);
+        assert_eq!(folder.config.pswd, "cassandra");
+        let nodes = match folder.config.profiles.get("prod") {
+            Some(prod) => {
+                assert_eq!(prod.user, "cassandra");
+                &prod.known_nodes
+            }
+            None => &folder.config.known_nodes,
+        };
+        assert_eq!(nodes, &["10.0.0.1:9042"]);
+        assert!(!format!("{:?}", folder.config).contains(&std::env::var("HOME").unwrap()));
+
+        let mut secrets = Workspace {
+            trust: Trust::Secrets,
+            ..Default::default()
+        };
+        let folder = secrets.add_folder(dir.clone()).unwrap();
+        assert!(folder.error.is_none() && folder.ignored.is_none());
+        let _ = std::fs::remove_file(dir.join(".cqlls.toml"));
+    }
+
+    /* `$${` is no variable */
+    std::fs::write(dir.join(".cqlls"), "db {\nuser: \"$${HOME}\"\n}\n").unwrap();
+    let mut workspace = Workspace::default();
+    assert_eq!(
+        workspace.add_folder(dir.clone()).unwrap().config.user,
+        "${HOME}"
+    );
+
+    /* the project's own cqlshrc is read, the user's doesn't go to the nodes a project picked */
+    let rc = "[connection]\nhostname = 10.0.0.5\n[authentication]\nusername = rc_user\npassword = rc_pass\n";
+    std::fs::create_dir_all(dir.join("scylla")).unwrap();
+    std::fs::write(dir.join("scylla").join("cqlshrc"), rc).unwrap();
+    let outside = dir.with_extension("outside");
+    std::fs::create_dir_all(&outside).unwrap();
+    std::fs::write(outside.join("cqlshrc"), rc).unwrap();
+    let sibling = format!(
+        "../{}/cqlshrc",
+        outside.file_name().unwrap().to_str().unwrap()
+    );
+    let absolute = outside.join("cqlshrc").display().to_string();
+    for (path, inside) in [
+        ("scylla/cqlshrc", true),
+        ("./scylla/../scylla/cqlshrc", true),
+        (sibling.as_str(), false),
+        (absolute.as_str(), false),
+        ("~/.cassandra/cqlshrc", false),
+    ] {
+        for (file, src) in [
+            (
+                ".cqlls",
+                format!(
+                    "db {{\nfrom_cqlshrc: \"{path}\"\nknown_nodes {{\n\"evil.example:9042\"\n}}\n}}\n\
+                     db \"prod\" {{\nfrom_cqlshrc: \"{path}\"\n}}\n"
+                ),
+            ),
+            (
+                ".cqlls.toml",
+                format!(
+                    "[db]\nfrom_cqlshrc = \"{path}\"\nknown_nodes = [\"evil.example:9042\"]\n\
+                     [profiles.prod]\nfrom_cqlshrc = \"{path}\"\n"
+                ),
+            ),
+        ] {
+            let _ = std::fs::remove_file(dir.join(".cqlls"));
+            std::fs::write(dir.join(file), src).unwrap();
+
+            let mut workspace = Workspace::default();
+            let folder = workspace.add_folder(dir.clone()).unwrap();
+            assert!(folder.error.is_none(), "{path}: {:?}", folder.error);
+            match inside {
+                true => {
+                    assert_eq!(folder.config.user, "rc_user", "{path}");
+                    assert_eq!(folder.config.profiles["prod"].pswd, "rc_pass");
+                    assert_eq!(folder.config.from_cqlshrc, path);
+                    assert!(folder.ignored.is_none(), "{:?}", folder.ignored);
+                }
+                false => {
+                    assert_eq!(folder.config.user, "cassandra", "{path}");
+                    assert_eq!(folder.config.pswd, "cassandra");
+                    assert_eq!(folder.config.from_cqlshrc, "");
+                    assert_eq!(folder.config.profiles["prod"].user, "cassandra");
+                    assert_eq!(folder.config.profiles["prod"].from_cqlshrc, "");
+                    let ignored = folder.ignored.clone().unwrap();
+                    assert!(ignored.contains("ignoring `from_cqlshrc`,"), "{ignored}");
+                    assert!(ignored.contains("`--trust-secrets`"), "{ignored}");
+                }
+            }
+
+            /* reading one outside doesn't need more than `--trust-secrets` */
+            if !path.starts_with('~') {
+                let mut secrets = Workspace {
+                    trust: Trust::Secrets,
+                    ..Default::default()
+                };
+                let folder = secrets.add_folder(dir.clone()).unwrap();
+                assert_eq!(folder.config.user, "rc_user", "{path}");
+                assert_eq!(folder.config.profiles["prod"].pswd, "rc_pass");
+                assert_eq!(folder.config.from_cqlshrc, path);
+                assert!(folder.ignored.is_none());
+            }
+            let _ = std::fs::remove_file(dir.join(".cqlls.toml"));
+        }
+    }
+    std::fs::remove_dir_all(&outside).unwrap();
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_untrusted_editor_settings() {
+    use cqlls::workspace::{Trust, Workspace};
+
+    let settings = serde_json::json!({
+        "db": { "user": "from_settings", "pswd_command": "touch pwned", "pswd_file": "/etc/hostname" },
+        "db \"prod\"": { "from_cqlshrc": "~/.cassandra/cqlshrc" },
+        "fmt": { "indent": 2, "udf_formatters": { "lua": "touch pwned" } }
+    });
+    let mine = CqllsConfig {
+        pswd_command: "pass show cassandra".into(),
+        ..Default::default()
+    };
+
+    /* folder settings and the `initializationOptions` of a client that connected don't get what the user's own config does */
+    let mut workspace = Workspace {
+        remote: true,
+        initialization_options: Some(settings.clone()),
+        ..Workspace::new(mine.clone())
+    };
+    workspace = workspace.reloaded();
+    assert_eq!(workspace.config.user, "from_settings");
+    assert_eq!(workspace.config.indent, 2);
+    assert_eq!(workspace.config.pswd_command, "pass show cassandra");
+    assert_eq!(workspace.config.pswd_file, "");
+    assert!(workspace.config.udf_formatters.is_empty());
+    assert_eq!(workspace.config.profiles["prod"].from_cqlshrc, "");
+    assert_eq!(workspace.config.profiles["prod"].pswd, "cassandra");
+    let ignored = workspace.settings_ignored.clone().unwrap();
+    assert!(
+        ignored.contains("`pswd_command`, `pswd_file`, `from_cqlshrc` and `udf_formatters`"),
+        "{ignored}"
+    );
+    assert!(workspace.config_errors().contains(&ignored));
+
+    workspace.settings =
+        Some(serde_json::json!({ "db": { "known_nodes": ["${HOME}:9042"], "user": "u" } }));
+    workspace = workspace.reloaded();
+    assert!(workspace.settings_error.is_none());
+    let ignored = workspace.settings_ignored.clone().unwrap();
+    assert!(ignored.contains("`known_nodes`"), "{ignored}");
+    assert_eq!(
+        workspace.config.known_nodes,
+        CqllsConfig::default().known_nodes
+    );
+    assert_eq!(workspace.config.user, "u");
+
+    /* the profile's cqlshrc isn't there to be read */
+    let mut settings = settings;
+    settings.as_object_mut().unwrap().remove("db \"prod\"");
+
+    /* over stdio `initializationOptions` are the user's own editor config, folder settings still aren't */
+    let mut stdio = Workspace {
+        initialization_options: Some(settings.clone()),
+        settings: Some(serde_json::json!({ "db": { "pswd_file": "/etc/hostname" } })),
+        ..Workspace::new(mine.clone())
+    }
+    .reloaded();
+    assert_eq!(stdio.config.pswd_command, "touch pwned");
+    assert_eq!(stdio.config.pswd_file, "/etc/hostname");
+    assert_eq!(stdio.config.udf_formatters["lua"], "touch pwned");
+    let ignored = stdio.settings_ignored.take().unwrap();
+    assert!(ignored.contains("`pswd_file`"), "{ignored}");
+    assert!(!ignored.contains("`pswd_command`"), "{ignored}");
+
+    let mut trusted = Workspace {
+        trust: Trust::Everything,
+        initialization_options: Some(settings),
+        ..Workspace::new(mine)
+    }
+    .reloaded();
+    assert_eq!(trusted.config.pswd_command, "touch pwned");
+    assert!(trusted.settings_ignored.take().is_none());
+}
+
+#[test]
+fn test_workspace_reload() {
+    use cqlls::workspace::Workspace;
+
+    let dir = std::env::temp_dir().join(format!("cqlls_reload_{}", std::process::id()));
+    let folder = dir.join("folder");
+    std::fs::create_dir_all(&folder).unwrap();
+    let started_with = dir.join("started_with");
+    std::fs::write(&started_with, "fmt {\nindent: 2\n}\n").unwrap();
+
+    let mut workspace = Workspace {
+        config_path: Some(started_with.clone()),
+        ..Default::default()
+    };
+    workspace.add_folder(folder.clone());
+    assert_eq!(workspace.folders[0].config_path, None);
+
+    std::fs::write(&started_with, "fmt {\nindent: 6\n}\n").unwrap();
+    std::fs::write(folder.join(".cqlls"), "fmt {\nindent: 3\n}\n").unwrap();
+
+    /* a new `.cqlls` is picked up, the folders stay */
+    let reloaded = workspace.reloaded();
+    assert_eq!(reloaded.config.indent, 6);
+    assert_eq!(reloaded.folders.len(), 1);
+    assert_eq!(reloaded.folders[0].config_path, Some(folder.join(".cqlls")));
+    assert_eq!(reloaded.folders[0].config.indent, 3);
+
+    std::fs::write(folder.join(".cqlls"), "fmt {\nindnet: 3\n}\n").unwrap();
+    let broken = reloaded.reloaded();
+    assert_eq!(
+        broken.folders[0].config.indent,
+        CqllsConfig::default().indent
+    );
+    assert_eq!(broken.config_errors().len(), 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_lsp_config_reload() {
+    use tower_lsp::lsp_types::Url;
+
+    let dir = std::env::temp_dir().join(format!("cqlls_lsp_reload_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = dir.join(".cqlls");
+    std::fs::write(&config, "fmt {\nindent: 2\n}\n").unwrap();
+    let root = Url::from_file_path(&dir).unwrap();
+    let document = Url::from_file_path(dir.join("users.cql")).unwrap();
+
+    let mut server = Server(
+        std::process::Command::new(env!("CARGO_BIN_EXE_cqlls"))
+            .current_dir(&dir)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    let mut stdin = server.stdin.take().unwrap();
+    let mut stdout = std::io::BufReader::new(server.stdout.take().unwrap());
+
+    lsp_write(
+        &mut stdin,
+        &serde_json::json!({
+            "jsonrpc": "2.0", "id": 1, "method": "initialize",
+            "params": {
+                "rootUri": root,
+                "capabilities": {
+                    "workspace": { "didChangeWatchedFiles": { "dynamicRegistration": true } }
+                }
+            }
+        })
+        .to_string(),
+    );
+    while lsp_read(&mut stdout)["id"] != 1 {}
+
+    let mut wait_for = |method: &str| loop {
+        let message = lsp_read(&mut stdout);
+        if message["method"] == method {
+            break message;
+        }
+    };
+
+    lsp_write(
+        &mut stdin,
+        r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+    );
+
+    /* `initialized` waits for the editor to accept the file watcher */
+    let registration = wait_for("client/registerCapability");
+    let watchers = &registration["params"]["registrations"][0]["registerOptions"]["watchers"];
+    assert_eq!(watchers[0]["globPattern"], "**/.cqlls");
+    lsp_write(
+        &mut stdin,
+        &serde_json::json!({ "jsonrpc": "2.0", "id": registration["id"], "result": null })
+            .to_string(),
+    );
+
+    lsp_write(
+        &mut stdin,
+        &serde_json::json!({
+            "jsonrpc": "2.0", "method": "textDocument/didOpen",
+            "params": { "textDocument": {
+                "uri": document, "languageId": "cql", "version": 1,
+                "text": "SELECT * FROM users;\n"
+            } }
+        })
+        .to_string(),
+    );
+    assert_eq!(
+        wait_for("textDocument/publishDiagnostics")["params"]["uri"],
+        document.as_str()
+    );
+
+    /* a broken config is reported and the open documents get new diagnostics */
+    std::fs::write(&config, "fmt {\nindent: two\n}\n").unwrap();
+    lsp_write(
+        &mut stdin,
+        &serde_json::json!({
+            "jsonrpc": "2.0", "method": "workspace/didChangeWatchedFiles",
+            "params": { "changes": [{ "uri": Url::from_file_path(&config).unwrap(), "type": 2 }] }
+        })
+        .to_string(),
+    );
+
+    let message = wait_for("window/showMessage");
+    assert!(
+        message["params"]["message"]
+            .as_str()
+            .unwrap()
+            .contains(".cqlls:2: `indent` must be u8")
+    );
+    assert_eq!(
+        wait_for("textDocument/publishDiagnostics")["params"]["uri"],
+        document.as_str()
+    );
+
+    drop(server);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_apply_settings() {
+    use serde_json::json;
+
+    let base = parse_config("fmt {\nindent: 3\nkeyword_case: upper\n}\n").unwrap();
+    let cfg = apply_settings(
+        base.clone(),
+        &json!({
+            "db": { "user": "app", "known_nodes": ["10.0.0.1:9042", "10.0.0.2:9042"] },
+            "fmt": { "indent": 5, "udf_formatters": { "lua": "stylua -" } },
+            "lint": { "select-star": "error" }
+        }),
+    )
+    .unwrap();
+
+    /* what the editor didn't set comes from the file, then the defaults */
+    assert_eq!(cfg.indent, 5);
+    assert_eq!(cfg.keyword_case, Case::Upper);
+    assert_eq!(cfg.type_padding, CqllsConfig::default().type_padding);
+    assert_eq!(cfg.user, "app");
+    assert_eq!(cfg.known_nodes, vec!["10.0.0.1:9042", "10.0.0.2:9042"]);
+    assert_eq!(cfg.udf_formatters["lua"], "stylua -");
+    assert_eq!(cfg.lint_level("select-star"), LintLevel::Error);
+
+    /* the rules of `parse_config`, reported by setting */
+    let error = |settings| apply_settings(base.clone(), &settings).unwrap_err();
+    assert_eq!(
+        error(json!({ "fmt": { "indent": 300 } })),
+        "`fmt.indent`: `indent` must be u8, got `300`"
+    );
+    assert_eq!(
+        error(json!({ "db": { "usr": "app" } })),
+        "`db.usr`: unknown db key `usr`"
+    );
+    assert_eq!(
+        error(json!({ "fmt": { "keyword_case": "title" } })),
+        "`fmt.keyword_case`: `keyword_case` must be upper/lower/preserve, got `\"title\"`"
+    );
+    assert_eq!(
+        error(json!({ "db": { "user": null } })),
+        "`db.user`: unsupported value `null`"
+    );
+    assert_eq!(
+        error(json!({ "fmt": 2 })),
+        "`fmt` must be an object, got `2`"
+    );
+}
+
+#[test]
+fn test_editor_settings_precedence() {
+    use cqlls::workspace::Workspace;
+    use serde_json::json;
+
+    let dir = std::env::temp_dir().join(format!("cqlls_settings_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(".cqlls"), "fmt {\nindent: 3\nmax_width: 80\n}\n").unwrap();
+
+    let mut workspace = Workspace {
+        initialization_options: Some(json!({ "fmt": { "indent": 5, "type_padding": 2 } })),
+        settings: Some(json!({ "fmt": { "indent": 7 } })),
+        ..Default::default()
+    };
+    workspace.add_folder(dir.clone());
+
+    /* `workspace/configuration` > `initializationOptions` > `.cqlls` > defaults */
+    let config = &workspace.folders[0].config;
+    assert_eq!(config.indent, 7);
+    assert_eq!(config.type_padding, 2);
+    assert_eq!(config.max_width, 80);
+    assert_eq!(config.keyword_case, CqllsConfig::default().keyword_case);
+
+    /* settings that don't validate leave the file as it is */
+    workspace.settings = Some(json!({ "fmt": { "indent": "wide" } }));
+    let reloaded = workspace.reloaded();
+    assert_eq!(reloaded.folders[0].config.indent, 5);
+    assert_eq!(
+        reloaded.settings_error.as_deref(),
+        Some("editor settings: `fmt.indent`: `indent` must be u8, got `\"wide\"`")
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_lsp_pulls_settings() {
+    let mut server = Server(
+        std::process::Command::new(env!("CARGO_BIN_EXE_cqlls"))
+            .current_dir(std::env::temp_dir())
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    let mut stdin = server.stdin.take().unwrap();
+    let mut stdout = std::io::BufReader::new(server.stdout.take().unwrap());
+
+    lsp_write(
+        &mut stdin,
+        &serde_json::json!({
+            "jsonrpc": "2.0", "id": 1, "method": "initialize",
+            "params": {
+                "initializationOptions": { "fmt": { "indent": 2 } },
+                "capabilities": { "workspace": { "configuration": true } }
+            }
+        })
+        .to_string(),
+    );
+    while lsp_read(&mut stdout)["id"] != 1 {}
+    lsp_write(
+        &mut stdin,
+        r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+    );
+
+    let request = loop {
+        let message = lsp_read(&mut stdout);
+        if message["method"] == "workspace/configuration" {
+            break message;
+        }
+    };
+    assert_eq!(request["params"]["items"][0]["section"], "cqlls");
+    lsp_write(
+        &mut stdin,
+        &serde_json::json!({
+            "jsonrpc": "2.0", "id": request["id"],
+            "result": [{ "fmt": { "max_width": -1 } }]
+        })
+        .to_string(),
+    );
+
+    let message = loop {
+        let message = lsp_read(&mut stdout);
+        if message["method"] == "window/showMessage" {
+            break message;
+        }
+    };
+    assert!(
+        message["params"]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("editor settings: `fmt.max_width`: `max_width` must be u16")
+    );
+
+    drop(server);
+}
+
+#[tokio::test]
+async fn test_password_sources() {
+    use cqlls::cqlsh::password;
+
+    /* SAFETY: the variables are only used by this test */
+    unsafe {
+        std::env::set_var("CQLLS_TEST_USER", "app");
+        std::env::set_var("CQLLS_TEST_PASSWORD", "from env");
+    }
+
+    let cfg = parse_config(
+        "db {\nuser: \"${CQLLS_TEST_USER}_ro\"\npswd: \"${CQLLS_TEST_PASSWORD}\"\n}\n",
+    )
+    .unwrap();
+    assert_eq!(cfg.user, "app_ro");
+    assert_eq!(password(&cfg).await.unwrap(), "from env");
+
+    let error = parse_config("db {\nuser: \"${CQLLS_TEST_UNSET}\"\n}\n").unwrap_err();
+    assert_eq!(
+        error.msg,
+        "environment variable `CQLLS_TEST_UNSET` isn't set"
+    );
+
+    let cfg =
+        parse_config("db {\npswd: \"plain\"\npswd_env: \"CQLLS_TEST_PASSWORD\"\n}\n").unwrap();
+    assert_eq!(password(&cfg).await.unwrap(), "from env");
+
+    let file = std::env::temp_dir().join(format!("cqlls_pswd_{}", std::process::id()));
+    std::fs::write(&file, "from file\n").unwrap();
+    let cfg = parse_config(&format!("db {{\npswd_file: \"{}\"\n}}\n", file.display())).unwrap();
+    assert_eq!(password(&cfg).await.unwrap(), "from file");
+    std::fs::remove_file(&file).unwrap();
+
+    let cfg =
+        parse_config("db {\npswd_command: \"printf 'from_command\\nsecond_line'\"\n}\n").unwrap();
+    assert_eq!(password(&cfg).await.unwrap(), "from_command");
+
+    /* quoted like in a shell */
+    let cfg = parse_config("db {\npswd_command: \"printf '%s' 'two words'\"\n}\n").unwrap();
+    assert_eq!(password(&cfg).await.unwrap(), "two words");
+
+    let cfg = parse_config("db {\npswd_command: \"printf 'unbalanced\"\n}\n").unwrap();
+    assert_eq!(
+        password(&cfg).await.unwrap_err(),
+        "can't parse `pswd_command` `printf 'unbalanced`"
+    );
+
+    let cfg = parse_config("db {\npswd_command: \"false\"\n}\n").unwrap();
+    assert_eq!(
+        password(&cfg).await.unwrap_err(),
+        "`pswd_command` `false` exited with exit status: 1"
+    );
+}
+
+#[test]
+fn test_password_never_shown() {
+    let cfg = parse_config("db {\npswd: \"hunter2\"\n}\n").unwrap();
+    assert!(!format!("{cfg:?}").contains("hunter2"));
+
+    for src in [
+        "db {\npswd: hunter2\n}\n",
+        "db {\npswd: \"hunter2\n}\n",
+        "db {\npswd \"hunter2\"\n}\n",
+    ] {
+        let error = parse_config(src).unwrap_err();
+        assert!(!error.msg.contains("hunter2"), "{}", error.msg);
+    }
+
+    let error = apply_settings(
+        CqllsConfig::default(),
+        &serde_json::json!({ "db": { "pswd": "hunter\n2" } }),
+    )
+    .unwrap_err();
+    assert!(!error.contains("hunter"), "{error}");
+
+    /* the sources round trip, `config show` masks only `pswd` */
+    let cfg = parse_config("db {\npswd_env: \"CQL_PASSWORD\"\n}\n").unwrap();
+    let mut written = vec![];
+    cfg.write_config(&mut written).unwrap();
+    let reparsed = parse_config(&String::from_utf8(written).unwrap()).unwrap();
+    assert_eq!(reparsed.pswd_env, "CQL_PASSWORD");
+    assert_eq!(reparsed.pswd_command, "");
+
+    /* quotes and backslashes are escaped, a comment sign inside of a value stays */
+    let cfg = CqllsConfig {
+        pswd: r#"ab"c#d\"#.to_string(),
+        ca_cert: r"C:\certs\ca.pem".to_string(),
+        ..Default::default()
+    };
+    let mut written = vec![];
+    cfg.write_config(&mut written).unwrap();
+    let reparsed = parse_config(&String::from_utf8(written).unwrap()).unwrap();
+    assert_eq!(reparsed.pswd, cfg.pswd);
+    assert_eq!(reparsed.ca_cert, cfg.ca_cert);
+    assert_eq!(
+        parse_config("db {\n    ca_cert: \"C:\\certs\\ca.pem\"\n}\n")
+            .unwrap()
+            .ca_cert,
+        r"C:\certs\ca.pem"
+    );
+}
+
+const PROFILES: &str = r#"
+default_profile: "staging"
+
+db {
+    user: "app"
+    pswd: "local secret"
+}
+
+db "staging" {
+    known_nodes {
+        "10.1.0.1:9042"
+    }
+}
+
+db "prod" {
+    user: "readonly"
+    pswd: "prod secret"
+    known_nodes {
+        "10.2.0.1:9042"
+    }
+}
+"#;
+
+#[test]
+fn test_parse_profiles() {
+    let cfg = parse_config(PROFILES).unwrap();
+
+    /* a profile starts from the plain `db` block */
+    assert_eq!(cfg.profile.as_deref(), Some("staging"));
+    assert_eq!(cfg.default_profile.as_deref(), Some("staging"));
+    assert_eq!(cfg.known_nodes, vec!["10.1.0.1:9042"]);
+    assert_eq!(cfg.user, "app");
+    assert_eq!(cfg.profiles["prod"].user, "readonly");
+    assert!(!format!("{cfg:?}").contains("secret"));
+
+    let mut prod = cfg.clone();
+    assert!(prod.use_profile("prod"));
+    assert_eq!(prod.known_nodes, vec!["10.2.0.1:9042"]);
+    assert_eq!(prod.pswd, "prod secret");
+    assert!(!prod.use_profile("dev"));
+    assert_eq!(prod.profile.as_deref(), Some("prod"));
+
+    let mut written = vec![];
+    cfg.write_config(&mut written).unwrap();
+    let reparsed = parse_config(&String::from_utf8(written).unwrap()).unwrap();
+    assert_eq!(reparsed.profile.as_deref(), Some("staging"));
+    assert_eq!(reparsed.profiles["prod"].known_nodes, vec!["10.2.0.1:9042"]);
+    assert_eq!(reparsed.known_nodes, vec!["10.1.0.1:9042"]);
+
+    let error = parse_config("default_profile: \"dev\"\ndb \"prod\" {\n}\n").unwrap_err();
+    assert_eq!(
+        (error.line, error.msg.as_str()),
+        (1, "unknown profile `dev`")
+    );
+    let error = parse_config("db \"prod\" {\n}\ndb \"prod\" {\n}\n").unwrap_err();
+    assert_eq!(
+        (error.line, error.msg.as_str()),
+        (3, "profile `prod` is defined twice")
+    );
+    let error = parse_config("db \"prod\" {\nusr: \"x\"\n}\n").unwrap_err();
+    assert_eq!(
+        (error.line, error.msg.as_str()),
+        (2, "unknown db key `usr`")
+    );
+
+    /* the editor can pick the profile too */
+    let picked = apply_settings(cfg, &serde_json::json!({ "default_profile": "prod" })).unwrap();
+    assert_eq!(picked.user, "readonly");
+}
+
+#[test]
+fn test_workspace_switch_profile() {
+    use cqlls::workspace::Workspace;
+
+    let dir = std::env::temp_dir().join(format!("cqlls_profiles_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(".cqlls"), PROFILES).unwrap();
+
+    let mut workspace = Workspace::default();
+    workspace.add_folder(dir.clone());
+    assert_eq!(workspace.active_profile().as_deref(), Some("staging"));
+    assert_eq!(
+        workspace.profile_names().into_iter().collect::<Vec<_>>(),
+        vec!["prod", "staging"]
+    );
+
+    /* the switch outlives a reload */
+    workspace.profile = Some("prod".to_string());
+    let switched = workspace.reloaded().reloaded();
+    assert_eq!(switched.active_profile().as_deref(), Some("prod"));
+    assert_eq!(switched.folders[0].config.user, "readonly");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_lsp_switch_profile() {
+    let path = std::env::temp_dir().join(format!("cqlls_lsp_profiles_{}", std::process::id()));
+    std::fs::write(&path, PROFILES).unwrap();
+
+    let mut server = Server(
+        std::process::Command::new(env!("CARGO_BIN_EXE_cqlls"))
+            .args(["--config", path.to_str().unwrap()])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    let mut stdin = server.stdin.take().unwrap();
+    let mut stdout = std::io::BufReader::new(server.stdout.take().unwrap());
+
+    let response = lsp_initialize_with(&mut stdin, &mut stdout);
+    assert_eq!(
+        response["result"]["capabilities"]["executeCommandProvider"]["commands"][0],
+        "cqlls.switchProfile"
+    );
+    lsp_write(
+        &mut stdin,
+        r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+    );
+
+    let mut next = |key: &str, value: serde_json::Value| loop {
+        let message = lsp_read(&mut stdout);
+        if message[key] == value {
+            break message;
+        }
+    };
+
+    let status = next("method", "cqlls/profile".into());
+    assert_eq!(status["params"]["profile"], "staging");
+    assert_eq!(
+        status["params"]["profiles"],
+        serde_json::json!(["prod", "staging"])
+    );
+
+    let switch = |id: u32, profile: &str| {
+        serde_json::json!({
+            "jsonrpc": "2.0", "id": id, "method": "workspace/executeCommand",
+            "params": { "command": "cqlls.switchProfile", "arguments": [profile] }
+        })
+        .to_string()
+    };
+
+    /* the response and the notification can come in either order */
+    lsp_write(&mut stdin, &switch(2, "prod"));
+    let (mut notified, mut response) = (None, None);
+    while notified.is_none() || response.is_none() {
+        let message = next("jsonrpc", "2.0".into());
+        if message["method"] == "cqlls/profile" {
+            notified = Some(message["params"]["profile"].clone());
+        } else if message["id"] == 2 {
+            response = Some(message["result"]["profile"].clone());
+        }
+    }
+    assert_eq!(notified.unwrap(), "prod");
+    assert_eq!(response.unwrap(), "prod");
+
+    lsp_write(&mut stdin, &switch(3, "dev"));
+    assert_eq!(
+        next("id", 3.into())["error"]["message"],
+        "unknown profile `dev`"
+    );
+
+    drop(server);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_cqlshrc() {
+    use cqlls::cqlshrc::Cqlshrc;
+    use cqlls::workspace::read_config;
+
+    /* the one the test cluster is set up with */
+    let shipped = Cqlshrc::read(std::path::Path::new("scylla/cqlshrc")).unwrap();
+    let mut cfg = CqllsConfig::default();
+    shipped.apply(&mut cfg);
+    assert_eq!(cfg.known_nodes, vec!["172.17.0.2:9042"]);
+    assert_eq!(cfg.user, "cassandra");
+    assert_eq!(cfg.ca_cert, "/etc/scylla/certs/ca.crt");
+    assert!(matches!(cfg.tls, TlsMode::None));
+
+    let mtls = Cqlshrc::parse(
+        "; written by cqlsh\n[connection]\nport: 9142\nssl = true\n\n\
+         [auth_provider]\nmodule = cassandra.auth\nusername = app\npassword = secret\n\n\
+         [ssl]\ncertfile = /certs/ca.pem\nusercert = /certs/client.pem\nuserkey = /certs/client.key\n",
+    );
+    let mut cfg = CqllsConfig::default();
+    mtls.apply(&mut cfg);
+    assert_eq!(cfg.known_nodes, vec!["127.0.0.1:9142"]);
+    assert_eq!((cfg.user.as_str(), cfg.pswd.as_str()), ("app", "secret"));
+    assert_eq!(cfg.client_cert, "/certs/client.pem");
+    assert_eq!(cfg.client_key, "/certs/client.key");
+    assert!(matches!(cfg.tls, TlsMode::MTls));
+
+    /* `.cqlls` wins over its cqlshrc, which is found next to it */
+    let dir = std::env::temp_dir().join(format!("cqlls_cqlshrc_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("cqlshrc"),
+        "[connection]\nhostname = 10.0.0.5\n[authentication]\nusername = rc_user\npassword = rc_pass\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join(".cqlls"),
+        "db {\n    user: \"mine\"\n    from_cqlshrc: \"cqlshrc\"\n}\n\n\
+         db \"other\" {\n    from_cqlshrc: \"missing\"\n}\n",
+    )
+    .unwrap();
+
+    let error = read_config(&dir.join(".cqlls")).unwrap_err();
+    assert!(error.contains(".cqlls:7: can't read"), "{error}");
+
+    std::fs::write(
+        dir.join(".cqlls"),
+        "db {\n    user: \"mine\"\n    from_cqlshrc: \"cqlshrc\"\n}\n",
+    )
+    .unwrap();
+    let cfg = read_config(&dir.join(".cqlls")).unwrap();
+    assert_eq!(cfg.user, "mine");
+    assert_eq!(cfg.pswd, "rc_pass");
+    assert_eq!(cfg.known_nodes, vec!["10.0.0.5:9042"]);
+
+    /* the path is written back as it was given */
+    let mut written = vec![];
+    cfg.write_config(&mut written).unwrap();
+    assert!(
+        String::from_utf8(written)
+            .unwrap()
+            .contains("from_cqlshrc: \"cqlshrc\"")
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_toml_config() {
+    use cqlls::config_toml::parse_toml_config;
+    use cqlls::workspace::discover_config;
+
+    let cfg = parse_toml_config(
+        r#"
+default_profile = "prod"
+
+[db]
+type = "scylla"
+known_nodes = ["127.0.0.1:9042", "127.0.0.2:9042"]
+pswd = "with # in it"
+
+[profiles.prod]
+user = "readonly"
+known_nodes = ["10.2.0.1:9042"]
+
+[fmt]
+indent = 2
+keyword_case = "upper"
+udf_formatters = { lua = "stylua -" }
+
+[lint]
+select-star = "error"
+"#,
+        None,
+    )
+    .unwrap();
+    assert_eq!(cfg.profile.as_deref(), Some("prod"));
+    assert_eq!(cfg.known_nodes, vec!["10.2.0.1:9042"]);
+    assert_eq!(cfg.user, "readonly");
+    assert_eq!(cfg.pswd, "with # in it");
+    assert_eq!(cfg.indent, 2);
+    assert_eq!(cfg.udf_formatters["lua"], "stylua -");
+    assert_eq!(cfg.lint_level("select-star"), LintLevel::Error);
+
+    /* errors point at the key */
+    let error = |src: &str| parse_toml_config(src, None).unwrap_err();
+    let e = error("[db]\ntype = \"scylla\"\n\n[fmt]\nindent = 300\n");
+    assert_eq!(
+        (e.line, e.msg.as_str()),
+        (5, "`indent` must be u8, got `300`")
+    );
+    let e = error("[lint]\nselect-star = \"error\"\nno-such-rule = \"off\"\n");
+    assert_eq!(
+        (e.line, e.msg.as_str()),
+        (3, "unknown lint rule `no-such-rule`")
+    );
+    let e = error("\n[fmt]\nudf_formatters = { lua = 1, sql = [1] }\n");
+    assert_eq!(e.line, 3);
+    let e = error("fmt = 2\n");
+    assert_eq!((e.line, e.msg.as_str()), (1, "`fmt` must be a table"));
+    let e = error("[nope]\nkey = 1\n");
+    assert_eq!((e.line, e.msg.as_str()), (1, "unknown block `nope`"));
+    assert_eq!(error("[db]\n[fmt\n").line, 2);
+    let cfg = parse_toml_config("[db]\nuser = 'a\"b'\n", None).unwrap();
+    assert_eq!(cfg.user, "a\"b");
+
+    /* strings are taken as TOML wrote them, only `${VAR}` is resolved */
+    let cfg = parse_toml_config("[db]\npswd = \"ab\\\"c#d\"\n", None).unwrap();
+    assert_eq!(cfg.pswd, "ab\"c#d");
+    let cfg = parse_toml_config("[db]\nuser = \"$${HOME}\"\n", None).unwrap();
+    assert_eq!(cfg.user, "${HOME}");
+    let cfg = parse_toml_config("[db]\nuser = \"${CQLLS_TOML_USER}\"\n", None);
+    assert!(cfg.is_err());
+    let e = error("[db]\npswd = 1234\n");
+    assert_eq!(
+        (e.line, e.msg.as_str()),
+        (2, "`pswd` must be a quoted string")
+    );
+    let e = error("[db]\nusr = \"a\"\n");
+    assert_eq!((e.line, e.msg.as_str()), (2, "unknown db key `usr`"));
+
+    /* a `.toml` file is read as TOML, and found before `.cqlls` */
+    let dir = std::env::temp_dir().join(format!("cqlls_toml_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(".cqlls"), "fmt {\nindent: 8\n}\n").unwrap();
+    assert_eq!(discover_config(&dir, None), Some(dir.join(".cqlls")));
+
+    std::fs::write(dir.join(".cqlls.toml"), "[fmt]\nindent = 3\n").unwrap();
+    let path = discover_config(&dir, None).unwrap();
+    assert_eq!(path, dir.join(".cqlls.toml"));
+    let cfg = parse_config_file(&std::fs::read_to_string(&path).unwrap(), &path).unwrap();
+    assert_eq!(cfg.indent, 3);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_config_migrate() {
+    use cqlls::config_toml::{migrate, parse_toml_config};
+
+    let written = |cfg: &CqllsConfig| {
+        let mut out = vec![];
+        cfg.write_config(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    let src = format!(
+        "{PROFILES}\nfmt {{\n    keyword_case: upper // a comment\n    udf_formatters {{\n        lua: \"stylua -\"\n    }}\n}}\n\nlint {{\n    select-star: error\n}}\n"
+    );
+    let toml = migrate(&src).unwrap();
+    assert!(toml.starts_with("default_profile = \"staging\"\n"));
+    assert!(toml.contains("[profiles.prod]"));
+    assert!(!toml.contains("a comment"));
+
+    /* the TOML says exactly what the `.cqlls` did */
+    let legacy = parse_config(&src).unwrap();
+    let migrated = parse_toml_config(&toml, None).unwrap();
+    assert_eq!(written(&migrated), written(&legacy));
+
+    /* nothing gets resolved into the new file */
+    let toml = migrate("db {\n    pswd: \"${CQLLS_MIGRATE_UNSET}\"\n}\n").unwrap();
+    assert_eq!(toml, "[db]\npswd = \"${CQLLS_MIGRATE_UNSET}\"\n");
+
+    /* the defaults survive the trip */
+    let toml = migrate(&written(&CqllsConfig::default())).unwrap();
+    assert_eq!(
+        written(&parse_toml_config(&toml, None).unwrap()),
+        written(&CqllsConfig::default())
+    );
+}
+
+#[test]
+fn test_config_completions() {
+    use cqlls::lsp::Backend;
+
+    let labels = |text: &str, line: u32, character: u32, toml: bool| -> Vec<String> {
+        Backend::config_completions(text, Position::new(line, character), toml)
+            .into_iter()
+            .map(|item| item.label)
+            .collect()
+    };
+
+    /* outside of every block */
+    let top = labels("\n", 0, 0, false);
+    for block in ["db", "fmt", "features", "lint", "debug", "default_profile"] {
+        assert!(top.contains(&block.to_string()), "{block} in {top:?}");
+    }
+
+    let text = "db {\n    \n    tls: \n    type: \"\n}\n\ndb \"prod\" {\n}\n\ndefault_profile: \n";
+    let keys = labels(text, 1, 4, false);
+    assert!(keys.contains(&"ca_cert".to_string()));
+    assert!(keys.contains(&"known_nodes".to_string()));
+    assert!(!keys.contains(&"indent".to_string()));
+
+    let items = Backend::config_completions(text, Position::new(2, 9), false);
+    let values: Vec<_> = items.iter().map(|i| i.label.as_str()).collect();
+    assert_eq!(values, vec!["none", "tls", "mtls"]);
+    assert_eq!(items[1].insert_text.as_deref(), Some("\"tls\""));
+
+    /* the quote is there already */
+    let items = Backend::config_completions(text, Position::new(3, 11), false);
+    assert_eq!(items[1].label, "scylla");
+    assert_eq!(items[1].insert_text.as_deref(), Some("scylla"));
+
+    assert_eq!(labels(text, 9, 17, false), vec!["prod"]);
+    assert_eq!(
+        labels("lint {\n    select-star: \n}\n", 1, 17, false),
+        vec!["off", "hint", "warning", "error"]
+    );
+    assert_eq!(
+        labels("features {\n    diagnostics: \n}\n", 1, 17, false),
+        vec!["true", "false"]
+    );
+    /* nothing to offer for the nodes themselves */
+    assert!(labels("db {\n    known_nodes {\n        \n    }\n}\n", 2, 8, false).is_empty());
+
+    /* `.cqlls.toml` */
+    let text = "[fmt]\nkeyword_case = \n\n[profiles.prod]\n\n[\n";
+    assert_eq!(
+        labels(text, 1, 15, true),
+        vec!["upper", "lower", "preserve"]
+    );
+    assert!(labels(text, 4, 0, true).contains(&"pswd_env".to_string()));
+    let items = Backend::config_completions(text, Position::new(5, 1), true);
+    assert_eq!(items[0].label, "db");
+    assert_eq!(items[0].insert_text.as_deref(), Some("db]\n$0"));
+}
+
+#[test]
+fn test_config_hover() {
+    use cqlls::lsp::Backend;
+    use tower_lsp::lsp_types::HoverContents;
+
+    let hover = |text: &str, line: u32, character: u32, toml: bool| {
+        Backend::config_hover(text, Position::new(line, character), toml).map(|hover| {
+            let HoverContents::Markup(markup) = hover.contents else {
+                panic!("expected markdown");
+            };
+            markup.value
+        })
+    };
+
+    let text = "db \"prod\" {\n    tls: \"mtls\"\n}\n\nfmt {\n    keyword_case: upper\n}\n";
+    assert!(hover(text, 0, 1, false).unwrap().starts_with("`db`"));
+    let tls = hover(text, 1, 5, false).unwrap();
+    assert!(tls.starts_with("`db.tls`: none | tls | mtls"), "{tls}");
+    /* a value shows its key */
+    assert_eq!(hover(text, 1, 11, false), Some(tls));
+    assert!(
+        hover(text, 5, 6, false)
+            .unwrap()
+            .contains("Casing of keywords")
+    );
+    assert_eq!(hover(text, 0, 6, false), None);
+
+    let range = Backend::config_hover(text, Position::new(1, 5), false)
+        .unwrap()
+        .range;
+    assert_eq!(
+        range,
+        Some(Range::new(Position::new(1, 4), Position::new(1, 7)))
+    );
+
+    let text = "[profiles.prod]\nuser = \"app\"\n\n[lint]\nselect-star = \"error\"\n";
+    assert!(hover(text, 0, 3, true).unwrap().starts_with("`profiles`"));
+    assert!(hover(text, 1, 1, true).unwrap().starts_with("`db.user`"));
+    assert!(hover(text, 4, 3, true).unwrap().contains("`SELECT *`"));
+}
+
+#[test]
+fn test_config_missing_files() {
+    use cqlls::lsp::Backend;
+    use tower_lsp::lsp_types::Url;
+
+    let dir = std::env::temp_dir().join(format!("cqlls_missing_files_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let ca = dir.join("ca.pem");
+    std::fs::write(&ca, "").unwrap();
+    let missing = dir.join("client.pem");
+
+    let uri = Url::from_file_path(dir.join(".cqlls")).unwrap();
+    let text = format!(
+        "db {{\n    tls: \"mtls\"\n    ca_cert: \"{}\"\n    client_cert: \"{}\"\n}}\n",
+        ca.display(),
+        missing.display()
+    );
+    let diagnostics = Backend::config_diagnostics(&uri, &text);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].range.start, Position::new(3, 0));
+    assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::WARNING));
+    assert_eq!(
+        diagnostics[0].message,
+        format!("`client_cert` `{}` doesn't exist", missing.display())
+    );
+
+    let uri = Url::from_file_path(dir.join(".cqlls.toml")).unwrap();
+    let text = format!("[db]\nca_cert = \"{}\"\n", missing.display());
+    let diagnostics = Backend::config_diagnostics(&uri, &text);
+    assert_eq!(diagnostics[0].range.start, Position::new(1, 0));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_connection_status() {
+    use cqlls::connection::{ConnectError, ConnectionState, ConnectionStatus};
+    use scylla::errors::{ConnectionError, ConnectionPoolError, MetadataError, NewSessionError};
+    use std::sync::Arc;
+
+    let status = ConnectionStatus {
+        state: ConnectionState::AuthFailed,
+        error: Some("bad credentials".into()),
+        ..Default::default()
+    };
+    assert_eq!(
+        serde_json::to_value(&status).unwrap(),
+        serde_json::json!({
+            "state": "auth-failed", "error": "bad credentials", "clusterName": null, "version": null
+        })
+    );
+
+    let e = ConnectError::from(NewSessionError::EmptyKnownNodesList);
+    assert_eq!(e.state, ConnectionState::Unreachable);
+    assert_eq!(
+        format!("{}: {e}", e.state),
+        format!("unreachable: {}", NewSessionError::EmptyKnownNodesList)
+    );
+
+    /* the state comes from the last error of the control connection */
+    let broken = |last_connection_error| {
+        ConnectError::from(NewSessionError::MetadataError(
+            MetadataError::ConnectionPoolError(ConnectionPoolError::Broken {
+                last_connection_error,
+            }),
+        ))
+    };
+    let tls = std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        rustls::Error::InvalidCertificate(rustls::CertificateError::UnknownIssuer),
+    );
+    let e = broken(ConnectionError::IoError(Arc::new(tls)));
+    assert_eq!(e.state, ConnectionState::TlsFailed);
+    let e = broken(ConnectionError::ConnectTimeout);
+    assert_eq!(e.state, ConnectionState::Unreachable);
+    let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+    let e = broken(ConnectionError::IoError(Arc::new(refused)));
+    assert_eq!(e.state, ConnectionState::Unreachable);
+}
+
+#[tokio::test]
+async fn test_connection_status_per_db() {
+    use cqlls::connection::{ConnectError, ConnectionState, record, status};
+
+    let config = |user: &str| CqllsConfig {
+        user: user.into(),
+        known_nodes: vec!["127.0.0.1:1".into()],
+        ..Default::default()
+    };
+    let (first, second) = (config("status_first"), config("status_second"));
+
+    let failed = ConnectError::new(ConnectionState::AuthFailed, "bad credentials");
+    assert!(record(&first, Err(failed)).await.is_err());
+    assert_eq!(status(&first).await.state, ConnectionState::AuthFailed);
+    assert_eq!(status(&second).await.state, ConnectionState::Connecting);
+
+    let failed = ConnectError::new(ConnectionState::Unreachable, "timed out");
+    assert!(record(&second, Err(failed)).await.is_err());
+    assert_eq!(status(&first).await.state, ConnectionState::AuthFailed);
+    assert_eq!(status(&second).await.state, ConnectionState::Unreachable);
+}
+
+#[tokio::test]
+async fn test_connection_steps() {
+    use cqlls::connection::{resolve, tcp_connect};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    assert_eq!(resolve(&address.to_string()).await.unwrap(), vec![address]);
+    assert!(resolve("no port").await.is_err());
+
+    assert_eq!(tcp_connect(address).await, Ok(()));
+    drop(listener);
+    assert!(
+        tcp_connect(address)
+            .await
+            .unwrap_err()
+            .starts_with(&address.to_string())
+    );
+}
+
+#[test]
+fn test_doctor() {
+    /* a port nobody listens on */
+    let address = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let path = std::env::temp_dir().join(format!("cqlls_doctor_{}", std::process::id()));
+    std::fs::write(
+        &path,
+        format!("db {{\n    known_nodes {{\n        \"{address}\"\n    }}\n}}\n"),
+    )
+    .unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_cqlls"))
+        .args(["--config", path.to_str().unwrap(), "doctor"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success());
+    assert!(
+        stdout.contains(&format!("ok      dns     {address} → {address}")),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(&format!("fail    tcp     {address}: ")),
+        "{stdout}"
+    );
+    assert!(!stdout.contains("auth"), "{stdout}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no node is reachable"));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_lsp_connection_status() {
+    let address = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let dir = std::env::temp_dir().join(format!("cqlls_lsp_status_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(".cqlls");
+    std::fs::write(
+        &path,
+        format!(
+            "db {{\n    known_nodes {{\n        \"{address}\"\n    }}\n}}\nfeatures {{\n    context_aware_completions: true\n    diagnostics: true\n}}\n"
+        ),
+    )
+    .unwrap();
+
+    let mut server = Server(
+        std::process::Command::new(env!("CARGO_BIN_EXE_cqlls"))
+            .args(["--config", path.to_str().unwrap()])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    let mut stdin = server.stdin.take().unwrap();
+    let mut stdout = std::io::BufReader::new(server.stdout.take().unwrap());
+
+    lsp_initialize_with(&mut stdin, &mut stdout);
+    lsp_write(
+        &mut stdin,
+        r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+    );
+
+    let mut next = |method: &str| loop {
+        let message = lsp_read(&mut stdout);
+        if message["method"] == method {
+            break message["params"].clone();
+        }
+    };
+    assert_eq!(next("cqlls/status")["state"], "connecting");
+
+    /* an unqualified table makes the diagnostics look for it in the database */
+    let uri = format!("file://{}", dir.join("a.cql").display());
+    lsp_write(
+        &mut stdin,
+        &serde_json::json!({
+            "jsonrpc": "2.0", "method": "textDocument/didOpen",
+            "params": { "textDocument": {
+                "uri": uri, "languageId": "cql", "version": 1, "text": "SELECT * FROM users;\n"
+            } }
+        })
+        .to_string(),
+    );
+
+    let message = next("window/showMessage");
+    assert_eq!(message["type"], 1);
+    assert!(
+        message["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Database unreachable: "),
+        "{message}"
+    );
+    let status = next("cqlls/status");
+    assert_eq!(status["state"], "unreachable");
+    assert!(status["error"].is_string());
+
+    drop(server);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_schema_cache() {
+    use cqlls::cqlsh::ColumnKind;
+    use cqlls::lsp::Backend;
+    use cqlls::schema;
+    use tower_lsp::LspService;
+
+    /* a database of its own, nothing else in the process reads it */
+    let mut cfg = CqllsConfig::with_knodes(vec!["schema-cache.invalid:9042".to_string()]);
+    cfg.features.push("diagnostics".to_string());
+    let column = |name: &str, kind: &str| ColumnKind {
+        column_name: name.to_string(),
+        kind: kind.to_string(),
+        position: 0,
+    };
+
+    let (service, _) = LspService::new(|client| Backend::new(client, cfg.clone()));
+    let backend = service.inner();
+    let text = "SELECT id, nmae FROM ks.users;\n";
+
+    /* nothing cached, the schema lints wait for the database */
+    let (diagnostics, complete) = backend.cached_diagnostics(text).await;
+    assert!(!complete);
+    assert!(
+        diagnostics
+            .iter()
+            .all(|d| d.code != Some(NumberOrString::String("unknown-column".into())))
+    );
+
+    schema::insert_columns(
+        &cfg,
+        "ks",
+        "users",
+        vec![column("id", "partition_key"), column("name", "regular")],
+    )
+    .await;
+    let (diagnostics, complete) = backend.cached_diagnostics(text).await;
+    assert!(complete);
+    let unknown: Vec<_> = diagnostics
+        .iter()
+        .filter(|d| d.code == Some(NumberOrString::String("unknown-column".into())))
+        .collect();
+    assert_eq!(unknown.len(), 1);
+    assert_eq!(unknown[0].message, "Unknown column `nmae` in `ks.users`");
+
+    /* another database doesn't see it */
+    let other = CqllsConfig::with_knodes(vec!["schema-cache-other.invalid:9042".to_string()]);
+    assert!(
+        schema::cached_columns(&other, "ks", "users")
+            .await
+            .is_none()
+    );
+    assert_eq!(
+        schema::cached_columns(&cfg, "KS", "Users")
+            .await
+            .unwrap()
+            .len(),
+        2
+    );
 }